@@ -1,21 +1,44 @@
-use std::ops::Index;
-use std::rc::Rc;
-use std::sync::{Arc, Mutex};
-use crate::builtin::engine::annotator::{AnnotationsSink, Annotator, AnnotatorContext};
-use crate::builtin::engine::entities::{EntitiesManager, FoshEntity};
+use crate::builtin::engine::annotator::{AnnotationsSink, Annotator};
+use crate::builtin::engine::contributors::ContributionContext;
+use crate::builtin::engine::entities::{Entity, EntityRef, FoshEntity};
 use crate::builtin::engine::parse_tree::PTNode;
 use crate::builtin::engine::{Type, Value};
 use crate::entities;
-use crate::parser::ast::{ASTKind, downcast_to_typed, Identifier, NumberLiteral, Parameter, ParenthesizedArgumentsList, PropertyCall, PropertyName, StringLiteral, Typed};
+use crate::parser::ast::{ASTKind, downcast_to_typed, Parameter, PropertyName, Typed};
 
 pub fn downcast_to_annotator<'a>(node: &'a PTNode<'a>) -> Option<&'a dyn Annotator> {
     match node.kind {
         ASTKind::Parameter => Some(node.value::<Parameter>()),
         ASTKind::PropertyName => Some(node.value::<PropertyName>()),
+        ASTKind::CommandName => Some(&BuiltinCommandAnnotator),
         _ => None
     }
 }
 
+// Command names are completed with builtins of the global entity on top of PATH executables.
+// A bare command name always runs a process, so builtins are completed as calls: `c` gives `$cd(`
+pub struct BuiltinCommandAnnotator;
+
+impl Annotator for BuiltinCommandAnnotator {
+    fn annotate<'a>(&self, node: &'a PTNode<'a>, sink: &mut AnnotationsSink) {
+        entities().path_annotator.annotate(node, sink);
+
+        let global = entities().global();
+        let global = global.borrow();
+        for (name, property) in global.properties().iter() {
+            if !name.starts_with(node.data) { continue; }
+            let text = if property.borrow().callee().is_some() {
+                format!("${}(", name)
+            } else {
+                format!("${}", name)
+            };
+            if !sink.completions().iter().any(|c| c.text == text) {
+                sink.add_described_completion(text, property.name());
+            }
+        }
+    }
+}
+
 impl Annotator for PropertyName {
     fn annotate<'a>(&self, node: &'a PTNode<'a>, sink: &mut AnnotationsSink) {
         let parent = node.parent().unwrap();
@@ -32,9 +55,40 @@ impl Annotator for PropertyName {
 
         let text = node.data;
 
-        for x in properties.keys() {
+        for (x, property) in properties.iter() {
             if x.starts_with(text) {
-                sink.completions.push(x.to_string());
+                sink.add_described_completion(x, property.name());
+            }
+        }
+        let mut known = properties.keys().any(|x| x.starts_with(text));
+        for x in left.prototype_property_names() {
+            if x.starts_with(text) && !properties.contains_key(&x) {
+                known = true;
+                let getter = left.prototype_property(&x).unwrap();
+                sink.add_described_completion(x, getter.name());
+            }
+        }
+        if parent.children().len() == 1 {
+            for x in entities().variable_names() {
+                if x.starts_with(text) && !properties.contains_key(&x) {
+                    known = true;
+                    match entities().get_variable(&x) {
+                        Some(variable) => sink.add_described_completion(x, variable.name()),
+                        None => sink.add_completion(x),
+                    }
+                }
+            }
+        }
+
+        // a prefix of existing property may be still being typed
+        if !known && !text.is_empty() {
+            sink.add_error(Some(format!("{} has no property `{}`", left.name(), text)));
+            let mut names: Vec<&String> = properties.keys().collect();
+            names.sort();
+            if names.is_empty() {
+                sink.add_hint(format!("{} has no properties", left.name()));
+            } else {
+                sink.add_hint(format!("Available properties: {}", names.iter().map(|x| x.as_str()).collect::<Vec<_>>().join(", ")));
             }
         }
     }
@@ -42,38 +96,211 @@ impl Annotator for PropertyName {
 
 impl Annotator for Parameter {
     fn annotate<'a>(&self, node: &'a PTNode<'a>, sink: &mut AnnotationsSink) {
-        let idx = node.position();
+        // first child of arguments list is opening paren
+        let idx = node.position() - 1;
+        let me = self.infer_value(node).unwrap();
 
-        // Property call
-        let parent = node.parent().unwrap().parent().unwrap();
-        let left = parent.children()[0];
-        let left = downcast_to_typed(left).unwrap().infer_value(left);
-        if left.is_none() { return; }
-        let left = left.unwrap();
-        let left = left.borrow();
-        let callee = left.callee().as_ref();
-        if callee.is_none() { return; }
-        let callee = callee.unwrap();
+        annotate_argument(node.parent().unwrap(), idx, Some(me), sink);
+    }
+}
 
-        if idx >= callee.arguments.len() { return; }
-        let arg = &callee.arguments[idx];
+// Completions of an argument which isn't typed yet, e.g. in `$cd( ` with cursor after the space.
+// It goes after every parameter which ends before the cursor
+pub fn annotate_absent_argument<'a>(list: &'a PTNode<'a>, pos: usize, sink: &mut AnnotationsSink) {
+    let idx = list.children().iter()
+        .filter(|x| x.kind == ASTKind::Parameter && x.origin.span.end() <= pos)
+        .count();
 
-        let me = self.infer_value(node).unwrap();
-        let me_ref = me.borrow();
+    annotate_argument(list, idx, None, sink);
+}
 
-        let value = if me_ref.implicits().contains_key(&Type::Number) {
-            me_ref.implicits()[&Type::Number](me.clone())
-        } else if me_ref.implicits().contains_key(&Type::String){
-            me_ref.implicits()[&Type::String](me.clone())
-        } else {
-            me.clone().into()
-        };
+fn annotate_argument<'a>(list: &'a PTNode<'a>, idx: usize, me: Option<EntityRef>, sink: &mut AnnotationsSink) {
+    // Property call
+    let parent = list.parent().unwrap();
+    let left = parent.children()[0];
+    let left = downcast_to_typed(left).unwrap().infer_value(left);
+    if left.is_none() { return; }
+    let left = left.unwrap();
+    let left = left.borrow();
+    let callee = left.callee().as_ref();
+    if callee.is_none() { return; }
+    let callee = callee.unwrap();
+
+    let arg = match callee.argument_at(idx) {
+        Some(arg) => arg,
+        None => {
+            sink.add_error(Some("too many arguments"));
+            sink.add_hint(format!("{} takes at most {} arguments", left.name(), callee.arguments.len()));
+            return;
+        }
+    };
+    let expected = arg.expected_types();
+    sink.add_hint(format!("{}: {}", arg.name, expected));
+
+    // nothing typed yet is completed like an empty string
+    let value = match me {
+        Some(me) => {
+            let me_ref = me.borrow();
+
+            if !arg.accepts(&me) {
+                sink.add_error(Some(format!("expected {}, got {}", expected, type_name(&me_ref))));
+            }
+
+            if me_ref.implicits().contains_key(&Type::Number) {
+                me_ref.implicits()[&Type::Number](me.clone())
+            } else if me_ref.implicits().contains_key(&Type::String){
+                me_ref.implicits()[&Type::String](me.clone())
+            } else {
+                me.clone().into()
+            }
+        }
+        None => Value::String(String::new()),
+    };
+
+    let previous = list.children().iter()
+        .filter(|x| x.kind == ASTKind::Parameter)
+        .take(idx)
+        .map(|x| x.value::<Parameter>().infer_value(x))
+        .collect();
+    let context = ContributionContext { position: idx, previous, ..ContributionContext::new() };
 
-        arg.contributor.contribute(value)
-            .iter()
-            .for_each(|a| sink.completions.push(a.to_string()));
+    // values which can't be written back are not offered, accepting them would give another value
+    arg.contributor.contribute(value, &context)
+        .iter()
+        .filter_map(|a| a.to_literal())
+        .for_each(|a| sink.add_completion(a));
+}
+
+// user facing name of the most specific type value converts to
+fn type_name(value: &Entity) -> &'static str {
+    [Type::Number, Type::Bool, Type::List, Type::String].iter()
+        .find(|t| value.implicits().contains_key(t))
+        .map_or(Type::Entity.display_name(), |t| t.display_name())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::cell::Cell;
+    use crate::builtin::engine::parse_tree::parse_line;
+    use crate::builtin::path_annotator::tests::wait_ready;
+    use crate::session::Session;
+    use crate::ui::settings::ColorType;
+    use super::*;
+
+    thread_local! {
+        static INIT: Cell<bool> = const { Cell::new(false) };
+    }
+
+    // entities() is per thread, so every test gets its own universe
+    pub fn init_entities() {
+        if !INIT.with(|i| i.replace(true)) {
+            Session::new();
+        }
+    }
+
+    // runs annotators on every node under the cursor. Cursor is marked with ^
+    pub fn annotate_with_default(line: &str) -> AnnotationsSink {
+        init_entities();
+
+        let cursor = line.find('^').expect("No cursor marker");
+        let line = line.replacen('^', "", 1);
+        let tree = parse_line(&line).unwrap();
+
+        let mut nodes = Vec::new();
+        tree.collect(&mut nodes, |a| a.origin.span.start() <= cursor && a.origin.span.end() >= cursor);
+
+        let mut sink = AnnotationsSink::new();
+        for node in nodes {
+            if let Some(annotator) = downcast_to_annotator(node) {
+                annotator.annotate(node, &mut sink);
+            }
+        }
+
+        sink
+    }
 
+    #[test]
+    fn test_absent_command_name_color() {
+        init_entities();
+        wait_ready(&entities().path_annotator);
+
+        assert_eq!(annotate_with_default("sh^ -c true").colors(), &vec![ColorType::CommandName]);
+        assert_eq!(annotate_with_default("fosh_no_such_command^").colors(), &vec![ColorType::AbsentCommandName]);
+        // prefixes of existing commands and global properties are not flagged
+        assert!(!annotate_with_default("s^").colors().contains(&ColorType::AbsentCommandName));
+        assert!(!annotate_with_default("jobs^").colors().contains(&ColorType::AbsentCommandName));
+    }
+
+    #[test]
+    fn test_builtin_command_names() {
+        init_entities();
+        wait_ready(&entities().path_annotator);
+
+        let sink = annotate_with_default("c^");
+        let names: Vec<&str> = sink.completions().iter().map(|c| c.text.as_str()).collect();
+        assert!(names.contains(&"$cd("), "{:?}", names);
+        assert!(names.contains(&"cat"), "{:?}", names);
+        assert!(names.iter().all(|n| n.starts_with('c') || n.starts_with("$c")), "{:?}", names);
+
+        let mut deduped = names.clone();
+        deduped.sort();
+        deduped.dedup();
+        assert_eq!(deduped.len(), names.len());
+    }
+
+    #[test]
+    fn test_unknown_property() {
+        let sink = annotate_with_default("$fosh_nonexistent^");
+        assert!(sink.colors().contains(&ColorType::Error));
+        assert!(sink.hints().contains(&"Error: Global has no property `fosh_nonexistent`".to_string()), "{:?}", sink.hints());
+        assert!(sink.hints().iter().any(|h| h.starts_with("Available properties: ") && h.contains("echo")), "{:?}", sink.hints());
+
+        let sink = annotate_with_default(r#"$"a".size^"#);
+        assert_eq!(sink.hints(), &vec!["Error: a has no property `size`".to_string(), "a has no properties".to_string()]);
+        let sink = annotate_with_default("$[1 2].size^");
+        assert!(sink.hints().contains(&"Available properties: at, length".to_string()), "{:?}", sink.hints());
+        let sink = annotate_with_default("$[1 2].lengt^");
+        assert!(sink.colors().is_empty(), "{:?}", sink.hints());
+        // getters of prototypes are properties too
+        let sink = annotate_with_default("$[1 2].ke^");
+        assert!(sink.colors().is_empty(), "{:?}", sink.hints());
+        assert_eq!(sink.completions()[0].text, "keys");
+
+        // prefixes are not flagged
+        let sink = annotate_with_default("$ech^");
+        assert!(sink.colors().is_empty(), "{:?}", sink.hints());
+    }
+
+    #[test]
+    fn test_argument_type_mismatch() {
+        let sink = annotate_with_default("$cd(5^)");
+        assert!(sink.colors().contains(&ColorType::Error));
+        assert!(sink.hints().contains(&"Error: expected string, got number".to_string()), "{:?}", sink.hints());
+
+        let sink = annotate_with_default(r#"$cd("/tm^")"#);
+        assert!(!sink.colors().contains(&ColorType::Error), "{:?}", sink.hints());
+    }
+
+    #[test]
+    fn test_too_many_arguments() {
+        let sink = annotate_with_default(r#"$cd("/" "/tm^")"#);
+        assert!(sink.colors().contains(&ColorType::Error));
+        assert!(sink.hints().contains(&"Error: too many arguments".to_string()), "{:?}", sink.hints());
+    }
+
+    #[test]
+    fn test_parameter_index() {
+        // the opening paren is the first child of the arguments list, it's not counted as an argument
+        let sink = annotate_with_default(r#"$alias("a^" "b")"#);
+        assert_eq!(sink.hints(), &vec!["name: string".to_string()]);
+        let sink = annotate_with_default(r#"$alias("a" "b^")"#);
+        assert_eq!(sink.hints(), &vec!["expansion: string".to_string()]);
+    }
 
+    #[test]
+    fn test_variadic_parameter_hint() {
+        let sink = annotate_with_default(r#"$echo("a" "b" "c^")"#);
 
+        assert!(sink.hints().contains(&"words: string".to_string()), "{:?}", sink.hints());
     }
 }
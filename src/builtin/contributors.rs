@@ -1,70 +1,376 @@
-use crate::builtin::engine::contributors::Contributor;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use crate::builtin::engine::contributors::{ContributionContext, Contributor};
 use crate::builtin::engine::Value;
+use crate::builtin::paths::expand_tilde;
 
-pub struct FilesContributor {}
+const LISTING_TTL: Duration = Duration::from_secs(2);
+
+pub struct FilesContributor {
+    cache: ListingCache,
+}
+
+impl Default for FilesContributor {
+    fn default() -> Self {
+        FilesContributor::new()
+    }
+}
 
 impl FilesContributor {
     pub fn new() -> Self {
-        Self {}
+        Self { cache: ListingCache::new(Box::new(Instant::now)) }
     }
 }
 
 impl Contributor for FilesContributor {
-    fn contribute(&self, value: Value) -> Vec<Value> {
-        let s = match value {
-            Value::String(s) => s,
-            Value::Number(v) => v.to_string(),
-            Value::Entity(_) => {return vec![]},
-        };
+    fn contribute(&self, value: Value, context: &ContributionContext) -> Vec<Value> {
+        complete_path(value, context, &self.cache, false)
+    }
+}
 
-        let mut result = Vec::new();
+pub struct DirectoriesContributor {
+    cache: ListingCache,
+}
 
-        let (dir, suffix, postfix) = match s.rfind('/') {
-            Some(i) => (&s[..i+1], &s[..i+1], &s[i + 1..]),
-            None => (".", "", &s[..]),
-        };
+impl Default for DirectoriesContributor {
+    fn default() -> Self {
+        DirectoriesContributor::new()
+    }
+}
 
+impl DirectoriesContributor {
+    pub fn new() -> Self {
+        Self { cache: ListingCache::new(Box::new(Instant::now)) }
+    }
+}
 
-        let path = std::fs::read_dir(dir);
-        if path.is_err() { return result; }
+impl Contributor for DirectoriesContributor {
+    fn contribute(&self, value: Value, context: &ContributionContext) -> Vec<Value> {
+        complete_path(value, context, &self.cache, true)
+    }
+}
 
-        let path = path.unwrap();
+pub struct EmptyContributor {}
 
-        for entry in path {
-            if entry.is_err() { continue; }
-            let entry = entry.unwrap();
-            let name = entry.file_name();
-            let name = name.to_str();
-            if name.is_none() { continue; }
-            let name = name.unwrap();
+impl Default for EmptyContributor {
+    fn default() -> Self {
+        EmptyContributor::new()
+    }
+}
 
-            if name.starts_with(&postfix) {
-                result.push(Value::String(format!("{}{}", suffix, name)));
+impl EmptyContributor {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Contributor for EmptyContributor {
+    fn contribute(&self, _: Value, _: &ContributionContext) -> Vec<Value> {
+        vec![]
+    }
+}
+
+// Directory listings reused between keystrokes. A listing is read again once it's older than LISTING_TTL
+// or the directory was modified since. Keys are absolute paths, so after `cd` a directory with the same
+// relative name is never served another one's listing. Expired listings are dropped whenever a new one
+// is stored, so only directories completed within the last LISTING_TTL are kept
+struct ListingCache {
+    listings: RefCell<HashMap<PathBuf, Listing>>,
+    // tests control time with it instead of sleeping
+    clock: Box<dyn Fn() -> Instant>,
+    // how many times a directory was actually read
+    #[cfg(test)]
+    scans: std::cell::Cell<usize>,
+}
+
+struct Listing {
+    // names with whether they are directories, in read_dir order
+    entries: Vec<(String, bool)>,
+    modified: Option<SystemTime>,
+    scanned_at: Instant,
+}
+
+impl ListingCache {
+    fn new(clock: Box<dyn Fn() -> Instant>) -> Self {
+        Self {
+            listings: RefCell::new(HashMap::new()),
+            clock,
+            #[cfg(test)]
+            scans: std::cell::Cell::new(0),
+        }
+    }
+
+    fn entries(&self, dir: &Path) -> Option<Vec<(String, bool)>> {
+        let now = (self.clock)();
+        let modified = std::fs::metadata(dir).and_then(|m| m.modified()).ok();
+        if let Some(listing) = self.listings.borrow().get(dir) {
+            if now.duration_since(listing.scanned_at) < LISTING_TTL && modified.is_some() && listing.modified == modified {
+                return Some(listing.entries.clone());
             }
         }
 
-        result
+        #[cfg(test)]
+        self.scans.set(self.scans.get() + 1);
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(dir).ok()?.flatten() {
+            let name = match entry.file_name().to_str() {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let is_dir = entry.metadata().map(|m| m.is_dir()).unwrap_or(false);
+            entries.push((name, is_dir));
+        }
+
+        let mut listings = self.listings.borrow_mut();
+        listings.retain(|_, l| now.duration_since(l.scanned_at) < LISTING_TTL);
+        listings.insert(dir.to_path_buf(), Listing {
+            entries: entries.clone(),
+            modified,
+            scanned_at: now,
+        });
+        Some(entries)
+    }
+}
+
+fn complete_path(value: Value, context: &ContributionContext, cache: &ListingCache, dirs_only: bool) -> Vec<Value> {
+    let s = match value {
+        Value::String(s) => s,
+        Value::Number(v) => v.to_string(),
+        Value::Bool(_) | Value::List(_) | Value::Entity(_) => {return vec![]},
+    };
+
+    let mut result = Vec::new();
+
+    // `src/bui` lists `src/` and keeps `src/` in front of every name, so completions extend what was typed.
+    // Without a slash the name is looked up in the current directory and nothing is glued
+    let (prefix, partial) = match s.rfind('/') {
+        Some(i) => s.split_at(i + 1),
+        None => ("", &s[..]),
+    };
+    let dir = if prefix.is_empty() { "." } else { prefix };
+
+    // completions keep `~` as typed, only the listed directory is expanded
+    let entries = match cache.entries(&context.cwd.join(expand_tilde(dir).as_ref())) {
+        Some(entries) => entries,
+        None => return result,
+    };
+
+    for (name, is_dir) in entries {
+        if !name.starts_with(partial) { continue; }
+        // like in other shells, hidden entries are offered only once a dot is typed
+        if name.starts_with('.') && !partial.starts_with('.') { continue; }
+
+        if dirs_only {
+            if is_dir {
+                result.push(Value::String(format!("{}{}/", prefix, name)));
+            }
+        } else {
+            result.push(Value::String(format!("{}{}", prefix, name)));
+        }
     }
+
+    result
 }
 
 
 
 #[cfg(test)]
 mod tests {
+    use crate::builtin::paths::tests::TempDir;
     use crate::builtin::annotator::tests::annotate_with_default;
+    use crate::runtime::execution::tests::execute_with_input;
     use super::*;
 
     #[test]
     fn test_file_contributor() {
-        let annotations = annotate_with_default("$ cd(\"^\")");
+        let completions: Vec<String> = FilesContributor::new()
+            .contribute(Value::String("".to_string()), &ContributionContext::new())
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
 
         let files: Vec<String> = std::fs::read_dir(".").unwrap()
-            .into_iter()
             .map(|x| x.unwrap().file_name().to_str().unwrap().to_string())
-            .map(|x| format!("\"{}/{}\"", ".", x))
+            .filter(|x| !x.starts_with('.'))
+            .map(|x| format!("\"{}\"", x))
             .collect();
 
-        assert_eq!(annotations.completions(), &files);
+        assert_eq!(&completions, &files);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_file_contributor_tilde() {
+        let home = std::env::var("HOME").unwrap();
+
+        let completions: Vec<String> = FilesContributor::new()
+            .contribute(Value::String("~/".to_string()), &ContributionContext::new())
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+
+        let files: Vec<String> = std::fs::read_dir(home).unwrap()
+            .map(|x| x.unwrap().file_name().to_str().unwrap().to_string())
+            .filter(|x| !x.starts_with('.'))
+            .map(|x| format!("\"~/{}\"", x))
+            .collect();
+
+        assert_eq!(completions, files);
+    }
+
+    fn complete(text: &str, context: &ContributionContext) -> Vec<String> {
+        let mut completions: Vec<String> = FilesContributor::new()
+            .contribute(Value::String(text.to_string()), context)
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        completions.sort();
+        completions
+    }
+
+    #[test]
+    fn test_nested_paths() {
+        let root = TempDir::new("contributor_nested");
+        std::fs::create_dir_all(root.join("one/two")).unwrap();
+        std::fs::write(root.join("one/first"), "").unwrap();
+        std::fs::write(root.join("one/two/second"), "").unwrap();
+        std::fs::write(root.join("one/two/other"), "").unwrap();
+        let context = ContributionContext { cwd: root.to_path_buf(), ..ContributionContext::new() };
+
+        let one_level = complete("one/", &context);
+        let partial = complete("one/t", &context);
+        let two_levels = complete("one/two/s", &context);
+
+        assert_eq!(one_level, vec!["\"one/first\"", "\"one/two\""]);
+        assert_eq!(partial, vec!["\"one/two\""]);
+        assert_eq!(two_levels, vec!["\"one/two/second\""]);
+
+        let context = ContributionContext { cwd: env!("CARGO_MANIFEST_DIR").into(), ..ContributionContext::new() };
+        assert_eq!(complete("src/bui", &context), vec!["\"src/builtin\""]);
+    }
+
+    #[test]
+    fn test_hidden_entries() {
+        let root = TempDir::new("contributor_hidden");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join(".gitignore"), "").unwrap();
+        std::fs::write(root.join("visible"), "").unwrap();
+        let context = ContributionContext { cwd: root.to_path_buf(), ..ContributionContext::new() };
+
+        let plain = complete("", &context);
+        let dotted = complete(".g", &context);
+
+        assert_eq!(plain, vec!["\"visible\""]);
+        assert_eq!(dotted, vec!["\".git\"", "\".gitignore\""]);
+    }
+
+    #[test]
+    fn test_completions_with_special_characters() {
+        let root = TempDir::new("contributor_quoting");
+        std::fs::write(root.join("my file"), "").unwrap();
+        std::fs::write(root.join("my \"quoted\" file"), "").unwrap();
+        std::fs::write(root.join("my \"quoted\" 'file'"), "").unwrap();
+        let context = ContributionContext { cwd: root.to_path_buf(), ..ContributionContext::new() };
+
+        let mut literals: Vec<String> = FilesContributor::new()
+            .contribute(Value::String("my".to_string()), &context)
+            .iter()
+            .filter_map(|x| x.to_literal())
+            .collect();
+        literals.sort();
+
+        // a name with both quotes can't be written as a literal, so it's not offered
+        assert_eq!(literals, vec!["\"my file\"", "'my \"quoted\" file'"]);
+        // accepted completion is a single argument holding the whole name
+        for (literal, name) in literals.iter().zip(["my file", "my \"quoted\" file"]) {
+            let (_, output) = execute_with_input(&format!("$echo({})", literal), "");
+            assert_eq!(output, format!("{}\n", name));
+        }
+    }
+
+    #[test]
+    fn test_listing_cache() {
+        let root = TempDir::new("contributor_cache");
+        std::fs::create_dir_all(root.join("first/sub")).unwrap();
+        std::fs::create_dir_all(root.join("second/sub")).unwrap();
+        std::fs::write(root.join("first/sub/a"), "").unwrap();
+        std::fs::write(root.join("second/sub/b"), "").unwrap();
+
+        let now = std::rc::Rc::new(std::cell::Cell::new(Instant::now()));
+        let clock = now.clone();
+        let contributor = FilesContributor { cache: ListingCache::new(Box::new(move || clock.get())) };
+        let first = ContributionContext { cwd: root.join("first"), ..ContributionContext::new() };
+        let complete = |context: &ContributionContext| -> Vec<String> {
+            let mut completions: Vec<String> = contributor.contribute(Value::String("sub/".to_string()), context)
+                .iter().map(|x| x.to_string()).collect();
+            completions.sort();
+            completions
+        };
+        let scans = || contributor.cache.scans.get();
+
+        assert_eq!(complete(&first), vec!["\"sub/a\""]);
+        assert_eq!(complete(&first), vec!["\"sub/a\""]);
+        assert_eq!(scans(), 1);
+
+        // same relative path after `cd` is another directory
+        let second = ContributionContext { cwd: root.join("second"), ..ContributionContext::new() };
+        assert_eq!(complete(&second), vec!["\"sub/b\""]);
+        assert_eq!(scans(), 2);
+
+        // modified directory is read again. Its mtime is set explicitly, so the test doesn't depend on
+        // the file system's timestamp granularity
+        std::fs::write(root.join("first/sub/c"), "").unwrap();
+        let modified = std::fs::metadata(root.join("first/sub")).unwrap().modified().unwrap();
+        std::fs::File::open(root.join("first/sub")).unwrap().set_modified(modified + Duration::from_secs(1)).unwrap();
+        assert_eq!(complete(&first), vec!["\"sub/a\"", "\"sub/c\""]);
+        assert_eq!(scans(), 3);
+
+        // expired listing is read again, and the other expired one is dropped meanwhile
+        now.set(now.get() + LISTING_TTL);
+        assert_eq!(complete(&first), vec!["\"sub/a\"", "\"sub/c\""]);
+        assert_eq!(scans(), 4);
+        assert_eq!(contributor.cache.listings.borrow().keys().collect::<Vec<_>>(), vec![&root.join("first/sub")]);
+
+    }
+
+    #[test]
+    fn test_contributor_uses_context_cwd() {
+        let dir = TempDir::new("contributor_cwd");
+        std::fs::create_dir_all(dir.join("fosh_sub")).unwrap();
+        std::fs::write(dir.join("fosh_file"), "").unwrap();
+
+        let context = ContributionContext { cwd: dir.to_path_buf(), ..ContributionContext::new() };
+        let mut files: Vec<String> = FilesContributor::new()
+            .contribute(Value::String("fosh_".to_string()), &context)
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        files.sort();
+        let dirs: Vec<String> = DirectoriesContributor::new()
+            .contribute(Value::String("".to_string()), &context)
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+
+        assert_eq!(files, vec!["\"fosh_file\"", "\"fosh_sub\""]);
+        assert_eq!(dirs, vec!["\"fosh_sub/\""]);
+    }
+
+    #[test]
+    fn test_directories_contributor() {
+        let annotations = annotate_with_default("$ cd(\"^\")");
+
+        let dirs: Vec<String> = std::fs::read_dir(".").unwrap()
+            .map(|x| x.unwrap())
+            .filter(|x| x.metadata().unwrap().is_dir())
+            .map(|x| x.file_name().to_str().unwrap().to_string())
+            .filter(|x| !x.starts_with('.'))
+            .map(|x| format!("\"{}/\"", x))
+            .collect();
+
+        let completions: Vec<String> = annotations.completions().iter().map(|c| c.text.clone()).collect();
+        assert_eq!(completions, dirs);
+    }
+
+}
@@ -1,4 +1,4 @@
-use std::fmt::Display;
+use std::fmt::{Display, Formatter};
 use crate::builtin::engine::parse_tree::PTNode;
 use crate::ui::settings::ColorType;
 
@@ -6,12 +6,47 @@ pub trait Annotator {
     fn annotate<'a>(&self, node: &'a PTNode<'a>, context: &mut AnnotationsSink);
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub text: String,
+    pub description: Option<String>,
+}
+
+impl Completion {
+    pub fn new<S: Into<String>>(text: S) -> Self {
+        Self {
+            text: text.into(),
+            description: None,
+        }
+    }
+
+    pub fn with_description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+}
+
+impl Display for Completion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.description {
+            Some(description) => write!(f, "{} — {}", self.text, description),
+            None => write!(f, "{}", self.text),
+        }
+    }
+}
+
 pub struct AnnotationsSink {
-    pub completions: Vec<String>,
+    pub completions: Vec<Completion>,
     pub colors: Vec<ColorType>,
     pub hints: Vec<String>,
 }
 
+impl Default for AnnotationsSink {
+    fn default() -> Self {
+        AnnotationsSink::new()
+    }
+}
+
 impl AnnotationsSink {
     pub fn new() -> Self {
         Self {
@@ -21,8 +56,12 @@ impl AnnotationsSink {
         }
     }
 
-    pub fn add_completion<S: Into<String>>(&mut self, completion: S) {
-        self.completions.push(completion.into());
+    pub fn add_completion<S: Into<String>>(&mut self, text: S) {
+        self.completions.push(Completion::new(text));
+    }
+
+    pub fn add_described_completion<S: Into<String>, D: Into<String>>(&mut self, text: S, description: D) {
+        self.completions.push(Completion::new(text).with_description(description));
     }
 
     pub fn add_error<S : Display>(&mut self, error: Option<S>) {
@@ -41,8 +80,16 @@ impl AnnotationsSink {
         self.hints.push(hint.into());
     }
 
+    // Sorts completions alphabetically with directories (trailing `/`) first and removes duplicates,
+    // so the order doesn't depend on the filesystem or on which annotators fired
+    pub fn sort_completions(&mut self) {
+        self.completions.sort_by(|a, b| {
+            is_directory(&b.text).cmp(&is_directory(&a.text)).then_with(|| a.text.cmp(&b.text))
+        });
+        self.completions.dedup_by(|a, b| a.text == b.text);
+    }
 
-    pub fn completions(&self) -> &Vec<String> {
+    pub fn completions(&self) -> &Vec<Completion> {
         &self.completions
     }
     pub fn colors(&self) -> &Vec<ColorType> {
@@ -53,6 +100,10 @@ impl AnnotationsSink {
     }
 }
 
+// path completions are quoted string values, so the slash may be followed by a quote
+fn is_directory(completion: &str) -> bool {
+    completion.trim_end_matches('"').ends_with('/')
+}
 
 pub struct AnnotatorContext<'a> {
     pub sink: &'a mut AnnotationsSink
@@ -67,3 +118,20 @@ impl <'a>AnnotatorContext<'a> {
         self.sink
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sort_completions() {
+        let mut sink = AnnotationsSink::new();
+        for x in ["\"b\"", "\"src/\"", "\"a\"", "\"b\"", "\"lib/\"", "echo"] {
+            sink.add_completion(x);
+        }
+        sink.sort_completions();
+
+        let texts: Vec<&str> = sink.completions().iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["\"lib/\"", "\"src/\"", "\"a\"", "\"b\"", "echo"]);
+    }
+}
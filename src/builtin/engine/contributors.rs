@@ -1,5 +1,34 @@
+use std::path::PathBuf;
+use crate::builtin::engine::entities::EntityRef;
 use crate::builtin::engine::Value;
 
+// What is known about the argument being completed besides its value
+pub struct ContributionContext {
+    // relative paths are relative to it
+    pub cwd: PathBuf,
+    // index of the argument among the call's arguments
+    pub position: usize,
+    // arguments before it, None where the value can't be inferred without execution
+    pub previous: Vec<Option<EntityRef>>,
+}
+
+impl Default for ContributionContext {
+    fn default() -> Self {
+        ContributionContext::new()
+    }
+}
+
+impl ContributionContext {
+    // first argument in the shell's current directory
+    pub fn new() -> Self {
+        Self {
+            cwd: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            position: 0,
+            previous: vec![],
+        }
+    }
+}
+
 pub trait Contributor {
-    fn contribute(&self, value: Value) -> Vec<Value>;
-}
\ No newline at end of file
+    fn contribute(&self, value: Value, context: &ContributionContext) -> Vec<Value>;
+}
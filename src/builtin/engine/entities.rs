@@ -1,25 +1,26 @@
-use std::borrow::Cow;
-use std::cell::{Ref, RefCell, RefMut};
+use std::cell::{Ref, RefCell};
 use std::collections::HashMap;
-use std::fmt::{Display, Formatter, write};
+use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
 use std::future::Future;
-use std::io::{Error, ErrorKind, Read, stderr, stdin, stdout, Write};
+use std::io::{Error, Read, stderr, stdin, stdout, Write};
 use std::os::unix::io::{AsFd, OwnedFd};
-use std::os::unix::prelude::{FromRawFd, RawFd};
+use std::os::unix::process::ExitStatusExt;
 use std::pin::Pin;
-use std::process::{Child, Command, Stdio};
+use std::process::{Child, ExitStatus};
 use std::rc::Rc;
-use std::task::{Context, Poll};
-use nix::libc::{stat};
-use nix::unistd::dup;
-use parse_display_derive::Display;
-use pipe::{PipeReader, PipeWriter};
-use fosh::error_printer::ErrorType;
-use crate::builtin::contributors::FilesContributor;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use nix::libc::{waitpid, WNOHANG};
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::unistd::{getpgid, getpgrp, Pid};
+use crate::error_printer::ErrorType;
+use crate::builtin::contributors::{DirectoriesContributor, EmptyContributor, FilesContributor};
+use crate::builtin::path_annotator::PathAnnotator;
 use crate::builtin::engine::{Argument, Type, Value};
-use crate::builtin::engine::parse_tree::{PTNode, PTNodeId};
+use crate::builtin::engine::parse_tree::PTNodeId;
 use crate::entities;
+use crate::runtime::terminal::ForegroundGuard;
 
 pub type EntityRef = Rc<RefCell<Entity>>;
 pub type FoshResult<A> = Result<A, EntityExecutionError>;
@@ -34,9 +35,44 @@ pub struct ExecutionConfig {
     pub std_out: Option<OwnedFd>,
     pub std_err: Option<OwnedFd>,
     pub pt: PTNodeId,
+    // processes read /dev/null instead of the shell's stdin when std_in is not set, so a command can't
+    // hang waiting for the terminal in background or steal the lines of a script piped into the shell
+    pub null_stdin: bool,
+    // processes still running at this moment are killed, see ProcessExecution::execute
+    pub deadline: Option<Instant>,
+    // gets output of captured commands like `{ make }` while they are still running, e.g. to show progress
+    pub output_hook: Option<OutputHook>,
+    // process group to spawn processes in, like the one of the first stage for the rest of a pipeline.
+    // None makes every process lead a group of its own
+    pub pgid: Option<Pid>,
+}
+
+pub type OutputHookFn = dyn Fn(&[u8]) + Send + Sync;
+
+#[derive(Clone)]
+pub struct OutputHook(pub Arc<OutputHookFn>);
+
+impl Debug for OutputHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OutputHook")
+    }
 }
 
 impl ExecutionConfig {
+    // None means the shell's own stream. pt is set by whoever executes it, like Session::eval
+    pub fn new(std_in: Option<OwnedFd>, std_out: Option<OwnedFd>, std_err: Option<OwnedFd>) -> Self {
+        Self {
+            std_in,
+            std_out,
+            std_err,
+            pt: PTNodeId::default(),
+            null_stdin: false,
+            deadline: None,
+            output_hook: None,
+            pgid: None,
+        }
+    }
+
     pub fn try_clone(&self) -> Result<ExecutionConfig, Error> {
         let std_in = match self.std_in.as_ref() {
             None => {None}
@@ -55,6 +91,10 @@ impl ExecutionConfig {
             std_out,
             std_err,
             pt: self.pt,
+            null_stdin: self.null_stdin,
+            deadline: self.deadline,
+            output_hook: self.output_hook.clone(),
+            pgid: self.pgid,
         })
     }
 }
@@ -62,17 +102,30 @@ impl ExecutionConfig {
 impl ExecutionConfig {
     pub fn new_with_dup(pt: PTNodeId, std_in: &OwnedFd, std_out: &OwnedFd, std_err: &OwnedFd) -> Result<ExecutionConfig, Error> {
         Ok(ExecutionConfig {
-            std_in: Some(std_in.try_clone()?),
-            std_out: Some(std_out.try_clone()?),
-            std_err: Some(std_err.try_clone()?),
             pt,
+            ..ExecutionConfig::new(Some(std_in.try_clone()?), Some(std_out.try_clone()?), Some(std_err.try_clone()?))
         })
     }
 }
 
+// Shell's own streams and no limits. Use it with struct update syntax, so new options don't touch every caller
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        ExecutionConfig::new(None, None, None)
+    }
+}
+
+// how long a process has to exit after SIGTERM before it is killed
+const TERMINATION_GRACE: Duration = Duration::from_secs(1);
+// how often a process is checked when it can't be waited on
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 pub struct ProcessExecution {
     child: Child,
     node_id: PTNodeId,
+    deadline: Option<Instant>,
+    // when try_execute sent SIGTERM after the deadline
+    terminated_at: Option<Instant>,
 }
 
 impl ProcessExecution {
@@ -80,8 +133,29 @@ impl ProcessExecution {
         Self {
             child,
             node_id,
+            deadline: None,
+            terminated_at: None,
         }
     }
+
+    pub fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    pub fn pid(&self) -> Pid {
+        Pid::from_raw(self.child.id() as i32)
+    }
+}
+
+// Process started with `&`, either the command itself or a copy of the shell running a pipeline or chain.
+// Stays in the table after it finished so its status can be shown
+pub struct Job {
+    pub id: usize,
+    pub command: String,
+    // also the process group of everything the job spawned
+    pub pid: Pid,
+    pub status: Option<ExitStatus>,
 }
 
 pub enum Execution {
@@ -92,10 +166,10 @@ pub enum Execution {
 
 impl Execution {
 
-    pub fn execute(mut self) -> FoshResult<EntityRef> {
+    pub fn execute(self) -> FoshResult<EntityRef> {
         match self {
             Execution::Pseudo(f) => f(),
-            Execution::Process(mut exec) => exec.execute()
+            Execution::Process(exec) => exec.execute()
 
         }
     }
@@ -114,36 +188,56 @@ impl Execution {
 
 pub struct Callee {
     pub arguments: Vec<Argument>,
-    pub callee: Box<dyn Fn(EntityRef, &[EntityRef], ExecutionConfig) -> Result<Execution, EntityExecutionError>>,
-    pub result_prototype: Option<Box<dyn Fn(EntityRef, &[Option<EntityRef>]) -> Option<EntityRef>>>,
+    // last argument may be repeated zero or more times
+    pub is_variadic: bool,
+    // number of trailing arguments which may be omitted
+    pub optional: usize,
+    // called with the entity on property access, like `.keys`
+    pub is_getter: bool,
+    pub callee: Box<CalleeFn>,
+    pub result_prototype: Option<Box<ResultPrototypeFn>>,
 }
 
+pub type CalleeFn = dyn Fn(EntityRef, &[EntityRef], ExecutionConfig) -> Result<Execution, EntityExecutionError>;
+// infers what a call returns from the entity and whatever arguments could be inferred, see Callee::result_prototype
+pub type ResultPrototypeFn = dyn Fn(EntityRef, &[Option<EntityRef>]) -> Option<EntityRef>;
+
 impl Callee {
     pub fn new<F>(block: F) -> Self
         where F: for<'b> Fn(EntityRef, &'b [EntityRef], ExecutionConfig) -> Result<Execution, EntityExecutionError> + 'static
     {
         Self {
             arguments: vec![],
+            is_variadic: false,
+            optional: 0,
+            is_getter: false,
             callee: Box::new(block),
             result_prototype: None,
         }
     }
 
     pub fn new_pseudo_execution<F>(block: F) -> Callee
-        where F: 'static + FnOnce(PTNodeId, &[EntityRef], &mut dyn Read, &mut dyn Write, &mut dyn Write)
+        where F: 'static + FnOnce(PTNodeId, &[EntityRef], &mut File, &mut File, &mut File)
             -> Result<EntityRef, EntityExecutionError> + Copy
     {
         Self {
             arguments: vec![],
+            is_variadic: false,
+            optional: 0,
+            is_getter: false,
             callee: Box::new(move |_me, args, mut config| {
-                let entities = args.iter().map(|a| a.clone()).collect::<Vec<_>>();
+                let entities = args.to_vec();
                 let execution = Execution::new_pseudo(move || {
                     let stdin = stdin();
                     let stdout = stdout();
                     let stderr = stderr();
                     let stderr = config.std_err.as_mut().map(|a| a.as_fd().try_clone_to_owned()).unwrap_or_else(|| stderr.as_fd().try_clone_to_owned());
                     let stdout = config.std_out.as_mut().map(|a| a.as_fd().try_clone_to_owned()).unwrap_or_else(|| stdout.as_fd().try_clone_to_owned());
-                    let stdin = config.std_in.as_mut().map(|a| a.as_fd().try_clone_to_owned()).unwrap_or_else(|| stdin.as_fd().try_clone_to_owned());
+                    let stdin = match config.std_in.as_mut() {
+                        Some(a) => a.as_fd().try_clone_to_owned(),
+                        None if config.null_stdin => File::open("/dev/null").map(OwnedFd::from),
+                        None => stdin.as_fd().try_clone_to_owned(),
+                    };
 
                     if stdin.is_err() {
                         return Err(EntityExecutionError::new_single(config.pt, ErrorType::CannotCloneFd, format!("{}", stdin.err().unwrap())));
@@ -177,6 +271,37 @@ impl Callee {
         self
     }
 
+    pub fn with_variadic(mut self) -> Self {
+        self.is_variadic = true;
+        self
+    }
+
+    pub fn with_optional(mut self, count: usize) -> Self {
+        self.optional = count;
+        self
+    }
+
+    pub fn with_getter(mut self) -> Self {
+        self.is_getter = true;
+        self
+    }
+
+    // Argument expected at given position, taking variadic tail into account
+    pub fn argument_at(&self, idx: usize) -> Option<&Argument> {
+        if idx < self.arguments.len() {
+            return self.arguments.get(idx);
+        }
+        if self.is_variadic { self.arguments.last() } else { None }
+    }
+
+    pub fn accepts_arity(&self, count: usize) -> bool {
+        if self.is_variadic {
+            count + 1 >= self.arguments.len()
+        } else {
+            count <= self.arguments.len() && count + self.optional >= self.arguments.len()
+        }
+    }
+
     pub fn with_result_prototype<F>(mut self, prototype: F) -> Self
         where F: Fn(EntityRef, &[Option<EntityRef>]) -> Option<EntityRef> + 'static
     {
@@ -187,15 +312,62 @@ impl Callee {
 
 impl ProcessExecution {
     pub fn execute(mut self) -> FoshResult<EntityRef> {
-        match self.child.wait() {
-            Ok(status) => {
+        let pid = Pid::from_raw(self.child.id() as i32);
+        let _foreground = ForegroundGuard::new(getpgid(Some(pid)).unwrap_or_else(|_| getpgrp()));
+
+        let status = match self.deadline {
+            Some(deadline) => self.wait_until(deadline),
+            None => self.child.wait().map(Some),
+        };
+
+        self.result_of(status)
+    }
+
+    // Doesn't block. None while the process is still running. Past the deadline the process gets SIGTERM
+    // and, if it's still there after TERMINATION_GRACE, SIGKILL, each on a later call than the previous step.
+    // Unlike execute, the process is not given the terminal. Nothing in the shell polls processes yet, see test_try_execute
+    pub fn try_execute(&mut self) -> Option<FoshResult<EntityRef>> {
+        let status = match self.child.try_wait() {
+            Ok(Some(_)) if self.terminated_at.is_some() => Ok(None),
+            Ok(None) => {
+                let now = Instant::now();
+                match self.terminated_at {
+                    Some(terminated_at) if now >= terminated_at + TERMINATION_GRACE => {
+                        if let Err(e) = self.signal(Signal::SIGKILL) {
+                            return Some(self.result_of(Err(e.into())));
+                        }
+                    }
+                    Some(_) => {}
+                    None if self.deadline.is_some_and(|deadline| now >= deadline) => {
+                        let _ = self.signal(Signal::SIGTERM);
+                        self.terminated_at = Some(now);
+                    }
+                    None => {}
+                }
+                return None;
+            }
+            status => status,
+        };
+
+        Some(self.result_of(status))
+    }
+
+    // None status means the process was killed on timeout
+    fn result_of(&self, status: std::io::Result<Option<ExitStatus>>) -> FoshResult<EntityRef> {
+        match status {
+            Ok(None) => {
+                Err(EntityExecutionError::new_single(self.node_id, ErrorType::Timeout, "Execution timed out")
+                    .with_status(Some(124)))
+            }
+            Ok(Some(status)) => {
                 if status.success() {
                     Ok(entities()
                         .make_entity("Execution result".to_string())
                         .with_property("status", Value::Number(status.code().unwrap_or(-1) as f64).into_entity())
                     )
                 } else {
-                    Err(EntityExecutionError::new_single(self.node_id, ErrorType::Execution, format!("Execution failed with status {}", status)))
+                    Err(EntityExecutionError::new_single(self.node_id, ErrorType::Execution, format!("Execution failed with status {}", status))
+                        .with_status(status.code()))
                 }
             }
             Err(e) => {
@@ -203,9 +375,44 @@ impl ProcessExecution {
             }
         }
     }
-}
 
+    // None if the process had to be killed
+    fn wait_until(&mut self, deadline: Instant) -> std::io::Result<Option<ExitStatus>> {
+        if let Some(status) = self.poll_until(deadline)? {
+            return Ok(Some(status));
+        }
+
+        let _ = self.signal(Signal::SIGTERM);
+        if self.poll_until(Instant::now() + TERMINATION_GRACE)?.is_none() {
+            self.signal(Signal::SIGKILL)?;
+            self.child.wait()?;
+        }
+
+        Ok(None)
+    }
+
+    // Signals the whole process group, so whatever the process started goes as well and can't keep
+    // its output open. A process that didn't get a group of its own is in the shell's one and is signalled alone
+    fn signal(&self, signal: Signal) -> nix::Result<()> {
+        let pid = self.pid();
+        match getpgid(Some(pid)) {
+            Ok(pgid) if pgid != getpgrp() => killpg(pgid, signal),
+            _ => kill(pid, signal),
+        }
+    }
 
+    fn poll_until(&mut self, deadline: Instant) -> std::io::Result<Option<ExitStatus>> {
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                return Ok(Some(status));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ErrorData {
@@ -237,15 +444,29 @@ impl ErrorData {
 #[derive(Debug, Clone)]
 pub struct EntityExecutionError {
     pub errors: HashMap<PTNodeId, ErrorData>,
+    // exit code of the failed process if any
+    pub status: Option<i32>,
+}
+
+impl Default for EntityExecutionError {
+    fn default() -> Self {
+        EntityExecutionError::new()
+    }
 }
 
 impl EntityExecutionError {
     pub fn new() -> Self {
         Self {
             errors: HashMap::new(),
+            status: None,
         }
     }
 
+    pub fn with_status(mut self, status: Option<i32>) -> Self {
+        self.status = status;
+        self
+    }
+
     pub fn new_single<S: Into<String>>(node_id: PTNodeId, kind: ErrorType, note: S) -> Self {
         let mut r = Self::new();
         r.with_error(node_id, kind).with_notes(vec![note.into()]);
@@ -254,7 +475,7 @@ impl EntityExecutionError {
 
     pub fn with_error(&mut self, node_id: PTNodeId, kind: ErrorType) -> &mut ErrorData {
         self.errors.insert(node_id, ErrorData::new(kind));
-        return self.errors.get_mut(&node_id).unwrap();
+        self.errors.get_mut(&node_id).unwrap()
     }
 }
 
@@ -299,6 +520,31 @@ pub struct Comms<'a> {
 }
 
 impl Entity {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    // Prototype chain from the nearest prototype. Stops at a cycle
+    pub fn prototypes(&self) -> Vec<EntityRef> {
+        let mut result: Vec<EntityRef> = Vec::new();
+        let mut prototype = self.prototype.clone();
+        while let Some(p) = prototype {
+            if result.iter().any(|x| Rc::ptr_eq(x, &p)) { break; }
+            prototype = p.borrow().prototype.clone();
+            result.push(p);
+        }
+
+        result
+    }
+    // Looks the property up in prototypes, nearest first
+    pub fn prototype_property(&self, name: &str) -> Option<EntityRef> {
+        self.prototypes().iter()
+            .find_map(|p| p.borrow().properties.get(name).cloned())
+    }
+    pub fn prototype_property_names(&self) -> Vec<String> {
+        self.prototypes().iter()
+            .flat_map(|p| p.borrow().properties.keys().cloned().collect::<Vec<_>>())
+            .collect()
+    }
     pub fn implicits(&self) -> &HashMap<Type, Box<dyn Fn(EntityRef) -> Value + 'static>> {
         &self.implicits
     }
@@ -322,14 +568,14 @@ impl FoshEntity for EntityRef {
         self.borrow_mut().callee = Some(Box::new(callee));
         self
     }
-    fn with_property(mut self, name: &str, property: EntityRef) -> Self {
+    fn with_property(self, name: &str, property: EntityRef) -> Self {
         self.borrow_mut().properties.insert(name.to_string(), property);
         self
     }
     fn add_property(&mut self, name: &str, property: EntityRef) {
         self.borrow_mut().properties.insert(name.to_string(), property);
     }
-    fn with_implicit<F, V>(mut self, type_: Type, implicit: F) -> Self
+    fn with_implicit<F, V>(self, type_: Type, implicit: F) -> Self
         where F: Fn(EntityRef) -> V, F: 'static, V: Into<Value>
     {
         self.borrow_mut().implicits.insert(type_, Box::new(move |e| implicit(e).into()));
@@ -346,7 +592,7 @@ impl FoshEntity for EntityRef {
                 panic!("Implicit string is not a string");
             }
         }
-        return None;
+        None
     }
 
     fn try_as_number(&self) -> Option<f64> {
@@ -359,7 +605,48 @@ impl FoshEntity for EntityRef {
                 panic!("Implicit number is not a number");
             }
         }
-        return None;
+        None
+    }
+
+    fn try_as_list(&self) -> Option<Vec<EntityRef>> {
+        let r = self.borrow();
+
+        if let Some(x) = r.implicits.get(&Type::List) {
+            if let Value::List(x) = x(self.clone()) {
+                return Some(x);
+            } else {
+                panic!("Implicit list is not a list");
+            }
+        }
+        None
+    }
+
+    fn try_as_bool(&self) -> Option<bool> {
+        let r = self.borrow();
+
+        if let Some(x) = r.implicits.get(&Type::Bool) {
+            if let Value::Bool(x) = x(self.clone()) {
+                return Some(x);
+            } else {
+                panic!("Implicit bool is not a bool");
+            }
+        }
+        None
+    }
+
+    fn is_truthy(&self) -> bool {
+        if let Some(b) = self.try_as_bool() {
+            return b;
+        }
+        if let Some(n) = self.try_as_number() {
+            return n.is_finite() && n != 0.0;
+        }
+        if let Some(s) = self.try_as_string() {
+            return !s.is_empty();
+        }
+
+        let status = self.borrow().properties.get("status").and_then(|s| s.try_as_number());
+        status.is_none_or(|s| s == 0.0)
     }
 }
 
@@ -373,18 +660,68 @@ pub trait FoshEntity {
 
     fn try_as_string(&self) -> Option<String>;
     fn try_as_number(&self) -> Option<f64>;
+    fn try_as_bool(&self) -> Option<bool>;
+    fn try_as_list(&self) -> Option<Vec<EntityRef>>;
+
+    // Decides conditions of `if` and `&&`/`||`, so all of them agree:
+    // - bool is its value
+    // - number is truthy unless it's zero, NaN or infinite
+    // - string is truthy unless it's empty
+    // - entity with `status` property, like a command result, is truthy if the status is zero
+    // - any other entity (list, builtin result) is truthy, it's a successful result
+    fn is_truthy(&self) -> bool;
 }
 
 pub struct EntitiesManager {
     pub files_contributor: FilesContributor,
+    pub directories_contributor: DirectoriesContributor,
+    pub empty_contributor: EmptyContributor,
+    pub path_annotator: PathAnnotator,
     any: EntityRef,
     global: EntityRef,
+    // prototype of entities asking the shell to quit
+    exit: EntityRef,
+    // user variables, shadow properties of global.
+    // Shell is single threaded and the manager lives forever, so RefCell is enough
+    variables: RefCell<HashMap<String, EntityRef>>,
+    jobs: RefCell<Vec<Job>>,
+    // command name -> text it expands to
+    aliases: RefCell<HashMap<String, String>>,
+}
+
+// `.keys` of any entity, names of its own properties in sorted order
+fn make_keys_getter() -> EntityRef {
+    let callee = Callee::new(|me, _args, _config| {
+        let mut keys: Vec<String> = me.borrow().properties.keys().cloned().collect();
+        keys.sort();
+        Ok(Execution::new_pseudo(move || {
+            let keys = keys.into_iter().map(|k| Value::String(k).into_entity()).collect::<Vec<_>>();
+            Ok(Value::List(keys).into_entity())
+        }))
+    }).with_getter();
+
+    Rc::new(RefCell::new(Entity {
+        name: "Keys getter".to_string(),
+        implicits: HashMap::new(),
+        callee: Some(Box::new(callee)),
+        properties: HashMap::new(),
+        prototype: None,
+    }))
+}
+
+impl Default for EntitiesManager {
+    fn default() -> Self {
+        EntitiesManager::new()
+    }
 }
 
 impl EntitiesManager {
     pub fn new() -> EntitiesManager {
-        EntitiesManager {
-            files_contributor: FilesContributor {},
+        let manager = EntitiesManager {
+            files_contributor: FilesContributor::new(),
+            directories_contributor: DirectoriesContributor::new(),
+            empty_contributor: EmptyContributor {},
+            path_annotator: PathAnnotator::new(),
             any: Rc::new(RefCell::new(Entity {
                 name: "Any".to_string(),
                 implicits: HashMap::new(),
@@ -399,7 +736,20 @@ impl EntitiesManager {
                 properties: HashMap::from([]),
                 prototype: None,
             })),
-        }
+            exit: Rc::new(RefCell::new(Entity {
+                name: "Exit".to_string(),
+                implicits: HashMap::new(),
+                callee: None,
+                properties: HashMap::new(),
+                prototype: None,
+            })),
+            variables: RefCell::new(HashMap::new()),
+            jobs: RefCell::new(Vec::new()),
+            aliases: RefCell::new(HashMap::new()),
+        };
+        manager.any.borrow_mut().properties.insert("keys".to_string(), make_keys_getter());
+
+        manager
     }
 
     pub fn make_entity(&self, name: String) -> EntityRef {
@@ -412,11 +762,144 @@ impl EntitiesManager {
         }))
     }
 
+    // Own property of the entity or the nearest one of its prototypes.
+    // Inherited methods are bound to the entity, so their callee gets it as `me`.
+    // Getters are returned as is, they need execution to get the value. See execute_property_insn
+    pub fn lookup_property(&self, entity: &EntityRef, name: &str) -> Option<EntityRef> {
+        let e = entity.borrow();
+        if let Some(property) = e.properties.get(name) {
+            return Some(property.clone());
+        }
+
+        let property = e.prototype_property(name)?;
+        let is_method = property.borrow().callee.as_ref().is_some_and(|c| !c.is_getter);
+        if is_method {
+            Some(self.bind(&property, entity))
+        } else {
+            Some(property)
+        }
+    }
+
+    fn bind(&self, method: &EntityRef, receiver: &EntityRef) -> EntityRef {
+        let m = method.borrow();
+        let callee = m.callee.as_ref().unwrap();
+
+        let target = method.clone();
+        let receiver = receiver.clone();
+        let mut bound = Callee::new(move |_me, args, config| {
+            let target = target.borrow();
+            (target.callee.as_ref().unwrap().callee)(receiver.clone(), args, config)
+        }).with_arguments(callee.arguments.clone());
+        bound.is_variadic = callee.is_variadic;
+        bound.optional = callee.optional;
+
+        self.make_entity(m.name.clone()).with_callee(bound)
+    }
+
     pub fn global(&self) -> EntityRef {
         self.global.clone()
     }
 
+    // Makes the entity available as `$name`. Embedding programs add their builtins this way,
+    // a builtin with the same name is replaced
+    pub fn register_global(&self, name: &str, entity: EntityRef) {
+        self.global.borrow_mut().properties.insert(name.to_string(), entity);
+    }
+
+    pub fn set_variable(&self, name: &str, value: EntityRef) {
+        self.variables.borrow_mut().insert(name.to_string(), value);
+    }
+
+    pub fn get_variable(&self, name: &str) -> Option<EntityRef> {
+        self.variables.borrow().get(name).cloned()
+    }
+
+    pub fn variable_names(&self) -> Vec<String> {
+        self.variables.borrow().keys().cloned().collect()
+    }
+
+    pub fn set_alias(&self, name: &str, expansion: &str) {
+        self.aliases.borrow_mut().insert(name.to_string(), expansion.to_string());
+    }
+
+    pub fn get_alias(&self, name: &str) -> Option<String> {
+        self.aliases.borrow().get(name).cloned()
+    }
+
+    // registers background process and returns its job id
+    pub fn add_job(&self, command: String, pid: Pid) -> usize {
+        let mut jobs = self.jobs.borrow_mut();
+        let id = jobs.last().map_or(1, |j| j.id + 1);
+        jobs.push(Job {
+            id,
+            command,
+            pid,
+            status: None,
+        });
+
+        id
+    }
+
+    pub fn jobs(&self) -> Ref<'_, Vec<Job>> {
+        self.jobs.borrow()
+    }
+
+    // remembers statuses of finished jobs, which also releases their zombies
+    pub fn reap_jobs(&self) {
+        for job in self.jobs.borrow_mut().iter_mut().filter(|j| j.status.is_none()) {
+            let mut status = 0;
+            if unsafe { waitpid(job.pid.as_raw(), &mut status, WNOHANG) } == job.pid.as_raw() {
+                job.status = Some(ExitStatus::from_raw(status));
+            }
+        }
+    }
+
+    // Entity which makes the shell quit with given status once it is the result of a command
+    pub fn make_exit_request(&self, name: String, status: i32) -> EntityRef {
+        Rc::new(RefCell::new(Entity {
+            name,
+            implicits: HashMap::new(),
+            callee: None,
+            properties: HashMap::from([("status".to_string(), Value::Number(status as f64).into_entity())]),
+            prototype: Some(self.exit.clone()),
+        }))
+    }
+
+    pub fn exit_status(&self, entity: &EntityRef) -> Option<i32> {
+        let entity = entity.borrow();
+        if !entity.prototype.as_ref().is_some_and(|p| Rc::ptr_eq(p, &self.exit)) {
+            return None;
+        }
+
+        Some(entity.properties.get("status")?.try_as_number()? as i32)
+    }
+
     pub fn any(&self) -> EntityRef {
         self.any.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::builtin::annotator::tests::init_entities;
+    use super::*;
+
+    #[test]
+    fn test_prototype_cycle() {
+        init_entities();
+        let manager = entities();
+        let first = manager.make_entity("first".to_string());
+        let second = manager.make_entity("second".to_string());
+        first.borrow_mut().prototype = Some(second.clone());
+        second.borrow_mut().prototype = Some(first.clone());
+        second.borrow_mut().properties.insert("kek".to_string(), Value::Number(1.0).into_entity());
+
+        assert_eq!(first.borrow().prototypes().len(), 2);
+        assert!(first.borrow().prototype_property("kek").is_some());
+        assert!(first.borrow().prototype_property("lol").is_none());
+        assert_eq!(first.borrow().prototype_property_names(), vec!["kek"]);
+
+        // break the cycle, otherwise the entities are never dropped
+        first.borrow_mut().prototype = None;
+    }
+}
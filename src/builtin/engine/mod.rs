@@ -3,27 +3,39 @@ pub mod contributors;
 pub mod entities;
 pub mod parse_tree;
 
-use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
-use std::num::ParseIntError;
-use std::ops::Deref;
-use std::rc::Rc;
 use crate::builtin::engine::contributors::Contributor;
-use crate::builtin::engine::entities::{Entity, FoshEntity, EntityExecutionError, EntityRef};
-use crate::{entities, EntitiesManager};
-use crate::parser::ast::{ASTKind, Boxed, Identifier, NumberLiteral, PropertyCall, StringLiteral};
-use crate::ui::settings::{ColorType, TUISettings};
+use crate::error_printer::ErrorType;
+use crate::builtin::engine::entities::{Callee, Execution, FoshEntity, EntityExecutionError, EntityRef};
+use crate::entities;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     String,
     Number,
+    Bool,
+    List,
     Entity
 }
 
+impl Type {
+    // user facing name for diagnostics
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Type::String => "string",
+            Type::Number => "number",
+            Type::Bool => "bool",
+            Type::List => "list",
+            Type::Entity => "entity",
+        }
+    }
+}
+
 pub enum Value {
     String(String),
     Number(f64),
+    Bool(bool),
+    List(Vec<EntityRef>),
     Entity(EntityRef)
 }
 
@@ -32,6 +44,15 @@ impl Display for Value {
         match self {
             Value::String(s) => write!(f, "\"{}\"", s),
             Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 { write!(f, " ")?; }
+                    write!(f, "{}", item.borrow())?;
+                }
+                write!(f, "]")
+            }
             Value::Entity(e) => write!(f, "{}", e.borrow()),
         }
     }
@@ -43,6 +64,8 @@ impl Value {
         match self {
             Value::String(_) => Type::String,
             Value::Number(_) => Type::Number,
+            Value::Bool(_) => Type::Bool,
+            Value::List(_) => Type::List,
             Value::Entity(_) => Type::Entity,
         }
     }
@@ -50,12 +73,50 @@ impl Value {
     pub fn into_entity(self) -> EntityRef {
         match self {
             Value::Entity(e) => e,
-            Value::String(s) => entities().make_entity(s.clone()).with_implicit(Type::String, move |e| s.clone()),
-            Value::Number(n) => entities().make_entity(format!("{}", n)).with_implicit(Type::Number, move |e| n),
+            Value::String(s) => entities().make_entity(s.clone()).with_implicit(Type::String, move |_e| s.clone()),
+            Value::Number(n) => entities().make_entity(format!("{}", n)).with_implicit(Type::Number, move |_e| n),
+            Value::Bool(b) => entities().make_entity(format!("{}", b)).with_implicit(Type::Bool, move |_e| b),
+            Value::List(items) => make_list(items),
         }
     }
 
+    // How the value is written in a command so it parses back into the same value.
+    // Double quoted strings have no escapes, so a string with `"` in it goes into raw single quotes.
+    // None for a string with both quotes, neither form can hold it
+    pub fn to_literal(&self) -> Option<String> {
+        match self {
+            Value::String(s) if s.contains('"') && s.contains('\'') => None,
+            Value::String(s) if s.contains('"') => Some(format!("'{}'", s)),
+            _ => Some(self.to_string()),
+        }
+    }
+}
 
+fn make_list(items: Vec<EntityRef>) -> EntityRef {
+    let length = items.len();
+    let elements = items.clone();
+    let at = entities().make_entity("List element call".to_string())
+        .with_callee(Callee::new(move |_me, args, config| {
+            let index = args[0].try_as_number().unwrap();
+            let element = elements.get(index as usize).filter(|_| index >= 0.0 && index.fract() == 0.0).cloned();
+            let length = elements.len();
+            Ok(Execution::Pseudo(Box::new(move || {
+                element.ok_or_else(|| EntityExecutionError::new_single(
+                    config.pt,
+                    ErrorType::Execution,
+                    format!("Index {} is out of bounds for list of length {}", index, length),
+                ))
+            })))
+        }).with_arguments(vec![Argument {
+            name: "index".to_string(),
+            possible_types: vec![Type::Number],
+            contributor: &entities().empty_contributor,
+        }]));
+
+    entities().make_entity(format!("List of {}", length))
+        .with_implicit(Type::List, move |_| Value::List(items.clone()))
+        .with_property("length", Value::Number(length as f64).into_entity())
+        .with_property("at", at)
 }
 
 #[derive(Clone)]
@@ -65,21 +126,50 @@ pub struct Argument {
     pub contributor: &'static dyn Contributor
 }
 
-impl Into<Value> for f64 {
-    fn into(self) -> Value {
-        Value::Number(self)
+impl Argument {
+    // value is accepted if it can be implicitly converted to any of possible types.
+    // Entity type accepts anything
+    pub fn accepts(&self, value: &EntityRef) -> bool {
+        let value = value.borrow();
+        self.possible_types.iter().any(|t| *t == Type::Entity || value.implicits().contains_key(t))
+    }
+
+    // user facing description like `string or number`
+    pub fn expected_types(&self) -> String {
+        self.possible_types.iter()
+            .map(|t| t.display_name())
+            .collect::<Vec<_>>()
+            .join(" or ")
+    }
+}
+
+impl From<f64> for Value {
+    fn from(val: f64) -> Self {
+        Value::Number(val)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(val: bool) -> Self {
+        Value::Bool(val)
+    }
+}
+
+impl From<Vec<EntityRef>> for Value {
+    fn from(val: Vec<EntityRef>) -> Self {
+        Value::List(val)
     }
 }
 
-impl Into<Value> for String {
-    fn into(self) -> Value {
-        Value::String(self)
+impl From<String> for Value {
+    fn from(val: String) -> Self {
+        Value::String(val)
     }
 }
 
-impl Into<Value> for EntityRef {
-    fn into(self) -> Value {
-        Value::Entity(self)
+impl From<EntityRef> for Value {
+    fn from(val: EntityRef) -> Self {
+        Value::Entity(val)
     }
 }
 
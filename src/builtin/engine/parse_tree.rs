@@ -1,11 +1,13 @@
-use std::borrow::Borrow;
-use std::cell::{Cell, Ref, RefCell, RefMut, UnsafeCell};
+use std::cell::{Cell, Ref, RefCell, UnsafeCell};
 use std::ops::Deref;
+use std::ops::Range;
+use lalrpop_util::ParseError;
 use typed_arena::Arena;
+use crate::error_printer::{ErrorReport, ErrorType};
 use crate::parser;
 use crate::parser::ast::{ASTKind, ASTNode, ASTValue, ASTError};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub struct PTNodeId(usize);
 
 pub struct PTNode<'a> {
@@ -22,7 +24,7 @@ pub struct PTNode<'a> {
 #[allow(dead_code)]
 impl<'a> PTNode<'a> {
     pub fn text(&'a self) -> &'a str {
-        return self.data;
+        self.data
     }
 
 
@@ -48,7 +50,26 @@ impl<'a> PTNode<'a> {
             if v.is_some() { return v; }
         }
 
-        return None;
+        None
+    }
+
+    // Like find_leaf_on_pos, but position in whitespace between tokens or at the end of input belongs
+    // to the leaf which ends closest before it. In `cd  foo` positions 2 and 3 give `cd`
+    pub fn find_nearest_leaf(&'a self, pos: usize) -> Option<&'a PTNode<'a>> {
+        if let Some(leaf) = self.find_leaf_on_pos(pos) {
+            return Some(leaf);
+        }
+
+        let mut nearest: Option<&'a PTNode<'a>> = None;
+        self.walk(&mut |node| {
+            if !node.is_leaf() || node.origin.span.end() > pos { return; }
+            // later leaves win ties, e.g. an empty error node right after the token
+            if nearest.is_none_or(|n| node.origin.span.end() >= n.origin.span.end()) {
+                nearest = Some(node);
+            }
+        });
+
+        nearest
     }
 
     pub fn walk<F>(&'a self, visitor: &mut F) where F: FnMut(&'a PTNode<'a>) {
@@ -63,12 +84,7 @@ impl<'a> PTNode<'a> {
     }
 
     pub fn find_child_with_kind<'b>(&'b self, kind: ASTKind) -> Option<&'b PTNode<'a>> {
-        for child in self.children.borrow().iter() {
-            if child.kind == kind {
-                return Some(child);
-            }
-        }
-        return None;
+        self.children.borrow().iter().find(|child| child.kind == kind).copied()
     }
     pub fn find_child_with_kind_rec<'b>(&'b self, kind: ASTKind) -> Option<&'b PTNode<'a>> {
         if self.kind == kind || (kind == ASTKind::Error && self.origin.value.kind() == ASTKind::Error) {
@@ -78,15 +94,15 @@ impl<'a> PTNode<'a> {
             let v = child.find_child_with_kind_rec(kind);
             if v.is_some() { return v; }
         }
-        return None;
+        None
     }
 
-    pub fn children(&'a self) -> Ref<Vec<&PTNode<'a>>> {
-        return RefCell::borrow(&self.children);
+    pub fn children(&'a self) -> Ref<'a, Vec<&'a PTNode<'a>>> {
+        RefCell::borrow(&self.children)
     }
 
     pub fn parent(&'a self) -> Option<&'a PTNode<'a>> {
-        return self.parent.get();
+        self.parent.get()
     }
 
     pub fn value<T: ASTValue>(&self) -> &T {
@@ -97,10 +113,7 @@ impl<'a> PTNode<'a> {
         if self.kind == kind {
             return Some(self);
         }
-        if self.parent.get().is_none() {
-            return None;
-        }
-        return self.parent.get().unwrap().find_parent_with_kind(kind);
+        self.parent.get()?.find_parent_with_kind(kind)
     }
 
     pub fn find_node(&'a self, id: PTNodeId) -> Option<&'a PTNode<'a>> {
@@ -111,7 +124,7 @@ impl<'a> PTNode<'a> {
             let v = child.find_node(id);
             if v.is_some() { return v; }
         }
-        return None;
+        None
     }
 
     pub fn position(&self) -> usize {
@@ -129,14 +142,12 @@ pub struct ParseTree<'a> {
 impl<'a> ParseTree<'a> {
     pub fn new(command: &'a str, ast: ASTNode) -> Self {
         let builder = ParseTreeBuilder::new(command);
-        let result = Self {
+
+        Self {
             builder,
             ast,
             root: Default::default(),
-        };
-
-
-        result
+        }
     }
 
     pub fn root(&'a self) -> &'a PTNode<'a> {
@@ -146,6 +157,10 @@ impl<'a> ParseTree<'a> {
         self.root.get().unwrap()
     }
 
+    pub fn ast(&self) -> &ASTNode {
+        &self.ast
+    }
+
     pub fn collect<F>(&'a self, container: &mut Vec<&'a PTNode<'a>>, predicate: F)
         where F: Fn(&'a PTNode<'a>) -> bool {
         self.root().walk(&mut |node| {
@@ -169,7 +184,7 @@ impl<'a> ParseTreeBuilder<'a> {
         }
     }
     fn parse_ast(&'a self, ast: &'a ASTNode) -> &'a PTNode<'a> {
-        return self.parse_node(ast, 0, 0, None).0;
+        self.parse_node(ast, 0, 0, None).0
     }
 
 
@@ -190,28 +205,158 @@ impl<'a> ParseTreeBuilder<'a> {
             id: PTNodeId(id),
         });
 
-        let node_root: &mut Option<&'a PTNode<'a>> = unsafe { std::mem::transmute(node.root.get())  };
+        let node_root: &mut Option<&'a PTNode<'a>> = unsafe { &mut *node.root.get() };
         if node_root.is_none() { *node_root = Some(node); root = Some(node); }
 
-        let mut pos = 0usize;
-        for child in &node.origin.children {
-            let (child_node, new_id) = self.parse_node(child, pos, id + 1, root.clone());
+        for (pos, child) in node.origin.children.iter().enumerate() {
+            let (child_node, new_id) = self.parse_node(child, pos, id + 1, root);
             id = new_id + 1;
             child_node.parent.set(Some(node));
             node.children.borrow_mut().push(child_node);
-            pos += 1;
         }
 
         (node, id)
     }
 }
 
-pub fn parse_line(line: &str) -> Option<ParseTree> {
+pub fn parse_line(line: &str) -> Option<ParseTree<'_>> {
     let ast = parser::parse(line);
     if ast.is_err() {
         return None;
     }
     let tree = ParseTree::new(line, ast.unwrap());
 
-    return Some(tree);
+    Some(tree)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    // recovered errors stay in the tree as error nodes, good for interactive editing
+    Lenient,
+    // any recovered error rejects the whole line, good for scripts
+    Strict,
+}
+
+pub fn parse_checked(line: &str, mode: ParseMode) -> Result<ParseTree<'_>, Vec<ErrorReport<'_>>> {
+    let ast = match parser::parse(line) {
+        Ok(ast) => ast,
+        Err(_) => {
+            let mut report = ErrorReport::new(0..line.len(), line, ErrorType::Syntax);
+            report.add_note("Cannot parse line");
+            return Err(vec![report]);
+        }
+    };
+
+    if mode == ParseMode::Strict {
+        let reports = syntax_errors(line, &ast);
+        if !reports.is_empty() {
+            return Err(reports);
+        }
+    }
+
+    Ok(ParseTree::new(line, ast))
+}
+
+// Reports every error node recovered by the parser
+pub fn syntax_errors<'a>(line: &'a str, ast: &ASTNode) -> Vec<ErrorReport<'a>> {
+    let mut reports = Vec::new();
+    collect_syntax_errors(line, ast, &mut reports);
+
+    reports
+}
+
+fn collect_syntax_errors<'a>(line: &'a str, ast: &ASTNode, reports: &mut Vec<ErrorReport<'a>>) {
+    if let Some(error) = ast.value.downcast_ref::<ASTError>() {
+        let report = match unrecognized_text(error) {
+            Some(span) => {
+                let mut report = ErrorReport::new(span.clone(), line, ErrorType::Syntax);
+                report.add_note(format!("Unexpected character '{}'", line.get(span).unwrap_or("?")));
+                report
+            }
+            None => {
+                let mut report = ErrorReport::new(ast.span.as_range(), line, ErrorType::Syntax);
+                report.add_note(format!("Expected {}", error.expected.kind()));
+                let tokens = error.expected_tokens();
+                if !tokens.is_empty() {
+                    let tokens: Vec<String> = tokens.iter().map(|t| format!("`{}`", t)).collect();
+                    report.add_hint(format!("expected one of: {}", tokens.join(", ")));
+                }
+                report
+            }
+        };
+        reports.push(report);
+    }
+    for child in &ast.children {
+        collect_syntax_errors(line, child, reports);
+    }
+}
+
+// Span of the text the tokenizer couldn't recognize, if that's what the parser stumbled on.
+// Such error is more precise than the whole recovered node
+fn unrecognized_text(error: &ASTError) -> Option<Range<usize>> {
+    match &error.error.as_ref()?.error {
+        ParseError::UnrecognizedToken { token: (start, ASTKind::Error, end), .. } => Some(*start..*end),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_checked() {
+        assert!(parse_checked("$cd(\"kek\")", ParseMode::Strict).is_ok());
+
+        let broken = "$cd(\"kek\"";
+        let errors = parse_checked(broken, ParseMode::Strict).err().unwrap();
+        assert_eq!(errors.len(), 1);
+
+        let tree = parse_checked(broken, ParseMode::Lenient).ok().unwrap();
+        assert!(tree.root().find_child_with_kind_rec(ASTKind::Error).is_some());
+    }
+
+    #[test]
+    fn test_leaf_at_end_of_input() {
+        let line = "$cd(";
+        let tree = parse_line(line).unwrap();
+
+        assert!(tree.root().find_leaf_on_pos(line.len()).is_none());
+        // missing closing paren of the arguments list
+        let leaf = tree.root().find_nearest_leaf(line.len()).unwrap();
+        assert_eq!(leaf.kind, ASTKind::CloseParen);
+        assert_eq!(leaf.origin.value.kind(), ASTKind::Error);
+        assert_eq!(leaf.parent().unwrap().kind, ASTKind::ParenthesizedArgumentsList);
+
+        assert_eq!(tree.root().find_nearest_leaf(line.len() + 1).unwrap().id(), leaf.id());
+        assert_eq!(tree.root().find_nearest_leaf(1).unwrap().data, "cd");
+    }
+
+    #[test]
+    fn test_unrecognized_character() {
+        let line = "$cd(\"a\" @ 1)";
+        let tree = parse_line(line).unwrap();
+        let reports = syntax_errors(line, tree.ast());
+
+        assert_eq!(reports.len(), 1);
+        let report = reports[0].to_string();
+        assert!(report.contains("\n  |         ^ "), "{}", report);
+        assert!(report.ends_with("note: Unexpected character '@'\n"), "{}", report);
+    }
+
+    #[test]
+    fn test_nearest_leaf_in_whitespace() {
+        let line = "cd  foo | grep x";
+        let tree = parse_line(line).unwrap();
+
+        assert!(tree.root().find_leaf_on_pos(2).is_none());
+        assert_eq!(tree.root().find_nearest_leaf(2).unwrap().data, "cd");
+        assert_eq!(tree.root().find_nearest_leaf(3).unwrap().data, "cd");
+        assert_eq!(tree.root().find_nearest_leaf(4).unwrap().data, "foo");
+        assert_eq!(tree.root().find_nearest_leaf(8).unwrap().data, "|");
+        assert_eq!(tree.root().find_nearest_leaf(line.len() + 3).unwrap().data, "x");
+
+        let tree = parse_line("  ls").unwrap();
+        assert!(tree.root().find_nearest_leaf(0).is_none());
+    }
 }
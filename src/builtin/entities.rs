@@ -1,26 +1,47 @@
-use std::collections::HashMap;
-use std::fmt::{Display, format, Formatter};
-use std::io::{Read, Write};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
 use std::rc::Rc;
-use pipe::{PipeReader, PipeWriter};
-use fosh::error_printer::ErrorType;
-use crate::builtin::contributors::FilesContributor;
+use std::time::{Duration, Instant};
+use crate::error_printer::ErrorType;
 use crate::builtin::engine::{Argument, Type, Value};
-use crate::builtin::engine::entities::{Callee, EntitiesManager, Entity, FoshEntity, EntityRef, EntityExecutionError};
+use crate::builtin::paths::{expand_tilde, find_executable};
+use crate::builtin::engine::entities::{Callee, EntitiesManager, FoshEntity, EntityRef, EntityExecutionError, Execution, ExecutionConfig};
+use crate::builtin::engine::parse_tree::{parse_line, PTNodeId, syntax_errors};
 use crate::entities;
+use crate::runtime::execution::execute;
+use termion::is_tty;
 
 
 pub fn initialize_universe(manager: &'static EntitiesManager) {
     manager.global().add_property("cd", make_cd(manager));
+    manager.global().add_property("match", make_match(manager));
+    manager.global().add_property("with_input", make_with_input(manager));
+    manager.global().add_property("timeout", make_timeout(manager));
+    manager.global().add_property("pwd", make_pwd(manager));
+    manager.global().add_property("echo", make_echo(manager));
+    manager.global().add_property("exit", make_exit(manager));
+    manager.global().add_property("jobs", make_jobs(manager));
+    manager.global().add_property("which", make_which(manager));
+    manager.global().add_property("export", make_export(manager));
+    manager.global().add_property("alias", make_alias(manager));
+    manager.global().add_property("ls", make_ls(manager));
+    manager.global().add_property("help", make_help(manager));
+
+    manager.any().add_property("eq", make_eq(manager));
+}
+
+// for map_err on writes to the output of a builtin
+fn write_error(pt: PTNodeId) -> impl Fn(std::io::Error) -> EntityExecutionError {
+    move |e| EntityExecutionError::new_single(pt, ErrorType::Execution, format!("Could not write output: {}", e))
 }
 
 fn make_cd(manager: &'static EntitiesManager) -> EntityRef {
     manager.make_entity("Change Directory call".to_string())
         .with_callee(
             Callee::new_pseudo_execution(
-                move |pt, args, stdin, stdout, stderr|
+                move |pt, args, _stdin, _stdout, _stderr|
                     {
-                        let arg = args.get(0).unwrap().clone();
+                        let arg = args.first().unwrap().clone();
                         if let Err(e) = std::env::set_current_dir(arg.try_as_string().unwrap()) {
                             return Err(EntityExecutionError::new_single(pt, ErrorType::Execution, format!("Could not change directory: {}", e)));
                         }
@@ -29,7 +50,7 @@ fn make_cd(manager: &'static EntitiesManager) -> EntityRef {
             ).with_arguments(vec![Argument {
                 name: "path".to_string(),
                 possible_types: vec![Type::String],
-                contributor: &manager.files_contributor,
+                contributor: &manager.directories_contributor,
             }]).with_result_prototype(
                 move |_, _| {
                     Some(entities().make_entity("cd success".to_string()).with_property("path", Into::<Value>::into("kek".to_string()).into_entity()))
@@ -38,4 +59,643 @@ fn make_cd(manager: &'static EntitiesManager) -> EntityRef {
         )
 }
 
+fn make_pwd(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_entity("Print Working Directory call".to_string())
+        .with_callee(
+            Callee::new_pseudo_execution(
+                move |pt, _args, _stdin, stdout, _stderr|
+                    {
+                        let dir = std::env::current_dir().map_err(|e| {
+                            EntityExecutionError::new_single(pt, ErrorType::Execution, format!("Could not get current directory: {}", e))
+                        })?;
+                        let dir = dir.to_string_lossy().to_string();
+
+                        writeln!(stdout, "{}", dir).map_err(write_error(pt))?;
+                        Ok(Value::String(dir).into_entity())
+                    }
+            )
+        )
+}
+
+fn make_echo(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_entity("Echo call".to_string())
+        .with_callee(
+            Callee::new_pseudo_execution(
+                move |pt, args, _stdin, stdout, _stderr|
+                    {
+                        let text = args.iter()
+                            .map(|a| a.try_as_string().unwrap())
+                            .collect::<Vec<_>>()
+                            .join(" ");
+
+                        writeln!(stdout, "{}", text).map_err(write_error(pt))?;
+                        Ok(Value::String(text).into_entity())
+                    }
+            ).with_arguments(vec![Argument {
+                name: "words".to_string(),
+                possible_types: vec![Type::String],
+                contributor: &manager.empty_contributor,
+            }]).with_variadic()
+        )
+}
+
+fn make_jobs(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_entity("Jobs call".to_string())
+        .with_callee(
+            Callee::new_pseudo_execution(
+                move |pt, _args, _stdin, stdout, _stderr|
+                    {
+                        entities().reap_jobs();
+
+                        let jobs = entities().jobs();
+                        let mut running = 0;
+                        for job in jobs.iter() {
+                            let status = match job.status {
+                                None => {
+                                    running += 1;
+                                    "Running".to_string()
+                                }
+                                Some(status) => match status.code() {
+                                    Some(code) => format!("Done({})", code),
+                                    None => "Killed".to_string(),
+                                },
+                            };
+
+                            writeln!(stdout, "[{}] {} {}", job.id, status, job.command.trim()).map_err(write_error(pt))?;
+                        }
+
+                        Ok(Value::String(format!("{} running, {} finished", running, jobs.len() - running)).into_entity())
+                    }
+            )
+        )
+}
+
+fn make_which(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_entity("Which call".to_string())
+        .with_callee(
+            Callee::new_pseudo_execution(
+                move |pt, args, _stdin, stdout, _stderr|
+                    {
+                        let name = args.first().unwrap().try_as_string().unwrap();
+                        let path = find_executable(&name).ok_or_else(|| {
+                            EntityExecutionError::new_single(pt, ErrorType::Execution, format!("{} not found in PATH", name))
+                        })?;
+                        let path = path.to_string_lossy().to_string();
+
+                        writeln!(stdout, "{}", path).map_err(write_error(pt))?;
+                        Ok(Value::String(path).into_entity())
+                    }
+            ).with_arguments(vec![Argument {
+                name: "command".to_string(),
+                possible_types: vec![Type::String],
+                contributor: &manager.empty_contributor,
+            }])
+        )
+}
+
+// Spawned commands inherit the shell environment, so setting it here is enough
+fn make_export(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_entity("Export call".to_string())
+        .with_callee(
+            Callee::new_pseudo_execution(
+                move |pt, args, _stdin, _stdout, _stderr|
+                    {
+                        let name = args.first().unwrap().try_as_string().unwrap();
+                        let value = args.get(1).unwrap().try_as_string().unwrap();
+                        // set_var panics on these
+                        if name.is_empty() || name.contains('=') || name.contains('\0') || value.contains('\0') {
+                            return Err(EntityExecutionError::new_single(pt, ErrorType::Execution, format!("Invalid environment variable {}", name)));
+                        }
+
+                        std::env::set_var(&name, &value);
+                        Ok(Value::String(value).into_entity())
+                    }
+            ).with_arguments(vec![
+                Argument {
+                    name: "name".to_string(),
+                    possible_types: vec![Type::String],
+                    contributor: &manager.empty_contributor,
+                },
+                Argument {
+                    name: "value".to_string(),
+                    possible_types: vec![Type::String],
+                    contributor: &manager.empty_contributor,
+                },
+            ])
+        )
+}
+
+// Expansion itself happens on the line before it is parsed for execution, see Session::expand_aliases
+fn make_alias(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_entity("Alias call".to_string())
+        .with_callee(
+            Callee::new_pseudo_execution(
+                move |pt, args, _stdin, _stdout, _stderr|
+                    {
+                        let name = args.first().unwrap().try_as_string().unwrap();
+                        let expansion = args.get(1).unwrap().try_as_string().unwrap();
+                        if name.is_empty() || name.contains(char::is_whitespace) {
+                            return Err(EntityExecutionError::new_single(pt, ErrorType::Execution, format!("Invalid alias name {}", name)));
+                        }
+
+                        entities().set_alias(&name, &expansion);
+                        Ok(Value::String(expansion).into_entity())
+                    }
+            ).with_arguments(vec![
+                Argument {
+                    name: "name".to_string(),
+                    possible_types: vec![Type::String],
+                    contributor: &manager.empty_contributor,
+                },
+                Argument {
+                    name: "expansion".to_string(),
+                    possible_types: vec![Type::String],
+                    contributor: &manager.empty_contributor,
+                },
+            ])
+        )
+}
+
+// Returns names in the directory as a list and prints them in columns
+fn make_ls(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_entity("List directory call".to_string())
+        .with_callee(
+            Callee::new_pseudo_execution(
+                move |pt, args, _stdin, stdout, _stderr|
+                    {
+                        let path = args.first().map_or(".".to_string(), |a| a.try_as_string().unwrap());
+                        let all = args.get(1).is_some_and(|a| a.try_as_bool().unwrap());
+
+                        let entries = std::fs::read_dir(expand_tilde(&path).as_ref()).map_err(|e| {
+                            EntityExecutionError::new_single(pt, ErrorType::Execution, format!("Could not read directory {}: {}", path, e))
+                        })?;
+                        let mut names = entries
+                            .filter_map(|e| e.ok())
+                            .map(|e| e.file_name().to_string_lossy().to_string())
+                            .filter(|name| all || !name.starts_with('.'))
+                            .collect::<Vec<_>>();
+                        names.sort();
+
+                        write_columns(stdout, &names).map_err(write_error(pt))?;
+                        Ok(Value::List(names.into_iter().map(|n| Value::String(n).into_entity()).collect()).into_entity())
+                    }
+            ).with_arguments(vec![
+                Argument {
+                    name: "path".to_string(),
+                    possible_types: vec![Type::String],
+                    contributor: &manager.directories_contributor,
+                },
+                Argument {
+                    name: "all".to_string(),
+                    possible_types: vec![Type::Bool],
+                    contributor: &manager.empty_contributor,
+                },
+            ]).with_optional(2)
+        )
+}
+
+// one name per line unless out is a terminal, like ls does
+fn write_columns(out: &mut File, names: &[String]) -> std::io::Result<()> {
+    if names.is_empty() { return Ok(()); }
+    if !is_tty(out) {
+        for name in names {
+            writeln!(out, "{}", name)?;
+        }
+        return Ok(());
+    }
+
+    let width = termion::terminal_size().map_or(80, |(w, _)| w as usize);
+    let column = names.iter().map(|n| n.chars().count()).max().unwrap() + 2;
+    let columns = (width / column).max(1);
+
+    for row in names.chunks(columns) {
+        let line = row.iter()
+            .map(|n| format!("{:width$}", n, width = column))
+            .collect::<String>();
+        writeln!(out, "{}", line.trim_end())?;
+    }
+
+    Ok(())
+}
+
+// Lists global properties with arguments of the callable ones and returns their names
+fn make_help(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_entity("Help call".to_string())
+        .with_callee(
+            Callee::new_pseudo_execution(
+                move |pt, _args, _stdin, stdout, _stderr|
+                    {
+                        let global = entities().global();
+                        let global = global.borrow();
+                        let mut names: Vec<&String> = global.properties().keys().collect();
+                        names.sort();
+
+                        for name in &names {
+                            let property = global.properties()[*name].borrow();
+                            let line = match property.callee() {
+                                Some(callee) => format!("{}({})  {}", name, argument_names(callee), property.name()),
+                                None => format!("{}  {}", name, property.name()),
+                            };
+                            writeln!(stdout, "{}", line).map_err(write_error(pt))?;
+                        }
+
+                        let names = names.into_iter().map(|n| Value::String(n.clone()).into_entity()).collect::<Vec<_>>();
+                        Ok(Value::List(names).into_entity())
+                    }
+            )
+        )
+}
+
+// `path? all?` for optional arguments, `words...` for variadic
+fn argument_names(callee: &Callee) -> String {
+    let optional_from = callee.arguments.len() - callee.optional;
+    callee.arguments.iter().enumerate()
+        .map(|(i, arg)| {
+            if callee.is_variadic && i + 1 == callee.arguments.len() {
+                format!("{}...", arg.name)
+            } else if i >= optional_from {
+                format!("{}?", arg.name)
+            } else {
+                arg.name.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// `$exit` alone is an exit request too, so it quits with 0
+fn make_exit(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_exit_request("Exit call".to_string(), 0)
+        .with_callee(
+            Callee::new_pseudo_execution(
+                move |_pt, args, _stdin, _stdout, _stderr|
+                    {
+                        let status = args.first().map_or(0, |a| a.try_as_number().unwrap() as i32);
+
+                        Ok(entities().make_exit_request("Exit request".to_string(), status))
+                    }
+            ).with_arguments(vec![Argument {
+                name: "status".to_string(),
+                possible_types: vec![Type::Number],
+                contributor: &manager.empty_contributor,
+            }]).with_optional(1)
+        )
+}
+
+// Filters stdin lines containing the pattern
+fn make_match(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_entity("Match call".to_string())
+        .with_callee(
+            Callee::new_pseudo_execution(
+                move |pt, args, stdin, stdout, _stderr|
+                    {
+                        let pattern = args.first().unwrap().try_as_string().unwrap();
+                        let mut matched = String::new();
+                        for line in BufReader::new(stdin).lines() {
+                            let line = line.map_err(|e| {
+                                EntityExecutionError::new_single(pt, ErrorType::Execution, format!("Could not read input: {}", e))
+                            })?;
+                            if !line.contains(&pattern) { continue; }
+
+                            writeln!(stdout, "{}", line).map_err(write_error(pt))?;
+                            matched.push_str(&line);
+                            matched.push('\n');
+                        }
+                        Ok(Value::String(matched).into_entity())
+                    }
+            ).with_arguments(vec![Argument {
+                name: "pattern".to_string(),
+                possible_types: vec![Type::String],
+                contributor: &manager.empty_contributor,
+            }])
+        )
+}
+
+// Entities with the same primitive implicit (number, bool or string) are equal if the values are.
+// Anything else, lists included, is equal only to itself
+pub fn entities_equal(a: &EntityRef, b: &EntityRef) -> bool {
+    if let (Some(a), Some(b)) = (a.try_as_number(), b.try_as_number()) {
+        return a == b;
+    }
+    if let (Some(a), Some(b)) = (a.try_as_bool(), b.try_as_bool()) {
+        return a == b;
+    }
+    if let (Some(a), Some(b)) = (a.try_as_string(), b.try_as_string()) {
+        return a == b;
+    }
+
+    Rc::ptr_eq(a, b)
+}
+
+// Used as `$x.eq(y)`, inherited by every entity
+fn make_eq(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_entity("Equality check".to_string())
+        .with_callee(
+            Callee::new(|me, args, _config| {
+                let equal = entities_equal(&me, &args[0]);
+                Ok(Execution::Pseudo(Box::new(move || Ok(Value::Bool(equal).into_entity()))))
+            }).with_arguments(vec![Argument {
+                name: "other".to_string(),
+                possible_types: vec![Type::Entity],
+                contributor: &manager.empty_contributor,
+            }])
+        )
+}
+
+// Used as `$with_input("text") { command }`, execution feeds the result into command's stdin
+fn make_with_input(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_entity("With input call".to_string())
+        .with_callee(
+            Callee::new_pseudo_execution(
+                move |_pt, args, _stdin, _stdout, _stderr|
+                    {
+                        let text = args.first().unwrap().try_as_string().unwrap();
+                        Ok(Value::String(text).into_entity())
+                    }
+            ).with_arguments(vec![Argument {
+                name: "text".to_string(),
+                possible_types: vec![Type::String],
+                contributor: &manager.empty_contributor,
+            }])
+        )
+}
+
+// Used as `$timeout(1 "sleep 5")`, processes of the command still running after the time is up are killed.
+// Builtins can't be interrupted, so they only finish early if they wait on such a process
+fn make_timeout(manager: &'static EntitiesManager) -> EntityRef {
+    manager.make_entity("Timeout call".to_string())
+        .with_callee(
+            Callee::new(|_me, args, config| {
+                let seconds = args[0].try_as_number().unwrap();
+                let line = args[1].try_as_string().unwrap();
+                Ok(Execution::Pseudo(Box::new(move || run_with_timeout(&line, seconds, config))))
+            }).with_arguments(vec![Argument {
+                name: "seconds".to_string(),
+                possible_types: vec![Type::Number],
+                contributor: &manager.empty_contributor,
+            }, Argument {
+                name: "command".to_string(),
+                possible_types: vec![Type::String],
+                contributor: &manager.empty_contributor,
+            }])
+        )
+}
+
+fn run_with_timeout(line: &str, seconds: f64, config: ExecutionConfig) -> Result<EntityRef, EntityExecutionError> {
+    let pt = config.pt;
+    // negative, NaN and values too big to be a deadline
+    let deadline = match Duration::try_from_secs_f64(seconds).ok().and_then(|d| Instant::now().checked_add(d)) {
+        Some(deadline) => deadline,
+        None => return Err(EntityExecutionError::new_single(pt, ErrorType::Semantic, format!("Invalid timeout {}", seconds))),
+    };
+    let tree = match parse_line(line) {
+        Some(tree) if syntax_errors(line, tree.ast()).is_empty() => tree,
+        _ => return Err(EntityExecutionError::new_single(pt, ErrorType::Syntax, format!("Cannot parse command `{}`", line))),
+    };
+
+    // an outer timeout may be even closer
+    let deadline = config.deadline.map_or(deadline, |d| d.min(deadline));
+    let config = ExecutionConfig { pt: tree.root().id(), deadline: Some(deadline), ..config };
 
+    // nodes of the command's tree mean nothing to the caller, so errors are moved to the call itself
+    execute(tree.root(), &config).execute().map_err(|e| {
+        let mut errors = e.errors.into_values();
+        let mut result = EntityExecutionError::new().with_status(e.status);
+        if let Some(first) = errors.next() {
+            let data = result.with_error(pt, first.kind);
+            data.hints = first.hints;
+            data.notes = first.notes;
+            for other in errors {
+                data.hints.extend(other.hints);
+                data.notes.extend(other.notes);
+            }
+        }
+        result
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtin::paths::tests::TempDir;
+    use crate::builtin::engine::entities::FoshEntity;
+    use crate::entities;
+    use crate::runtime::execution::tests::execute_with_input;
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::{FromRawFd, OwnedFd};
+    use crate::builtin::engine::entities::ExecutionConfig;
+    use crate::builtin::engine::parse_tree::parse_line;
+    use crate::runtime::execution::execute;
+
+    #[test]
+    fn test_pwd() {
+        let (result, output) = execute_with_input("$pwd()", "");
+        let dir = std::env::current_dir().unwrap().to_string_lossy().to_string();
+
+        assert_eq!(output, format!("{}\n", dir));
+        assert_eq!(result.unwrap().try_as_string(), Some(dir));
+    }
+
+    #[test]
+    fn test_echo() {
+        let (result, output) = execute_with_input(r#"$echo("hello" "world")"#, "");
+
+        assert_eq!(output, "hello world\n");
+        assert_eq!(result.unwrap().try_as_string(), Some("hello world".to_string()));
+
+        let (result, output) = execute_with_input("$echo()", "");
+        assert_eq!(output, "\n");
+        assert_eq!(result.unwrap().try_as_string(), Some("".to_string()));
+    }
+
+    #[test]
+    fn test_eq() {
+        let eq = |line| execute_with_input(line, "").0.unwrap().try_as_bool();
+
+        assert_eq!(eq("$1.eq(1)"), Some(true));
+        assert_eq!(eq("$1.eq(2)"), Some(false));
+        assert_eq!(eq(r#"$"kek".eq("kek")"#), Some(true));
+        assert_eq!(eq(r#"$1.eq("1")"#), Some(false));
+        // entities without implicits are compared by identity
+        assert_eq!(eq("$[1].eq([1])"), Some(false));
+        assert_eq!(eq("$cd.eq(cd)"), Some(true));
+        assert_eq!(eq("$cd.eq(pwd)"), Some(false));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let start = std::time::Instant::now();
+        let (result, _) = execute_with_input(r#"$timeout(1 "sleep 5")"#, "");
+        assert!(start.elapsed().as_secs() < 4);
+
+        let error = result.err().unwrap();
+        assert_eq!(error.status, Some(124));
+        let data = error.errors.values().next().unwrap();
+        assert!(matches!(data.kind, crate::error_printer::ErrorType::Timeout));
+        assert_eq!(data.notes, vec!["Execution timed out"]);
+
+        // the grandchild goes too, otherwise reading the output would wait for it
+        let start = std::time::Instant::now();
+        let (result, output) = execute_with_input(r#"$timeout(1 "sh -c 'sleep 5; echo kek'")"#, "");
+        assert!(start.elapsed().as_secs() < 4);
+        assert_eq!(result.err().unwrap().status, Some(124));
+        assert_eq!(output, "");
+
+        let (result, output) = execute_with_input(r#"$timeout(5 "echo kek | cat")"#, "");
+        assert!(result.is_ok());
+        assert_eq!(output, "kek\n");
+
+        for seconds in ["-1", "100000000000000000000000"] {
+            let (result, _) = execute_with_input(&format!(r#"$timeout({} "true")"#, seconds), "");
+            let error = result.err().unwrap();
+            let data = error.errors.values().next().unwrap();
+            assert!(matches!(data.kind, crate::error_printer::ErrorType::Semantic), "{}", seconds);
+        }
+    }
+
+    // polls the job table until the job with given command finishes
+    fn wait_job(command: &str) {
+        let start = std::time::Instant::now();
+        loop {
+            entities().reap_jobs();
+            if entities().jobs().iter().any(|j| j.command.trim() == command && j.status.is_some()) {
+                return;
+            }
+            assert!(start.elapsed() < std::time::Duration::from_secs(5), "{} did not finish", command);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_jobs() {
+        // sleep is killed by the test, so it's running for sure until then
+        let (_, output) = execute_with_input("true & sleep 1000 > /dev/null & $jobs()", "");
+        assert!(output.contains("Running sleep 1000 > /dev/null\n"));
+
+        wait_job("true");
+        let pid = entities().jobs().iter().find(|j| j.command.starts_with("sleep")).unwrap().pid;
+        nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL).unwrap();
+        wait_job("sleep 1000 > /dev/null");
+
+        let (result, output) = execute_with_input("$jobs()", "");
+        assert!(output.contains("Done(0) true\n"), "{}", output);
+        assert!(output.contains("Killed sleep 1000 > /dev/null\n"), "{}", output);
+        assert_eq!(result.unwrap().try_as_string(), Some("0 running, 2 finished".to_string()));
+    }
+
+    #[test]
+    fn test_help() {
+        let (result, output) = execute_with_input("$help()", "");
+
+        assert!(output.contains("\ncd(path)  Change Directory call\n"), "{}", output);
+        assert!(output.contains("\necho(words...)  Echo call\n"), "{}", output);
+        assert!(output.contains("\nls(path? all?)  "), "{}", output);
+
+        let names: Vec<String> = result.unwrap().try_as_list().unwrap().iter().map(|n| n.try_as_string().unwrap()).collect();
+        assert!(names.contains(&"cd".to_string()));
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+    }
+
+    #[test]
+    fn test_which() {
+        let (result, output) = execute_with_input(r#"$which("sh")"#, "");
+        let path = result.unwrap().try_as_string().unwrap();
+
+        assert!(path.ends_with("/sh"));
+        assert_eq!(output, format!("{}\n", path));
+
+        let (result, output) = execute_with_input(r#"$which("definitely-not-a-command")"#, "");
+        assert!(result.is_err());
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn test_export() {
+        let (result, _) = execute_with_input(r#"$export("FOSH_TEST_EXPORT" "kek")"#, "");
+        assert_eq!(result.unwrap().try_as_string(), Some("kek".to_string()));
+
+        let (result, _) = execute_with_input("$FOSH_TEST_EXPORT", "");
+        assert_eq!(result.unwrap().try_as_string(), Some("kek".to_string()));
+
+        let (_, output) = execute_with_input("printenv FOSH_TEST_EXPORT", "");
+        assert_eq!(output, "kek\n");
+
+        let (result, _) = execute_with_input(r#"$export("A=B" "kek")"#, "");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_ls() {
+        let dir = TempDir::new("test_ls");
+        for name in ["b", "a", ".hidden"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let dir = dir.to_string_lossy().to_string();
+        let names = |line: String| {
+            let (result, output) = execute_with_input(&line, "");
+            let names = result.unwrap().try_as_list().unwrap().iter()
+                .map(|n| n.try_as_string().unwrap())
+                .collect::<Vec<_>>();
+            (names, output)
+        };
+
+        let (list, output) = names(format!(r#"$ls("{}")"#, dir));
+        assert_eq!(list, vec!["a", "b"]);
+        // output is a pipe here
+        assert_eq!(output, "a\nb\n");
+
+        let (list, _) = names(format!(r#"$ls("{}" true)"#, dir));
+        assert_eq!(list, vec![".hidden", "a", "b"]);
+
+        let (result, _) = execute_with_input(&format!(r#"$ls("{}").length"#, dir), "");
+        assert_eq!(result.unwrap().try_as_number(), Some(2.0));
+
+        let (result, _) = execute_with_input("$ls()", "");
+        assert!(result.unwrap().try_as_list().is_some());
+
+        // columns only on a terminal
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let line = format!(r#"$ls("{}")"#, dir);
+        let tree = parse_line(&line).unwrap();
+        let config = ExecutionConfig {
+            std_out: Some(unsafe { OwnedFd::from_raw_fd(pty.slave) }),
+            pt: tree.root().id(),
+            ..Default::default()
+        };
+        assert!(execute(tree.root(), &config).execute().is_ok());
+        drop(config);
+
+        let mut master = unsafe { File::from_raw_fd(pty.master) };
+        let mut output = [0u8; 64];
+        let read = master.read(&mut output).unwrap();
+        assert_eq!(&output[..read], b"a  b\r\n");
+    }
+
+    #[test]
+    fn test_exit() {
+        let (result, _) = execute_with_input("$exit(2)", "");
+        assert_eq!(entities().exit_status(&result.unwrap()), Some(2));
+
+        let (result, _) = execute_with_input("$exit", "");
+        assert_eq!(entities().exit_status(&result.unwrap()), Some(0));
+
+        let (result, _) = execute_with_input("$exit()", "");
+        assert_eq!(entities().exit_status(&result.unwrap()), Some(0));
+
+        let (result, _) = execute_with_input("$pwd()", "");
+        assert_eq!(entities().exit_status(&result.unwrap()), None);
+
+        let (result, _) = execute_with_input("$exit(1 2)", "");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_match_filters_piped_lines() {
+        let (result, output) = execute_with_input(r#"$match("ke")"#, "kek\nlol\narbidol\nkeks\n");
+
+        assert_eq!(output, "kek\nkeks\n");
+        assert_eq!(result.unwrap().try_as_string(), Some("kek\nkeks\n".to_string()));
+    }
+}
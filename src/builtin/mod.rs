@@ -2,4 +2,6 @@ pub mod annotator;
 pub mod entities;
 pub mod contributors;
 pub mod engine;
+pub mod paths;
+pub mod path_annotator;
 
@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::builtin::engine::annotator::{AnnotationsSink, Annotator};
+use crate::builtin::engine::parse_tree::PTNode;
+use crate::builtin::paths::{expand_tilde, is_executable, path_directories};
+use crate::entities;
+use crate::ui::settings::ColorType;
+
+const MAX_CACHE_AGE: Duration = Duration::from_secs(30);
+
+// Completes command names with executables found in PATH.
+// Directories are scanned in background thread, so the cache may be not ready right after startup
+pub struct PathAnnotator {
+    cache: Arc<PathCache>,
+    max_age: Duration,
+}
+
+struct PathCache {
+    // None means PATH at the moment of scan
+    directories: Option<Vec<PathBuf>>,
+    names: Mutex<Vec<String>>,
+    ready: AtomicBool,
+    scanning: AtomicBool,
+    last_scan: Mutex<Option<Instant>>,
+}
+
+impl Default for PathAnnotator {
+    fn default() -> Self {
+        PathAnnotator::new()
+    }
+}
+
+impl PathAnnotator {
+    pub fn new() -> Self {
+        Self::with_directories(None, MAX_CACHE_AGE)
+    }
+
+    pub fn with_directories(directories: Option<Vec<PathBuf>>, max_age: Duration) -> Self {
+        let annotator = Self {
+            cache: Arc::new(PathCache {
+                directories,
+                names: Mutex::new(Vec::new()),
+                ready: AtomicBool::new(false),
+                scanning: AtomicBool::new(false),
+                last_scan: Mutex::new(None),
+            }),
+            max_age,
+        };
+        annotator.refresh();
+
+        annotator
+    }
+
+    // false until the first scan completes
+    pub fn is_ready(&self) -> bool {
+        self.cache.ready.load(Ordering::Acquire)
+    }
+
+    // Starts rescan unless one is already running. Old names are served until it completes
+    pub fn refresh(&self) {
+        if self.cache.scanning.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let cache = self.cache.clone();
+        std::thread::spawn(move || {
+            cache.update_cache();
+            cache.scanning.store(false, Ordering::Release);
+        });
+    }
+
+    fn is_stale(&self) -> bool {
+        self.cache.last_scan.lock().unwrap()
+            .is_none_or(|t| t.elapsed() > self.max_age)
+    }
+
+    // None while the cache is still loading
+    pub fn complete(&self, prefix: &str) -> Option<Vec<String>> {
+        if self.is_stale() {
+            self.refresh();
+        }
+        if !self.is_ready() {
+            return None;
+        }
+
+        let names = self.cache.names.lock().unwrap();
+        Some(names.iter().filter(|x| x.starts_with(prefix)).cloned().collect())
+    }
+
+    // Whether the command would be found like exec does. None while the cache is still loading
+    pub fn resolves(&self, name: &str) -> Option<bool> {
+        if name.contains('/') {
+            return Some(is_executable(expand_tilde(name).as_ref().as_ref()));
+        }
+        if !self.is_ready() {
+            return None;
+        }
+
+        Some(self.cache.names.lock().unwrap().iter().any(|x| x == name))
+    }
+}
+
+impl PathCache {
+    fn update_cache(&self) {
+        let directories = match &self.directories {
+            Some(directories) => directories.clone(),
+            None => path_directories(),
+        };
+
+        // the same name in later directories is shadowed, so it's listed once
+        let mut seen = HashSet::new();
+        let mut names = Vec::new();
+        for dir in directories {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let name = match entry.file_name().to_str() {
+                    Some(name) => name.to_string(),
+                    None => continue,
+                };
+                if seen.contains(&name) || !is_executable(&entry.path()) {
+                    continue;
+                }
+                seen.insert(name.clone());
+                names.push(name);
+            }
+        }
+
+        *self.names.lock().unwrap() = names;
+        *self.last_scan.lock().unwrap() = Some(Instant::now());
+        self.ready.store(true, Ordering::Release);
+    }
+}
+
+impl Annotator for PathAnnotator {
+    fn annotate<'a>(&self, node: &'a PTNode<'a>, sink: &mut AnnotationsSink) {
+        let completions = self.complete(node.data);
+        let known = node.data.is_empty()
+            || entities().get_alias(node.data).is_some()
+            || entities().global().borrow().properties().contains_key(node.data);
+
+        match self.resolves(node.data) {
+            Some(true) => sink.add_color(ColorType::CommandName),
+            // a prefix of some command may be still being typed
+            Some(false) if !known && completions.as_ref().is_some_and(|c| c.is_empty()) => {
+                sink.add_color(ColorType::AbsentCommandName)
+            }
+            _ => {}
+        }
+
+        match completions {
+            Some(names) => names.into_iter().for_each(|x| sink.add_completion(x)),
+            None => sink.add_hint("Scanning PATH..."),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::builtin::paths::tests::TempDir;
+    use std::os::unix::fs::PermissionsExt;
+    use super::*;
+
+    pub(crate) fn wait_ready(annotator: &PathAnnotator) {
+        let start = Instant::now();
+        while !annotator.is_ready() {
+            assert!(start.elapsed() < Duration::from_secs(5), "PATH scan did not complete");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_completions_after_scan() {
+        let dir = TempDir::new("path_annotator");
+        let command = dir.join("fosh_test_command");
+        std::fs::write(&command, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&command, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let annotator = PathAnnotator::with_directories(Some(vec![dir.to_path_buf()]), MAX_CACHE_AGE);
+        wait_ready(&annotator);
+
+        assert_eq!(annotator.complete("fosh_te"), Some(vec!["fosh_test_command".to_string()]));
+        assert_eq!(annotator.complete("other"), Some(vec![]));
+
+        assert_eq!(annotator.resolves("fosh_test_command"), Some(true));
+        assert_eq!(annotator.resolves("fosh_test_comman"), Some(false));
+        assert_eq!(annotator.resolves(command.to_str().unwrap()), Some(true));
+        assert_eq!(annotator.resolves("./fosh_test_command"), Some(false));
+
+    }
+
+    #[test]
+    fn test_skips_duplicates_and_non_executables() {
+        let root = TempDir::new("path_annotator_dedup");
+        let first = root.join("first");
+        let second = root.join("second");
+        std::fs::create_dir_all(&first).unwrap();
+        std::fs::create_dir_all(&second).unwrap();
+
+        for (dir, name, mode) in [
+            (&first, "fosh_exec", 0o755),
+            (&first, "fosh_plain", 0o644),
+            (&second, "fosh_exec", 0o755),
+            (&second, "fosh_other", 0o700),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, "#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        std::fs::create_dir_all(first.join("fosh_dir")).unwrap();
+
+        let annotator = PathAnnotator::with_directories(Some(vec![first, second]), MAX_CACHE_AGE);
+        wait_ready(&annotator);
+
+        let mut names = annotator.complete("fosh_").unwrap();
+        names.sort();
+        assert_eq!(names, vec!["fosh_exec".to_string(), "fosh_other".to_string()]);
+
+    }
+}
@@ -0,0 +1,96 @@
+use std::borrow::Cow;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+// Expands leading `~` or `~/` to the home directory. `~user` forms are left untouched
+pub fn expand_tilde(path: &str) -> Cow<'_, str> {
+    if path != "~" && !path.starts_with("~/") {
+        return Cow::Borrowed(path);
+    }
+
+    match std::env::var("HOME") {
+        Ok(home) => Cow::Owned(format!("{}{}", home, &path[1..])),
+        Err(_) => Cow::Borrowed(path),
+    }
+}
+
+// Directories listed in PATH in lookup order
+pub fn path_directories() -> Vec<PathBuf> {
+    std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).collect())
+        .unwrap_or_default()
+}
+
+pub fn is_executable(path: &Path) -> bool {
+    path.metadata().is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+// Resolves command name like exec does: names containing `/` are taken as is, others are searched in PATH
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = PathBuf::from(expand_tilde(name).as_ref());
+        return if is_executable(&path) { Some(path) } else { None };
+    }
+
+    path_directories().into_iter()
+        .map(|dir| dir.join(name))
+        .find(|path| is_executable(path))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::ops::Deref;
+    use super::*;
+
+    // Fresh directory for a test, removed with everything inside when dropped, so failed tests clean up too
+    pub struct TempDir {
+        path: PathBuf,
+    }
+
+    impl TempDir {
+        // name only has to differ between tests, the process id is added to it
+        pub fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("fosh_{}_{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+
+            Self { path }
+        }
+    }
+
+    impl Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let home = std::env::var("HOME").unwrap();
+
+        assert_eq!(expand_tilde("~"), home);
+        assert_eq!(expand_tilde("~/proj"), format!("{}/proj", home));
+        assert_eq!(expand_tilde("~user/proj"), "~user/proj");
+        assert_eq!(expand_tilde("a/~/b"), "a/~/b");
+    }
+
+    #[test]
+    fn test_find_executable() {
+        let sh = find_executable("sh").unwrap();
+        assert!(sh.is_absolute());
+        assert!(sh.ends_with("sh"));
+
+        assert_eq!(find_executable(sh.to_str().unwrap()), Some(sh));
+        assert_eq!(find_executable("definitely-not-a-command"), None);
+        assert_eq!(find_executable("Cargo.toml"), None);
+        assert_eq!(find_executable("./Cargo.toml"), None);
+    }
+}
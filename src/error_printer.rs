@@ -0,0 +1,212 @@
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use termion::color::{Blue, Color, Cyan, Fg, Red, Reset};
+use unicode_width::UnicodeWidthChar;
+
+const TAB_WIDTH: usize = 8;
+
+#[derive(parse_display_derive::Display)]
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorType {
+    Syntax,
+    Semantic,
+    Execution,
+    CannotCreatePipe,
+    CannotCloneFd,
+    Timeout,
+    Internal
+}
+
+pub struct ErrorReport<'a> {
+    span: Range<usize>,
+    text: Cow<'a, str>,
+
+    hints: Vec<Cow<'a, str>>,
+    notes: Vec<Cow<'a, str>>,
+
+    error_type: ErrorType,
+    // escape sequences are only emitted when set, so redirected output stays clean
+    colored: bool,
+}
+
+impl<'a> ErrorReport<'a> {
+    pub fn new(span: Range<usize>, text: &'a str, error_type: ErrorType) -> Self {
+        Self {
+            span,
+            text: Cow::Borrowed(text),
+            hints: Vec::new(),
+            error_type,
+            notes: Vec::new(),
+            colored: false,
+        }
+    }
+
+    pub fn with_colors(mut self, colored: bool) -> Self {
+        self.colored = colored;
+        self
+    }
+
+    pub fn add_hint<S : Into<Cow<'a, str>>>(&mut self, hint: S) {
+        self.hints.push(hint.into());
+    }
+
+    pub fn add_note<S : Into<Cow<'a, str>>>(&mut self, note: S)  {
+        self.notes.push(note.into());
+    }
+
+    // for reports on text which doesn't outlive the report, like a line with expanded aliases
+    pub fn into_owned(self) -> ErrorReport<'static> {
+        ErrorReport {
+            span: self.span,
+            text: Cow::Owned(self.text.into_owned()),
+            hints: self.hints.into_iter().map(|x| Cow::Owned(x.into_owned())).collect(),
+            notes: self.notes.into_iter().map(|x| Cow::Owned(x.into_owned())).collect(),
+            error_type: self.error_type,
+            colored: self.colored,
+        }
+    }
+}
+
+impl<'a> Display for ErrorReport<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {}", paint(self.colored, Red, "error:"), self.error_type)?;
+
+        // only the line where the span starts is shown, the rest of the span is cut off
+        let text: &str = &self.text;
+        let span_start = self.span.start.min(text.len());
+        let line_start = text[..span_start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[line_start..].find('\n').map_or(text.len(), |i| line_start + i);
+        let line = &text[line_start..line_end];
+        let line_number = text[..line_start].matches('\n').count() + 1;
+        let span = span_start - line_start..self.span.end.clamp(span_start, line_end) - line_start;
+
+        if line.len() <= 80 {
+            print_with_highlight(line, line_number, span, self.colored, f)?;
+        } else {
+            let delta = 80usize.saturating_sub(span.end - span.start + 1);
+
+            let mut start = span.start.saturating_sub(delta);
+            let mut end = (span.end + delta).min(line.len());
+            while !line.is_char_boundary(start) { start -= 1; }
+            while !line.is_char_boundary(end) { end += 1; }
+
+            print_with_highlight(&line[start..end], line_number, span.start - start..span.end - start, self.colored, f)?;
+        }
+
+        for hint in &self.hints {
+            writeln!(f, "{} {}", paint(self.colored, Cyan, "hint:"), hint)?;
+        }
+        for hint in &self.notes {
+            writeln!(f, "{} {}", paint(self.colored, Blue, "note:"), hint)?;
+        }
+
+        Ok(())
+
+    }
+}
+
+// print given line with its number and underline the span with ^^^^^.
+// Span is in bytes, underline is in displayed columns: tabs are expanded and wide chars take two
+fn print_with_highlight(text: &str, line_number: usize, span: Range<usize>, colored: bool, f: &mut Formatter<'_>) -> std::fmt::Result {
+    // empty span, like a missing token, still gets a mark at its position
+    let span = if span.is_empty() { span.start..span.start + 1 } else { span };
+
+    let mut line = String::with_capacity(text.len());
+    let mut underline = String::with_capacity(text.len());
+    let mut column = 0;
+    for (i, c) in text.char_indices() {
+        let width = if c == '\t' {
+            TAB_WIDTH - column % TAB_WIDTH
+        } else {
+            c.width().unwrap_or(0)
+        };
+        column += width;
+
+        if c == '\t' {
+            line.push_str(&" ".repeat(width));
+        } else {
+            line.push(c);
+        }
+        let mark = if span.start <= i && i < span.end { "^" } else { " " };
+        underline.push_str(&mark.repeat(width));
+    }
+    if span.start == text.len() {
+        underline.push('^');
+    }
+
+    let gutter = line_number.to_string();
+    writeln!(f, "{} | {}", gutter, line)?;
+    writeln!(f, "{} | {}", " ".repeat(gutter.len()), paint(colored, Red, &underline))
+}
+
+fn paint<C: Color>(colored: bool, color: C, text: &str) -> String {
+    if colored {
+        format!("{}{}{}", Fg(color), text, Fg(Reset))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_line() {
+        let report = ErrorReport::new(4..7, "cat kek", ErrorType::Semantic);
+
+        assert_eq!(report.to_string(), "error: Semantic\n1 | cat kek\n  |     ^^^\n");
+    }
+
+    #[test]
+    fn test_second_line() {
+        let mut report = ErrorReport::new(12..15, "echo a;\ncat kek\nls", ErrorType::Execution);
+        report.add_note("kek");
+
+        assert_eq!(report.to_string(), "error: Execution\n2 | cat kek\n  |     ^^^\nnote: kek\n");
+    }
+
+    #[test]
+    fn test_tab_before_span() {
+        let report = ErrorReport::new(5..8, "\tcat kek", ErrorType::Semantic);
+
+        assert_eq!(report.to_string(), "error: Semantic\n1 |         cat kek\n  |             ^^^\n");
+    }
+
+    #[test]
+    fn test_wide_chars_before_span() {
+        let report = ErrorReport::new(7..10, "日本 kek", ErrorType::Semantic);
+
+        assert_eq!(report.to_string(), "error: Semantic\n1 | 日本 kek\n  |      ^^^\n");
+    }
+
+    #[test]
+    fn test_colors() {
+        let mut report = ErrorReport::new(4..7, "cat kek", ErrorType::Semantic).with_colors(true);
+        report.add_hint("lol");
+
+        let text = report.to_string();
+        assert!(text.starts_with(&format!("{}error:{} Semantic", Fg(Red), Fg(Reset))));
+        assert!(text.contains(&format!("{}    ^^^{}", Fg(Red), Fg(Reset))));
+        assert!(text.contains(&format!("{}hint:{} lol", Fg(Cyan), Fg(Reset))));
+
+        assert!(!report.with_colors(false).to_string().contains('\x1b'));
+    }
+
+    #[test]
+    fn test_empty_span() {
+        let report = ErrorReport::new(4..4, "cat kek", ErrorType::Syntax);
+        assert_eq!(report.to_string(), "error: Syntax\n1 | cat kek\n  |     ^  \n");
+
+        let report = ErrorReport::new(7..7, "cat kek", ErrorType::Syntax);
+        assert_eq!(report.to_string(), "error: Syntax\n1 | cat kek\n  |        ^\n");
+    }
+
+    #[test]
+    fn test_span_crossing_newline() {
+        let report = ErrorReport::new(5..10, "echo a\nb", ErrorType::Syntax);
+
+        assert_eq!(report.to_string(), "error: Syntax\n1 | echo a\n  |      ^\n");
+    }
+}
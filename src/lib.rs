@@ -0,0 +1,81 @@
+// The shell as a library, so other programs can embed it. See Session::eval.
+// The fosh binary is a thin loop on top of it
+
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{stderr, Write};
+use termion::is_tty;
+use crate::builtin::engine::entities::EntitiesManager;
+use crate::builtin::engine::parse_tree::PTNode;
+use crate::error_printer::ErrorReport;
+
+pub mod error_printer;
+pub mod misc;
+
+pub mod builtin;
+pub mod parser;
+pub mod runtime;
+pub mod session;
+pub mod ui;
+
+pub use crate::builtin::engine::{Argument, Type, Value};
+pub use crate::builtin::engine::entities::{Callee, EntityExecutionError, EntityRef, Execution, ExecutionConfig, FoshEntity};
+pub use crate::session::Session;
+
+// The shell is single threaded: entities are Rc/RefCell, so they are created, used and dropped on one thread.
+// Threads spawned for pipes and PATH scanning never touch them. The current universe is therefore per thread,
+// which also gives every test its own universe
+thread_local! {
+    static ENTITIES: Cell<Option<&'static EntitiesManager>> = const { Cell::new(None) };
+}
+
+pub fn entities() -> &'static EntitiesManager {
+    ENTITIES.with(|e| e.get()).expect("No entities manager in this thread. See Session::new")
+}
+
+// Makes given manager the one returned by entities() in the current thread. See Session::new
+pub fn set_entities(manager: &'static EntitiesManager) {
+    ENTITIES.with(|e| e.set(Some(manager)));
+}
+
+pub fn construct_error_report<'a, 'b>(s: &'b str, root: &'a PTNode<'a>, error: &EntityExecutionError) -> Vec<ErrorReport<'b>> {
+    let mut reports = Vec::new();
+
+    for (node_id, msg) in &error.errors {
+        let node = root.find_node(*node_id).unwrap();
+        let mut report = ErrorReport::new(
+            node.origin.span.as_range(),
+            s,
+            msg.kind
+        );
+
+        for note in &msg.notes {
+            report.add_note(note.to_owned());
+        }
+
+        for hint in &msg.hints {
+            report.add_hint(hint.to_owned());
+        }
+
+        reports.push(report);
+    }
+
+    reports
+}
+
+// Prints errors of a stage which didn't stop the execution, like the first command of `a ; b`.
+// They go to the stderr of the execution, so embedding programs get them in their own stream
+pub fn report<'a>(root: &'a PTNode<'a>, error: &EntityExecutionError, config: &ExecutionConfig) {
+    let reports = construct_error_report(root.data, root, error);
+    let (mut out, colored): (Box<dyn Write>, bool) = match config.std_err.as_ref().map(|e| e.try_clone()) {
+        Some(Ok(err)) => {
+            let colored = is_tty(&err);
+            (Box::new(File::from(err)), colored)
+        }
+        Some(Err(_)) => return,
+        None => (Box::new(stderr()), is_tty(&stderr())),
+    };
+    for report in reports {
+        let _ = writeln!(out, "{}", report.with_colors(colored));
+    }
+}
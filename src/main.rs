@@ -1,32 +1,13 @@
-#![allow(unused_imports)]
-
-extern crate core;
-
-mod ui;
-mod parser;
-mod builtin;
-mod runtime;
-
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::fs::File;
 use nix::unistd;
 
-use std::io::{stderr, stdin, stdout, Write};
-use std::os::unix::io::{AsRawFd, FromRawFd};
-use nix::libc::dup;
-use termion::event::Key;
-use termion::input::{TermRead};
+use std::io::{stderr, stdin};
 use termion::is_tty;
-use termion::raw::IntoRawMode;
-use fosh::error_printer::ErrorReport;
-use crate::builtin::engine::entities::{EntitiesManager, EntityExecutionError, EntityRef, ExecutionConfig};
-use crate::builtin::engine::parse_tree::{parse_line, PTNode, PTNodeId};
-use crate::builtin::entities::initialize_universe;
-use crate::parser::ast::ASTKind;
-use crate::runtime::execution::execute;
-use crate::ui::settings::TUISettings;
-use crate::ui::tui::TUI;
+use fosh::builtin::engine::entities::FoshEntity;
+use fosh::builtin::engine::parse_tree::{parse_line, ParseMode};
+use fosh::runtime::explain::explain;
+use fosh::ui::settings::TUISettings;
+use fosh::session::{RunError, Session};
+use fosh::ui::tui::TUI;
 
 fn set_unique_pid() -> nix::Result<()> {
     let pgid = unistd::getpid();
@@ -39,48 +20,59 @@ fn set_unique_pid() -> nix::Result<()> {
     Ok(())
 }
 
-static mut ENTITIES: Option<EntitiesManager> = None;
-
-pub fn entities() -> &'static EntitiesManager {
-    unsafe {
-        ENTITIES.as_ref().unwrap()
-    }
-}
-
-pub fn construct_error_report<'a, 'b>(s: &'b str, root: &'a PTNode<'a>, error: &EntityExecutionError) -> Vec<ErrorReport<'b>> {
-    let mut reports = Vec::new();
-
-    for (node_id, msg) in &error.errors {
-        let node = root.find_node(*node_id).unwrap();
-        let mut report = ErrorReport::new(
-            node.origin.span.as_range(),
-            s,
-            msg.kind
-        );
-
-        for note in &msg.notes {
-            report.add_note(note.to_owned());
+// Runs line like the interactive loop does and returns the status for the process to exit with
+fn run_once(session: &Session, line: &str) -> i32 {
+    match session.run(line) {
+        Ok(entity) => {
+            if let Some(status) = session.entities().exit_status(&entity) {
+                return status;
+            }
         }
-
-        for hint in &msg.hints {
-            report.add_hint(hint.to_owned());
+        Err(err) => {
+            let colored = is_tty(&stderr());
+            let syntax = matches!(err, RunError::Syntax(_));
+            for report in err.into_reports() {
+                eprintln!("{}", report.with_colors(colored));
+            }
+            if syntax {
+                return 2;
+            }
         }
-
-        reports.push(report);
     }
 
-    reports
+    session.entities().get_variable("?")
+        .and_then(|s| s.try_as_number())
+        .map_or(0, |s| s as i32)
 }
 
-pub fn report<'a>(root: &'a PTNode<'a>, error: &EntityExecutionError) {
-    let reports = construct_error_report(root.data, root, error);
-    for report in reports {
-        println!("{}", report);
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|a| a.as_str()) == Some("--explain") {
+        let line = args[2..].join(" ");
+        match parse_line(&line) {
+            Some(tree) => print!("{}", explain(tree.root())),
+            None => eprintln!("Cannot parse {}", line),
+        }
+        return;
     }
-}
-
 
-fn main() {
+    let mode = if args.iter().skip(1).any(|a| a == "--strict") {
+        ParseMode::Strict
+    } else {
+        ParseMode::Lenient
+    };
+
+    if let Some(i) = args.iter().position(|a| a == "-c") {
+        let line = match args.get(i + 1) {
+            Some(line) => line,
+            None => {
+                eprintln!("fosh: -c requires an argument");
+                std::process::exit(2);
+            }
+        };
+        let session = Session::new().with_mode(mode);
+        std::process::exit(run_once(&session, line));
+    }
 
     if is_tty(&stdin()) {
         if let Err(e) = set_unique_pid() {
@@ -89,44 +81,40 @@ fn main() {
     }
 
 
-    unsafe {
-        ENTITIES = Some(EntitiesManager::new());
-    }
-    let settings = RefCell::new(TUISettings::new());
-
-    initialize_universe(entities());
-
-    let mut tui = TUI::new(">> ".into(), &settings);
+    // error reports go to stderr, like the ones report() prints for earlier stages of the line
+    let colored = is_tty(&stderr());
+    // scripts piped into the shell get neither prompt nor echo of results
+    let interactive = is_tty(&stdin());
+    // lines of a piped script must not be eaten by the commands it runs
+    let session = Session::new().with_mode(mode).with_null_stdin(!interactive);
+    *session.settings().borrow_mut() = TUISettings::load();
+    let mut tui = TUI::new(">> ".into(), &session);
 
     loop {
         let line = tui.next_line().unwrap();
         if line.is_none() { break; }
         let line = line.unwrap();
         if line.is_empty() { continue; }
-        let tree = parse_line(&line).unwrap();
-
-        if tree.root().find_child_with_kind_rec(ASTKind::Error).is_some() {
-            println!("Syntax error");
-            continue;
-        }
 
-        let config = ExecutionConfig {
-            std_in: None,
-            std_out: None,
-            std_err: None,
-            pt: tree.root().id()
-        };
-        match execute(tree.root(), &config).execute() {
+        match session.run(&line) {
             Ok(entity) => {
-                println!("Entity: {}", entity.borrow());
+                if let Some(status) = session.entities().exit_status(&entity) {
+                    std::process::exit(status);
+                }
+                if interactive {
+                    println!("Entity: {}", entity.borrow());
+                }
             }
             Err(err) => {
-                let reports = construct_error_report(&line, tree.root(), &err);
-                for report in reports {
-                    println!("{}", report);
+                // scripts should fail fast instead of going on with the next line
+                let fatal = matches!(err, RunError::Syntax(_)) && session.mode() == ParseMode::Strict;
+                for report in err.into_reports() {
+                    eprintln!("{}", report.with_colors(colored));
+                }
+                if fatal {
+                    std::process::exit(2);
                 }
             }
         }
     }
 }
-
@@ -1,19 +1,18 @@
-use std::fmt::{Debug, Display, format};
-use std::fs::File;
-use std::io::{Error, ErrorKind, stderr, stdin, stdout};
-use std::os::unix::io::RawFd;
-use std::os::unix::prelude::{AsRawFd, CommandExt, FromRawFd};
-use std::process::{Child, Stdio};
-use std::rc::Rc;
+use std::borrow::Cow;
+use std::fmt::{Debug, Display};
+use std::io::Error;
+use std::os::unix::prelude::CommandExt;
+use std::process::Stdio;
+use std::str::FromStr;
 use downcast_rs::{Downcast, impl_downcast};
-use lalrpop_util::ErrorRecovery;
-use lalrpop_util::lexer::Token;
-use nix::unistd::dup2;
+use lalrpop_util::{ErrorRecovery, ParseError};
+use nix::unistd::{Pid, setpgid};
 use termion::color::{Bg, Cyan, Fg, Green, LightGreen, LightMagenta, LightYellow, Magenta, Red, Yellow};
-use fosh::error_printer::ErrorType;
-use crate::builtin::engine::entities::{Callee, EntitiesManager, Entity, FoshEntity, EntityExecutionError, EntityRef, ProcessExecution, Execution};
-use crate::builtin::engine::parse_tree::{PTNode, PTNodeId};
-use crate::builtin::engine::{Type, Value};
+use crate::error_printer::ErrorType;
+use crate::builtin::engine::entities::{Callee, FoshEntity, EntityExecutionError, EntityRef, ProcessExecution, Execution};
+use crate::builtin::engine::parse_tree::PTNode;
+use crate::builtin::engine::Value;
+use crate::builtin::paths::expand_tilde;
 use crate::entities;
 
 #[derive(Debug, Eq, PartialEq)]
@@ -65,7 +64,7 @@ impl Eq for ASTNode {}
 
 impl ASTNode {
     pub fn new_simple<T: ASTValue>(l: usize, r: usize, value: T, children: Vec<ASTNode>) -> Self {
-        return Self::new(Span::new(l, r), Box::new(value), children);
+        Self::new(Span::new(l, r), Box::new(value), children)
     }
     pub fn new(span: Span, value: Box<dyn ASTValue>, children: Vec<ASTNode>) -> Self {
         Self { span, value, children }
@@ -79,7 +78,7 @@ impl ASTNode {
             let v = child.find_child_with_kind(kind);
             if v.is_some() { return v; }
         }
-        return None;
+        None
     }
 }
 
@@ -87,17 +86,35 @@ impl ASTNode {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ASTKind {
     Piped,
-    Sequenced,
+    // commands separated by &, all but the last one run in background
+    Background,
+    // commands separated by ;, run one after another
     Delimited,
+    And,
+    Or,
+    Redirected,
+    Redirection,
 
     // General mode tokens
     Ampersand,
+    // & with no command after it
+    TrailingAmpersand,
     Pipe,
+    DoubleAmpersand,
+    DoublePipe,
+    // > >> 2> <
+    Greater,
+    DoubleGreater,
+    ErrGreater,
+    Less,
     SemiColon,
+    // ; with no command after it
+    TrailingSemiColon,
     Dollar,
 
     // Special mode tokens
     DoubleQuote,
+    SingleQuote,
     Literal,
 
     // Function mode tokens
@@ -105,16 +122,34 @@ pub enum ASTKind {
     CloseParen,
     OpenBrace,
     CloseBrace,
+    OpenBracket,
+    CloseBracket,
     StringLiteral,
     NumberLiteral,
+    BoolLiteral,
     Dot,
     Comma,
     Identifier,
     Equals,
+    If,
+    Else,
+    While,
+    // + - * / %
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
     VariableName,
+    EnvironmentVariable,
 
     // Function mode non-terminals
     Function,
+    // `[a b c]`
+    ListLiteral,
+    // `(expr)` after `$`
+    Arithmetic,
+    BinaryOperation,
     ParenthesizedArgumentsList,
     PropertyInsn,
     PropertyCall,
@@ -122,6 +157,10 @@ pub enum ASTKind {
     Assignation,
     BracedCommand,
     Parameter,
+    // `if cond { a } else { b }` after `$`, else branch is optional
+    Conditional,
+    // `while cond { body }` after `$`
+    WhileLoop,
 
     // Command mode non-terminals
     Command,
@@ -144,12 +183,18 @@ impl ASTKind {
         match self {
             ASTKind::Dollar => buf.push_str(&Fg(Yellow).to_string()),
             ASTKind::Pipe => buf.push_str(&Fg(Cyan).to_string()),
-            ASTKind::Ampersand => buf.push_str(&Fg(Cyan).to_string()),
-            ASTKind::SemiColon => buf.push_str(&Fg(Cyan).to_string()),
+            ASTKind::Ampersand | ASTKind::TrailingAmpersand => buf.push_str(&Fg(Cyan).to_string()),
+            ASTKind::SemiColon | ASTKind::TrailingSemiColon => buf.push_str(&Fg(Cyan).to_string()),
+            ASTKind::DoubleAmpersand => buf.push_str(&Fg(Cyan).to_string()),
+            ASTKind::DoublePipe => buf.push_str(&Fg(Cyan).to_string()),
+            ASTKind::Greater | ASTKind::DoubleGreater | ASTKind::ErrGreater | ASTKind::Less => buf.push_str(&Fg(Cyan).to_string()),
+            ASTKind::Plus | ASTKind::Minus | ASTKind::Star | ASTKind::Slash | ASTKind::Percent => buf.push_str(&Fg(Cyan).to_string()),
+            ASTKind::If | ASTKind::Else | ASTKind::While => buf.push_str(&Fg(Yellow).to_string()),
             ASTKind::PropertyName => buf.push_str(&Fg(LightYellow).to_string()),
             ASTKind::CommandName => buf.push_str(&Fg(LightGreen).to_string()),
             ASTKind::StringLiteral => buf.push_str(&Fg(Green).to_string()),
             ASTKind::VariableName => buf.push_str(&Fg(Magenta).to_string()),
+            ASTKind::EnvironmentVariable => buf.push_str(&Fg(LightMagenta).to_string()),
             ASTKind::Error => buf.push_str(&Bg(Red).to_string()),
             _ => {}
         }
@@ -182,7 +227,7 @@ impl_downcast!(ASTValue);
 
 macro_rules! simple_token {
     ($name: ident, $kind: expr) => {
-        #[derive(Debug)]
+        #[derive(Debug, Default)]
         pub struct $name {
         }
 
@@ -202,6 +247,7 @@ macro_rules! simple_token {
 
 
 simple_token!(Ampersand, ASTKind::Ampersand);
+simple_token!(TrailingAmpersand, ASTKind::TrailingAmpersand);
 simple_token!(OpenParen, ASTKind::OpenParen);
 simple_token!(CloseParen, ASTKind::CloseParen);
 simple_token!(Literal, ASTKind::Literal);
@@ -209,10 +255,22 @@ simple_token!(OpenBrace, ASTKind::OpenBrace);
 simple_token!(CloseBrace, ASTKind::CloseBrace);
 simple_token!(Dot, ASTKind::Dot);
 simple_token!(SemiColon, ASTKind::SemiColon);
+simple_token!(TrailingSemiColon, ASTKind::TrailingSemiColon);
 simple_token!(Dollar, ASTKind::Dollar);
 simple_token!(Pipe, ASTKind::Pipe);
 simple_token!(StringLiteral, ASTKind::StringLiteral);
 simple_token!(NumberLiteral, ASTKind::NumberLiteral);
+simple_token!(BoolLiteral, ASTKind::BoolLiteral);
+simple_token!(OpenBracket, ASTKind::OpenBracket);
+simple_token!(CloseBracket, ASTKind::CloseBracket);
+simple_token!(ListLiteral, ASTKind::ListLiteral);
+simple_token!(Plus, ASTKind::Plus);
+simple_token!(Minus, ASTKind::Minus);
+simple_token!(Star, ASTKind::Star);
+simple_token!(Slash, ASTKind::Slash);
+simple_token!(Percent, ASTKind::Percent);
+simple_token!(Arithmetic, ASTKind::Arithmetic);
+simple_token!(BinaryOperation, ASTKind::BinaryOperation);
 simple_token!(Identifier, ASTKind::Identifier);
 simple_token!(ParenthesizedArgumentsList, ASTKind::ParenthesizedArgumentsList);
 simple_token!(PropertyInsn, ASTKind::PropertyInsn);
@@ -223,21 +281,42 @@ simple_token!(CommandName, ASTKind::CommandName);
 simple_token!(CommandArguments, ASTKind::CommandArguments);
 simple_token!(Function, ASTKind::Function);
 simple_token!(Piped, ASTKind::Piped);
-simple_token!(Sequenced, ASTKind::Sequenced);
+simple_token!(Background, ASTKind::Background);
 simple_token!(Delimited, ASTKind::Delimited);
+simple_token!(And, ASTKind::And);
+simple_token!(Or, ASTKind::Or);
+simple_token!(DoubleAmpersand, ASTKind::DoubleAmpersand);
+simple_token!(DoublePipe, ASTKind::DoublePipe);
+simple_token!(Redirected, ASTKind::Redirected);
+simple_token!(Redirection, ASTKind::Redirection);
+simple_token!(Greater, ASTKind::Greater);
+simple_token!(DoubleGreater, ASTKind::DoubleGreater);
+simple_token!(ErrGreater, ASTKind::ErrGreater);
+simple_token!(Less, ASTKind::Less);
 simple_token!(BracedCommand, ASTKind::BracedCommand);
 simple_token!(Assignation, ASTKind::Assignation);
 simple_token!(Equals, ASTKind::Equals);
 simple_token!(VariableName, ASTKind::VariableName);
 simple_token!(Parameter, ASTKind::Parameter);
+simple_token!(EnvironmentVariable, ASTKind::EnvironmentVariable);
+simple_token!(If, ASTKind::If);
+simple_token!(Else, ASTKind::Else);
+simple_token!(Conditional, ASTKind::Conditional);
+simple_token!(While, ASTKind::While);
+simple_token!(WhileLoop, ASTKind::WhileLoop);
 
 pub trait Typed {
     fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef>;
 }
 
 impl PropertyCall {
+    // Name of the called property, `c` in `a.b.c()`
     pub fn get_property_name<'a>(&self, node: &'a PTNode<'a>) -> Option<&'a str> {
-        return node.find_child_with_kind(ASTKind::PropertyName).map(|x| x.data);
+        let insn = node.children()[0];
+        let name = insn.children().last().copied()?;
+        if name.kind != ASTKind::PropertyName { return None; }
+
+        Some(name.data)
     }
 
     pub fn get_arguments<'a>(&self, node: &'a PTNode<'a>) -> Vec<&'a PTNode<'a>> {
@@ -252,24 +331,51 @@ impl PropertyCall {
             .children()
             .iter()
             .filter(|x| { matches!(x.kind, ASTKind::StringLiteral | ASTKind::NumberLiteral | ASTKind::Identifier) })
-            .map(|x| *x)
+            .copied()
             .collect();
 
         result
     }
 
+    // Receiver the property is looked up in, `a.b` in `a.b.c()`.
+    // None for `c()` as it has no receiver and is looked up in variables and globals
     pub fn left_hand<'a>(&self, pt: &'a PTNode<'a>) -> Option<&'a PTNode<'a>> {
-        let first = pt.children().get(0).unwrap().clone();
-        if first.kind != ASTKind::PropertyName {
-            return Some(first);
+        // PropertyInsn is either `receiver . name` or a bare `name`
+        let insn = pt.children()[0];
+        if insn.children().len() == 3 {
+            return Some(insn.children()[0]);
         }
         None
     }
 }
 
+impl Conditional {
+    pub fn get_condition<'a>(&self, pt: &'a PTNode<'a>) -> &'a PTNode<'a> {
+        pt.children()[1]
+    }
+
+    pub fn get_then<'a>(&self, pt: &'a PTNode<'a>) -> &'a PTNode<'a> {
+        pt.children()[2]
+    }
+
+    pub fn get_else<'a>(&self, pt: &'a PTNode<'a>) -> Option<&'a PTNode<'a>> {
+        pt.children().get(4).copied()
+    }
+}
+
+impl WhileLoop {
+    pub fn get_condition<'a>(&self, pt: &'a PTNode<'a>) -> &'a PTNode<'a> {
+        pt.children()[1]
+    }
+
+    pub fn get_body<'a>(&self, pt: &'a PTNode<'a>) -> &'a PTNode<'a> {
+        pt.children()[2]
+    }
+}
+
 impl Identifier {
     pub fn get_value<'a>(&self, node: &'a PTNode<'a>) -> Value {
-        return Value::String(node.data.to_string());
+        Value::String(node.data.to_string())
     }
 }
 
@@ -286,6 +392,20 @@ impl ASTError {
     pub fn new_artificial<T: ASTValue>(expected: T) -> Self {
         Self { expected: Box::new(expected), error: None }
     }
+
+    // Terminals the parser would accept where it failed, like `)` or `identifier`
+    pub fn expected_tokens(&self) -> Vec<String> {
+        let expected = match self.error.as_ref().map(|e| &e.error) {
+            Some(ParseError::UnrecognizedToken { expected, .. }) => expected,
+            Some(ParseError::UnrecognizedEOF { expected, .. }) => expected,
+            _ => return vec![],
+        };
+
+        // lalrpop gives them as string literals of the grammar, `"\")\""`
+        expected.iter()
+            .map(|t| t.strip_prefix('"').and_then(|t| t.strip_suffix('"')).unwrap_or(t).replace("\\\"", "\""))
+            .collect()
+    }
 }
 
 impl ASTValue for ASTError {
@@ -298,16 +418,19 @@ pub fn downcast_to_typed<'a>(pt: &'a PTNode) -> Option<&'a dyn Typed> {
     match pt.kind {
         ASTKind::StringLiteral => Some(pt.value::<StringLiteral>()),
         ASTKind::NumberLiteral => Some(pt.value::<NumberLiteral>()),
+        ASTKind::BoolLiteral => Some(pt.value::<BoolLiteral>()),
+        ASTKind::ListLiteral => Some(pt.value::<ListLiteral>()),
         ASTKind::Function => Some(pt.value::<Function>()),
         ASTKind::PropertyCall => Some(pt.value::<PropertyCall>()),
         ASTKind::Delimited => Some(pt.value::<Delimited>()),
         ASTKind::Piped => Some(pt.value::<Piped>()),
-        ASTKind::Sequenced => Some(pt.value::<Sequenced>()),
+        ASTKind::Background => Some(pt.value::<Background>()),
         ASTKind::BracedCommand => Some(pt.value::<BracedCommand>()),
         ASTKind::Command => Some(pt.value::<Command>()),
         ASTKind::PropertyInsn => Some(pt.value::<PropertyInsn>()),
         ASTKind::PropertyName => Some(pt.value::<PropertyName>()),
         ASTKind::Parameter => Some(pt.value::<Parameter>()),
+        ASTKind::EnvironmentVariable => Some(pt.value::<EnvironmentVariable>()),
         _ => None,
     }
 }
@@ -318,37 +441,80 @@ impl Typed for Parameter {
     }
 }
 
+impl Redirection {
+    pub fn get_target<'a>(&self, pt: &'a PTNode<'a>) -> &'a str {
+        let target = pt.children()[1];
+        match target.kind {
+            ASTKind::StringLiteral => target.value::<StringLiteral>().get_value(target),
+            _ => target.data,
+        }
+    }
+}
+
+impl StringLiteral {
+    // Content between quotes. Closing quote may be absent in unfinished input
+    pub fn get_value<'a>(&self, pt: &'a PTNode<'a>) -> &'a str {
+        let quote = &pt.data[..1];
+        if pt.data.ends_with(quote) && pt.data.len() > 1 {
+            &pt.data[1..pt.data.len() - 1]
+        } else {
+            &pt.data[1..]
+        }
+    }
+}
+
 impl Typed for StringLiteral {
     fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef> {
-        let result = if pt.data.ends_with("\"") && pt.data.len() > 1 {
-            (&pt.data[1..pt.data.len() - 1]).to_string()
-        } else {
-            (&pt.data[1..]).to_string()
-        };
+        let result = self.get_value(pt).to_string();
 
+        Some(Value::String(result).into_entity())
+    }
+}
 
-        return
-            Some(Value::String(result).into_entity());
+impl Typed for EnvironmentVariable {
+    fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef> {
+        let value = std::env::var(pt.data).unwrap_or_default();
+
+        Some(Value::String(value).into_entity())
     }
 }
 
 impl Typed for NumberLiteral {
     fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef> {
-        Some(pt.data.parse::<f64>()
-            .map_err(|e| e.to_string())
+        f64::from_str(pt.data)
+            .ok()
             .map(|x| Value::Number(x).into_entity())
-            .unwrap()
-        )
     }
 }
 
-impl Typed for Function {
+impl Typed for BoolLiteral {
+    fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef> {
+        Some(Value::Bool(pt.data == "true").into_entity())
+    }
+}
+
+impl ListLiteral {
+    pub fn get_elements<'a>(&self, pt: &'a PTNode<'a>) -> Vec<&'a PTNode<'a>> {
+        pt.children().iter()
+            .filter(|c| !matches!(c.kind, ASTKind::OpenBracket | ASTKind::CloseBracket))
+            .copied()
+            .collect()
+    }
+}
+
+impl Typed for ListLiteral {
     fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef> {
-        let v = downcast_to_typed(pt.children()[1])
-            .map(|x| x.infer_value(pt.children()[1]));
+        let elements = self.get_elements(pt).into_iter()
+            .map(|e| downcast_to_typed(e)?.infer_value(e))
+            .collect::<Option<Vec<_>>>()?;
 
-        if v.is_none() { return None; }
-        return None;
+        Some(Value::List(elements).into_entity())
+    }
+}
+
+impl Typed for Function {
+    fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef> {
+        downcast_to_typed(pt.children()[1])?.infer_value(pt.children()[1])
     }
 }
 
@@ -356,13 +522,10 @@ impl Typed for PropertyCall {
     fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef> {
         let left = pt.children()[0];
 
-        let left = downcast_to_typed(left).unwrap().infer_value(left);
-        if left.is_none() { return None; }
-        let left = left.unwrap();
+        let left = downcast_to_typed(left).unwrap().infer_value(left)?;
 
         if let Some(callee) = left.borrow().callee() {
-            if callee.result_prototype.is_none() { return None; }
-            let result_prototype = callee.result_prototype.as_ref().unwrap();
+            let result_prototype = callee.result_prototype.as_ref()?;
             let right = pt.children()[1];
             let values: Vec<Option<EntityRef>> = right.children().iter()
                 .filter(|a| downcast_to_typed(a).is_some())
@@ -388,9 +551,12 @@ impl Typed for PropertyCall {
 impl Typed for PropertyName {
     fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef> {
         let name = pt.data;
+        if let Some(variable) = entities().get_variable(name) {
+            return Some(variable);
+        }
         let global = entities().global();
-        let property = global.borrow().properties().get(name).map(|a| a.clone());
-        return property;
+        let property = global.borrow().properties().get(name).cloned();
+        property
     }
 }
 
@@ -400,15 +566,15 @@ impl Typed for PropertyInsn {
 
         let left = downcast_to_typed(left).unwrap().infer_value(left);
         if pt.children().len() == 1 { return left; }
-        if left.is_none() { return None; }
-        let left = left.unwrap();
+        let left = left?;
 
 
         let right = pt.children()[2];
-        let name = right.data;
+        let property = entities().lookup_property(&left, right.data)?;
 
-        let x = left.borrow().properties().get(name).map(|x| x.clone());
-        x
+        // value of a getter is known only after execution
+        let is_getter = property.borrow().callee().as_ref().is_some_and(|c| c.is_getter);
+        if is_getter { None } else { Some(property) }
     }
 }
 
@@ -418,64 +584,118 @@ impl Typed for BracedCommand {
     }
 }
 
+// Value of a chain is the value of its last stage, just like execution returns the last stage's result
+fn infer_last_stage<'a>(pt: &'a PTNode<'a>) -> Option<EntityRef> {
+    let last = *pt.children().iter().rev()
+        .find(|c| !matches!(c.kind, ASTKind::SemiColon | ASTKind::TrailingSemiColon | ASTKind::Ampersand | ASTKind::TrailingAmpersand | ASTKind::Pipe))?;
+    downcast_to_typed(last)?.infer_value(last)
+}
+
 impl Typed for Delimited {
-    fn infer_value<'a>(&self, _: &'a PTNode<'a>) -> Option<EntityRef> {
-        todo!()
+    fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef> {
+        infer_last_stage(pt)
     }
 }
 
-impl Typed for Sequenced {
-    fn infer_value<'a>(&self, _: &'a PTNode<'a>) -> Option<EntityRef> {
-        todo!()
+impl Typed for Background {
+    fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef> {
+        infer_last_stage(pt)
     }
 }
 
 impl Typed for Piped {
-    fn infer_value<'a>(&self, _: &'a PTNode<'a>) -> Option<EntityRef> {
-        todo!()
+    fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef> {
+        infer_last_stage(pt)
+    }
+}
+
+impl Command {
+    pub fn get_name<'a>(&self, pt: &'a PTNode<'a>) -> &'a str {
+        pt.children().first().unwrap().data
+    }
+
+    pub fn get_arguments<'a>(&self, pt: &'a PTNode<'a>) -> Vec<Cow<'a, str>> {
+        let args = pt.children().get(1).copied();
+        match args {
+            Some(args) if !args.data.is_empty() => {
+                // lexer skips whitespace, but never let blank literals become argv entries
+                args.children().iter()
+                    .filter(|x| x.kind != ASTKind::Literal || !x.data.trim().is_empty())
+                    .map(|x| {
+                        if x.kind == ASTKind::StringLiteral {
+                            Cow::Borrowed(x.value::<StringLiteral>().get_value(x))
+                        } else if x.data.starts_with('~') {
+                            // quoted or escaped tilde stays as is like in sh
+                            Cow::Owned(expand_tilde(&unescape(x.data)).into_owned())
+                        } else {
+                            unescape(x.data)
+                        }
+                    }).collect()
+            }
+            _ => vec![]
+        }
+    }
+}
+
+// `\x` stands for x. Backslash at the very end has nothing to escape and is kept
+fn unescape(literal: &str) -> Cow<'_, str> {
+    if !literal.contains('\\') {
+        return Cow::Borrowed(literal);
+    }
+
+    let mut result = String::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            result.push(chars.next().unwrap_or('\\'));
+        } else {
+            result.push(c);
+        }
     }
+
+    Cow::Owned(result)
 }
 
 impl Typed for Command {
     fn infer_value<'a>(&self, pt: &'a PTNode<'a>) -> Option<EntityRef> {
-        let children = pt.children();
-        let name = children.get(0).unwrap().data.to_owned();
-        let args = children.get(1).unwrap();
-        let args = if args.data.len() > 0 {
-            args.children().iter().map(|x| x.data.to_owned()).collect()
-        } else {
-            vec![]
-        };
+        let name = self.get_name(pt).to_owned();
+        let args: Vec<String> = self.get_arguments(pt).into_iter().map(|x| x.into_owned()).collect();
         let entity = entities().make_entity(format!("{} {:?}", name, args));
         let node_id = pt.id();
         let entity = entity.with_callee(
-            Callee::new(move |_me, parameters, config| {
+            Callee::new(move |_me, _parameters, config| {
                 let mut command = std::process::Command::new(name.clone());
-                fn redir(old: RawFd, new: Option<File>) -> Result<(), Error> {
-                    if let Some(new) = new {
-                        dup2(old, new.as_raw_fd()).map_err(|e| {
-                            Error::new(ErrorKind::Other, format!("dup2 failed: {}", e))
-                        })?;
-                    }
-
-                    Ok(())
-                }
                 command.args(args.clone());
 
+                // Stdio takes ownership of the fds, config gives away its own copies, so each fd is closed exactly once
                 let config = config;
-                if config.std_out.is_some() {
-                    command.stdout(Stdio::from(config.std_out.unwrap()));
+                if let Some(fd) = config.std_out {
+                    command.stdout(Stdio::from(fd));
+                }
+                if let Some(fd) = config.std_err {
+                    command.stderr(Stdio::from(fd));
                 }
-                if config.std_err.is_some() {
-                    command.stderr(Stdio::from(config.std_err.unwrap()));
+                if let Some(fd) = config.std_in {
+                    command.stdin(Stdio::from(fd));
+                } else if config.null_stdin {
+                    command.stdin(Stdio::null());
                 }
-                if config.std_in.is_some() {
-                    command.stdin(Stdio::from(config.std_in.unwrap()));
+                // own process group, or the one of the pipeline it belongs to, so Ctrl+C on the terminal
+                // interrupts the child but not the shell. ProcessExecution makes it the foreground group while waiting
+                let pgid = config.pgid.unwrap_or(Pid::from_raw(0));
+                unsafe {
+                    command.pre_exec(move || {
+                        setpgid(Pid::from_raw(0), pgid)
+                            .map_err(|e| Error::other(format!("setpgid failed: {}", e)))
+                    });
                 }
 
                 match command.spawn() {
                     Ok(child) => {
-                        Ok(Execution::Process(ProcessExecution::new(child, node_id)))
+                        // same in the parent, otherwise we may try to hand the terminal over before the child did it
+                        let pid = Pid::from_raw(child.id() as i32);
+                        let _ = setpgid(pid, config.pgid.unwrap_or(pid));
+                        Ok(Execution::Process(ProcessExecution::new(child, node_id).with_deadline(config.deadline)))
                     }
                     Err(e) => {
                         let mut err = EntityExecutionError::new();
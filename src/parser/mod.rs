@@ -1,5 +1,7 @@
 pub mod ast;
 mod tokenizer;
+// generated by lalrpop from cmd.lalrpop, see build.rs
+#[allow(clippy::all, unused)]
 mod cmd;
 
 #[cfg(not(test))]
@@ -9,61 +11,75 @@ pub use cmd::*;
 
 use crate::parser::ast::{ASTKind, ASTNode};
 use crate::parser::tokenizer::Tokenizer;
+#[cfg(test)]
+use crate::parser::tokenizer::Spanned;
+
+pub type ParseResult = Result<ASTNode, lalrpop_util::ParseError<usize, ASTKind, (usize, usize)>>;
 
+// lets tests run any of the generated parsers
+#[cfg(test)]
 trait ParserAdapter {
-    fn parse(&self, cmd: &str) -> Result<ASTNode, lalrpop_util::ParseError<usize, ASTKind, (usize, usize)>>;
+    fn parse(&self, cmd: &str) -> ParseResult;
 
-    #[cfg(test)]
-    fn parse_with_tokens(&self, tokens: Vec<Result<(usize, ASTKind, usize), (usize, usize)>>) -> Result<ASTNode, lalrpop_util::ParseError<usize, ASTKind, (usize, usize)>>;
+    fn parse_with_tokens(&self, tokens: Vec<Spanned<ASTKind, usize, (usize, usize)>>) -> ParseResult;
 }
 
+#[cfg(test)]
 macro_rules! impl_adapter {
     ($t:ty) => {
         impl ParserAdapter for $t {
-            fn parse(&self, cmd: &str) -> Result<ASTNode, lalrpop_util::ParseError<usize, ASTKind, (usize, usize)>> {
+            fn parse(&self, cmd: &str) -> ParseResult {
                 let tokens = tokenizer::Tokenizer::new(cmd);
                 return self.parse(tokens);
             }
 
-            #[cfg(test)]
-            fn parse_with_tokens(&self, tokens: Vec<Result<(usize, ASTKind, usize), (usize, usize)>>) -> Result<ASTNode, lalrpop_util::ParseError<usize, ASTKind, (usize, usize)>> {
+            fn parse_with_tokens(&self, tokens: Vec<Spanned<ASTKind, usize, (usize, usize)>>) -> ParseResult {
                 return self.parse(tokens.into_iter());
             }
         }
     };
 }
 
+#[cfg(test)]
 impl_adapter!(DelimitedParser);
+#[cfg(test)]
 impl_adapter!(PropertyCallNodeParser);
+#[cfg(test)]
 impl_adapter!(ValueParser);
 
 
-pub fn parse(data: &str) -> Result<ASTNode, lalrpop_util::ParseError<usize, ASTKind, (usize, usize)>> {
+// lalrpop parsers are empty structs with static tables, so building one per call costs nothing
+pub fn parse(data: &str) -> ParseResult {
     let parser = DelimitedParser::new();
-    return parser.parse(Tokenizer::new(data));
+    parser.parse(Tokenizer::new(data))
 }
 
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use std::fmt::{Debug, format};
     use crate::parser::tokenizer::tests::*;
-    use logos::{Logos, Source};
     use rand::rngs::SmallRng;
     use rand::{Rng, SeedableRng};
     use rand::seq::IteratorRandom;
     use crate::builtin::engine::parse_tree::ParseTree;
-    use crate::parser::ast::{ASTNode, Span};
+    use crate::parser::ast::ASTNode;
     use crate::parser::ast::*;
     use crate::parser::cmd::DelimitedParser;
-    use crate::parser::{ParserAdapter, PropertyInsnNodeParser};
-    use crate::parser::tokenizer::tests::tokenize;
+    use crate::parser::ParserAdapter;
     use crate::parser::tokenizer::Tokenizer;
+    use std::str::FromStr;
+    use crate::builtin::engine::entities::FoshEntity;
 
-    static TERMINALS : [ASTKind;15] = [
+    static TERMINALS : [ASTKind;22] = [
         ASTKind::Ampersand,
         ASTKind::Pipe,
+        ASTKind::DoubleAmpersand,
+        ASTKind::DoublePipe,
+        ASTKind::Greater,
+        ASTKind::DoubleGreater,
+        ASTKind::ErrGreater,
+        ASTKind::Less,
         ASTKind::SemiColon,
         ASTKind::Dollar,
         ASTKind::OpenParen,
@@ -76,13 +92,14 @@ pub mod tests {
         ASTKind::Comma,
         ASTKind::Literal,
         ASTKind::Identifier,
-        ASTKind::DoubleQuote
+        ASTKind::DoubleQuote,
+        ASTKind::SingleQuote
     ];
 
-    pub fn build_pt_def(data: &str) -> ParseTree {
+    pub fn build_pt_def(data: &str) -> ParseTree<'_> {
         ParseTree::new(data, build_ast(DelimitedParser::new(), data))
     }
-    fn build_pt<T: ParserAdapter>(adapter: T, data: &str) -> ParseTree {
+    fn build_pt<T: ParserAdapter>(adapter: T, data: &str) -> ParseTree<'_> {
         ParseTree::new(data, build_ast(adapter, data))
     }
     fn build_ast<T: ParserAdapter>(adapter: T, data: &str) -> ASTNode {
@@ -95,7 +112,7 @@ pub mod tests {
         validate_ast_error(data, ast);
     }
 
-    fn validate_ast_error(data: &str, ast: Result<ASTNode, lalrpop_util::ParseError<usize, ASTKind, (usize, usize)>>) {
+    fn validate_ast_error(data: &str, ast: ParseResult) {
         assert!(ast.is_ok(), "Parsing failed: {}\n Error: {:?}", data, ast);
 
         println!("{:?}", ast.as_ref()
@@ -106,7 +123,7 @@ pub mod tests {
         assert!(error.is_some(), "No error found in parse tree");
 
     }
-    fn validate_ast(data: &str, ast: Result<ASTNode, lalrpop_util::ParseError<usize, ASTKind, (usize, usize)>>) {
+    fn validate_ast(data: &str, ast: ParseResult) {
         assert!(ast.is_ok(), "Parsing failed: {}\n Error: {:?}", data, ast);
 
         let ast_str = format!("{:?}", ast.as_ref().unwrap());
@@ -127,7 +144,7 @@ pub mod tests {
     {
         let data = format!("{:?}", tokens);
         let tokens = tokens.into_iter().map(|x| Ok((0usize, x, 0usize)))
-            .collect::<Vec<Result<(usize, ASTKind, usize), (usize, usize)>>>();
+            .collect::<Vec<Spanned<ASTKind, usize, (usize, usize)>>>();
         let ast = adapter.parse_with_tokens(tokens);
         validate_ast(&data, ast);
     }
@@ -135,7 +152,7 @@ pub mod tests {
     fn assert_parsed_with_errors_special<T: ParserAdapter>(adapter: T, tokens: Vec<ASTKind>) {
         let data = format!("{:?}", tokens);
         let tokens = tokens.into_iter().map(|x| Ok((0usize, x, 0usize)))
-            .collect::<Vec<Result<(usize, ASTKind, usize), (usize, usize)>>>();
+            .collect::<Vec<Spanned<ASTKind, usize, (usize, usize)>>>();
         let ast = adapter.parse_with_tokens(tokens);
         validate_ast_error(&data, ast);
     }
@@ -158,7 +175,7 @@ pub mod tests {
         let iterations = rng.gen_range(50u32..100);
 
         for _ in 0..iterations {
-            let mut tokens: Vec<Result<(usize, ASTKind, usize), (usize, usize)>> = Vec::new();
+            let mut tokens: Vec<Spanned<ASTKind, usize, (usize, usize)>> = Vec::new();
             for i in 0..rng.gen_range(1usize..10) {
                 let token = terminals.iter().choose(&mut rng).unwrap();
                 tokens.push(Ok((i, *token, i+1)));
@@ -166,7 +183,7 @@ pub mod tests {
 
             let error_msg = &format!("Parsing failed.\nTokens: {:?}", tokens);
             DelimitedParser::new()
-                .parse(tokens.into_iter())
+                .parse(tokens)
                 .expect(error_msg);
 
         }
@@ -177,7 +194,7 @@ pub mod tests {
 
     #[test]
     fn test_leftmost_recursion() {
-        let pt = build_pt(DelimitedParser::new(), "$kek.lol.arbidol");
+        let pt = build_pt(DelimitedParser::new(), "$kek.lol.arbidol()");
 
         let node = pt.root().find_child_with_kind_rec(ASTKind::PropertyCall).unwrap();
         let call: &PropertyCall = node.value();
@@ -187,6 +204,23 @@ pub mod tests {
 
     }
 
+    #[test]
+    fn test_left_hand_of_chains() {
+        let cases = [
+            ("$kek()", None, "kek"),
+            ("$kek.lol()", Some("kek"), "lol"),
+            (r#"$"a".kek.lol("b")"#, Some(r#""a".kek"#), "lol"),
+        ];
+        for (line, receiver, name) in cases {
+            let pt = build_pt(DelimitedParser::new(), line);
+            let node = pt.root().find_child_with_kind_rec(ASTKind::PropertyCall).unwrap();
+            let call: &PropertyCall = node.value();
+
+            assert_eq!(call.left_hand(node).map(|x| x.text()), receiver, "{}", line);
+            assert_eq!(call.get_property_name(node), Some(name), "{}", line);
+        }
+    }
+
 
     #[test]
     fn test_parse_empty() {
@@ -204,10 +238,7 @@ pub mod tests {
             PropertyCallNodeParser::new(),
             tokenize_function_level("kek()")
         );
-        assert_parsed_special(
-            PropertyCallNodeParser::new(),
-            tokenize_function_level("kek")
-        );
+        // without arguments list it's a property access, see test_value
         assert_parsed_with_errors_special(
             PropertyCallNodeParser::new(),
             tokenize_function_level("kek(")
@@ -228,6 +259,10 @@ pub mod tests {
             ValueParser::new(),
             tokenize_function_level(r#" {lol} "#)
         );
+        assert_parsed_special(
+            ValueParser::new(),
+            tokenize_function_level("kek")
+        );
         assert_parsed_special(
             ValueParser::new(),
             tokenize_function_level(r#" {lol}.kek() "#)
@@ -245,6 +280,48 @@ pub mod tests {
         assert_parsed(r#"5.0"#);
     }
 
+    #[test]
+    fn test_parse_empty_string() {
+        assert_parsed(r#"$cd("")"#);
+
+        let pt = build_pt_def(r#"$"""#);
+        let literal = pt.root().find_child_with_kind_rec(ASTKind::StringLiteral).unwrap();
+        assert_eq!(literal.data, r#""""#);
+        assert!(literal.children().is_empty());
+    }
+
+    #[test]
+    fn test_parse_arithmetic_precedence() {
+        let pt = build_pt_def("$(1 + 2 * 3 - 4)");
+
+        // ((1 + (2 * 3)) - 4)
+        let root = pt.root().find_child_with_kind_rec(ASTKind::BinaryOperation).unwrap();
+        assert_eq!(root.children()[1].kind, ASTKind::Minus);
+        assert_eq!(root.children()[2].data, "4");
+
+        let sum = root.children()[0];
+        assert_eq!(sum.children()[1].kind, ASTKind::Plus);
+        assert_eq!(sum.children()[2].kind, ASTKind::BinaryOperation);
+        assert_eq!(sum.children()[2].data, "2 * 3");
+
+        assert_parsed("$((1 + 2) * 3)");
+        assert_parsed("$(x.y % foo(1))");
+    }
+
+    #[test]
+    fn test_parse_number_literals() {
+        crate::builtin::annotator::tests::init_entities();
+        for literal in ["-5", "3e10", "-1.5e-3", ".5", "-.25"] {
+            let line = format!("$foo({})", literal);
+            let pt = build_pt_def(&line);
+
+            let node = pt.root().find_child_with_kind_rec(ASTKind::NumberLiteral).unwrap();
+            assert_eq!(node.data, literal);
+            let value = node.value::<NumberLiteral>().infer_value(node).unwrap().try_as_number();
+            assert_eq!(value, Some(f64::from_str(literal).unwrap()));
+        }
+    }
+
     #[test]
     fn test_parse_property_invocation_no_arg() {
         assert_parsed("$foo");
@@ -294,10 +371,37 @@ pub mod tests {
     }
 
     #[test]
-    fn test_parse_sequenced() {
+    fn test_background_and_delimited_kinds() {
+        let pt = build_pt_def("a & b");
+        assert_eq!(pt.root().kind, ASTKind::Background);
+
+        let pt = build_pt_def("a ; b");
+        assert_eq!(pt.root().kind, ASTKind::Delimited);
+
+        // & binds tighter than ;
+        let pt = build_pt_def("a & b ; c");
+        assert_eq!(pt.root().kind, ASTKind::Delimited);
+        assert_eq!(pt.root().children()[0].kind, ASTKind::Background);
+    }
+
+    #[test]
+    fn test_parse_background() {
         assert_parsed(r#"$lol"#);
         assert_parsed(r#"$lol & echo"#);
         assert_parsed(r#"$lol & echo & kek"#);
+        assert_parsed(r#"sleep 1 &"#);
+        assert_parsed(r#"sleep 1 & echo &"#);
+        assert_parsed(r#"sleep 1 & ; echo"#);
+    }
+
+    #[test]
+    fn test_parse_trailing_semicolon() {
+        assert_parsed(r#"echo a ;"#);
+        assert_parsed(r#"echo a ; echo b ;"#);
+        assert_parsed(r#"sleep 1 & ;"#);
+        assert_parsed(r#"${echo a ;}"#);
+        assert_parsed_with_errors(r#";"#);
+        assert_parsed_with_errors(r#"echo a ; ;"#);
     }
 
     #[test]
@@ -317,6 +421,24 @@ pub mod tests {
         assert_parsed(r#"${lol}.kek() ; lol"#);
     }
 
+    #[test]
+    fn test_parse_conditional() {
+        assert_parsed(r#"$if true { echo a }"#);
+        assert_parsed(r#"$if x.eq(1) { echo a } else { echo b }"#);
+        assert_parsed(r#"$if { test -f a } { echo a } else { echo b } | cat"#);
+
+        let pt = build_pt_def(r#"$if "" { a } else { b }"#);
+        let conditional = pt.root().find_child_with_kind_rec(ASTKind::Conditional).unwrap();
+        assert_eq!(conditional.children()[1].kind, ASTKind::StringLiteral);
+        assert_eq!(conditional.children()[4].data, "{ b }");
+    }
+
+    #[test]
+    fn test_parse_while() {
+        assert_parsed(r#"$while i { echo a ; $i = { $(i - 1) } }"#);
+        assert_parsed_with_errors(r#"$while true"#);
+    }
+
     #[test]
     fn test_assignation() {
         assert_parsed(r#"$foo = 5"#);
@@ -338,6 +460,74 @@ pub mod tests {
         assert_eq!(node.data, r#""kek""#)
     }
 
+    #[test]
+    fn parse_raw_string_literal() {
+        let pt = build_pt_def(r#"$foo('raw $ text')"#);
+
+        let node = pt.root().find_child_with_kind_rec(ASTKind::StringLiteral).unwrap();
+        assert_eq!(node.data, r#"'raw $ text'"#);
+        assert_eq!(node.value::<StringLiteral>().get_value(node), "raw $ text");
+
+        assert_parsed(r#"$foo('')"#);
+        assert_parsed(r#"$foo('"kek"' "'lol'")"#);
+    }
+
+    fn command_arguments(data: &str) -> Vec<String> {
+        let pt = build_pt_def(data);
+        let node = pt.root().find_child_with_kind_rec(ASTKind::Command).unwrap();
+
+        node.value::<Command>().get_arguments(node).into_iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn command_arguments_ignore_trailing_whitespace() {
+        assert_eq!(command_arguments("echo hi   "), vec!["hi"]);
+        assert_eq!(command_arguments("echo  a  b "), vec!["a", "b"]);
+        assert_eq!(command_arguments("echo a\t\tb\t"), vec!["a", "b"]);
+        assert_eq!(command_arguments("echo hi ; "), vec!["hi"]);
+        assert_eq!(command_arguments("echo hi | "), vec!["hi"]);
+        assert_eq!(command_arguments("echo    "), Vec::<String>::new());
+        assert_eq!(command_arguments("echo '' "), vec![""]);
+    }
+
+    #[test]
+    fn command_arguments_unescape_literals() {
+        assert_eq!(command_arguments(r"cat my\ file"), vec!["my file"]);
+        assert_eq!(command_arguments(r"echo a\\b \; 'c\ d'"), vec![r"a\b", ";", r"c\ d"]);
+        assert_eq!(command_arguments(r"echo a\"), vec![r"a\"]);
+    }
+
+    #[test]
+    fn command_arguments_expand_tilde() {
+        let home = std::env::var("HOME").unwrap();
+
+        assert_eq!(command_arguments("cat ~/notes ~"), vec![format!("{}/notes", home), home]);
+        assert_eq!(command_arguments(r"cat ~user a~/b '~/notes' \~/notes"), vec!["~user", "a~/b", "~/notes", "~/notes"]);
+    }
+
+    #[test]
+    fn parse_quoted_command_arguments() {
+        let pt = build_pt_def(r#"echo 'hello world' "kek" lol"#);
+
+        let node = pt.root().find_child_with_kind_rec(ASTKind::Command).unwrap();
+        let args = node.value::<Command>().get_arguments(node);
+        assert_eq!(args, vec!["hello world", "kek", "lol"]);
+    }
+
+    #[test]
+    fn infer_value_of_last_stage() {
+        crate::builtin::annotator::tests::init_entities();
+        let infer = |line: &str| {
+            let pt = build_pt_def(line);
+            downcast_to_typed(pt.root()).unwrap().infer_value(pt.root())
+        };
+        let global = |name: &str| crate::entities().global().borrow().properties()[name].clone();
+
+        assert!(std::rc::Rc::ptr_eq(&infer("ls ; $echo").unwrap(), &global("echo")));
+        assert!(std::rc::Rc::ptr_eq(&infer("ls | $echo").unwrap(), &global("echo")));
+        assert!(std::rc::Rc::ptr_eq(&infer("$echo ; ls | $pwd").unwrap(), &global("pwd")));
+    }
+
     #[test]
     fn just_braced_command() {
         assert_parsed(r#"${lol}"#);
@@ -1,4 +1,4 @@
-use logos::{Lexer, Logos, Span};
+use logos::{Lexer, Logos};
 use crate::parser::ast::{ASTKind};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Logos)]
@@ -9,24 +9,50 @@ pub enum TopLevelToken {
     #[token("|")]
     Pipe,
 
+    #[token("&&")]
+    DoubleAmpersand,
+
+    #[token("||")]
+    DoublePipe,
+
     #[token(";")]
     SemiColon,
 
     #[token("$")]
     Dollar,
 
-    #[regex("[^ |;&\n\t$\"}]+")]
+    // `#` inside a word is part of it, at the start of a word it begins a comment.
+    // `\x` keeps x in the word whatever it is, so `my\ file` is a single literal
+    #[regex("([^ |;&\n\t$\"'}#<>\\\\]|\\\\.?)([^ |;&\n\t$\"'}<>\\\\]|\\\\.?)*")]
     Literal,
 
+    #[token(">")]
+    Greater,
+
+    #[token(">>")]
+    DoubleGreater,
+
+    #[token("2>")]
+    ErrGreater,
+
+    #[token("<")]
+    Less,
+
     #[token("}")]
     RightBrace,
 
     #[token("\"")]
     DoubleQuote,
 
+    #[token("'")]
+    SingleQuote,
+
     #[regex("[ \n\t]+", logos::skip, priority = 1)]
     Whitespace,
 
+    #[regex("#[^\n]*", logos::skip)]
+    Comment,
+
     #[error]
     Error,
 
@@ -46,6 +72,20 @@ enum StringLevelToken {
 
 }
 
+// Single quoted strings are raw, everything until the closing quote is literal
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Logos)]
+enum RawStringLevelToken {
+    #[error]
+    Error,
+
+    #[token("'")]
+    SingleQuote,
+
+    #[regex("[^']+")]
+    Literal,
+
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Logos)]
 enum FunctionLevelToken {
     #[error]
@@ -63,27 +103,89 @@ enum FunctionLevelToken {
     #[token("}")]
     RightBrace,
 
+    #[token("[")]
+    LeftBracket,
+
+    #[token("]")]
+    RightBracket,
+
     #[token(".")]
     Dot,
 
     #[token(",")]
     Comma,
 
-    #[regex(r"[0-9]*(\.[0-9]+)?")]
+    // at least one digit is required, otherwise empty input would match. `.5` is a number too
+    #[regex(r"-?([0-9]+(\.[0-9]+)?|\.[0-9]+)([eE][-+]?[0-9]+)?")]
     Number,
 
+    #[token("+")]
+    Plus,
+
+    #[token("-")]
+    Minus,
+
+    #[token("*")]
+    Star,
+
+    #[token("/")]
+    Slash,
+
+    #[token("%")]
+    Percent,
+
+    #[token("true")]
+    True,
+
+    #[token("false")]
+    False,
+
+    #[token("if")]
+    If,
+
+    #[token("else")]
+    Else,
+
+    #[token("while")]
+    While,
+
     #[token("\"")]
     DoubleQuote,
 
+    #[token("'")]
+    SingleQuote,
+
     #[regex(r#"[a-zA-Z](\w|[_0-9])*"#)]
     Identifier,
 
+    // `$?` is the status of the last command
+    #[token("?")]
+    Status,
+
     #[token("&")]
     Ampersand,
 
     #[token("|")]
     Pipe,
 
+    #[token("&&")]
+    DoubleAmpersand,
+
+    #[token("||")]
+    DoublePipe,
+
+    #[token(">")]
+    Greater,
+
+    #[token(">>")]
+    DoubleGreater,
+
+    #[token("2>")]
+    ErrGreater,
+
+    #[token("<")]
+    Less,
+
     #[token(";")]
     SemiColon,
 
@@ -94,9 +196,9 @@ enum FunctionLevelToken {
     Whitespace,
 }
 
-impl Into<ASTKind> for StringLevelToken {
-    fn into(self) -> ASTKind {
-        match self {
+impl From<StringLevelToken> for ASTKind {
+    fn from(val: StringLevelToken) -> Self {
+        match val {
             StringLevelToken::DoubleQuote => ASTKind::DoubleQuote,
             StringLevelToken::Literal => ASTKind::Literal,
             _ => ASTKind::Error,
@@ -104,55 +206,100 @@ impl Into<ASTKind> for StringLevelToken {
     }
 }
 
-impl Into<ASTKind> for FunctionLevelToken {
-    fn into(self) -> ASTKind {
-        match self {
+impl From<RawStringLevelToken> for ASTKind {
+    fn from(val: RawStringLevelToken) -> Self {
+        match val {
+            RawStringLevelToken::SingleQuote => ASTKind::SingleQuote,
+            RawStringLevelToken::Literal => ASTKind::Literal,
+            _ => ASTKind::Error,
+        }
+    }
+}
+
+impl From<FunctionLevelToken> for ASTKind {
+    fn from(val: FunctionLevelToken) -> Self {
+        match val {
             FunctionLevelToken::LeftParen => ASTKind::OpenParen,
             FunctionLevelToken::RightParen => ASTKind::CloseParen,
             FunctionLevelToken::LeftBrace => ASTKind::OpenBrace,
             FunctionLevelToken::RightBrace => ASTKind::CloseBrace,
+            FunctionLevelToken::LeftBracket => ASTKind::OpenBracket,
+            FunctionLevelToken::RightBracket => ASTKind::CloseBracket,
             FunctionLevelToken::Dot => ASTKind::Dot,
             FunctionLevelToken::Comma => ASTKind::Comma,
             FunctionLevelToken::Number => ASTKind::NumberLiteral,
+            FunctionLevelToken::True | FunctionLevelToken::False => ASTKind::BoolLiteral,
+            FunctionLevelToken::If => ASTKind::If,
+            FunctionLevelToken::Else => ASTKind::Else,
+            FunctionLevelToken::While => ASTKind::While,
+            FunctionLevelToken::Plus => ASTKind::Plus,
+            FunctionLevelToken::Minus => ASTKind::Minus,
+            FunctionLevelToken::Star => ASTKind::Star,
+            FunctionLevelToken::Slash => ASTKind::Slash,
+            FunctionLevelToken::Percent => ASTKind::Percent,
             FunctionLevelToken::Identifier => ASTKind::Identifier,
+            FunctionLevelToken::Status => ASTKind::Identifier,
             FunctionLevelToken::Error => ASTKind::Error,
             FunctionLevelToken::Ampersand => ASTKind::Ampersand,
             FunctionLevelToken::Pipe => ASTKind::Pipe,
+            FunctionLevelToken::DoubleAmpersand => ASTKind::DoubleAmpersand,
+            FunctionLevelToken::DoublePipe => ASTKind::DoublePipe,
+            FunctionLevelToken::Greater => ASTKind::Greater,
+            FunctionLevelToken::DoubleGreater => ASTKind::DoubleGreater,
+            FunctionLevelToken::ErrGreater => ASTKind::ErrGreater,
+            FunctionLevelToken::Less => ASTKind::Less,
             FunctionLevelToken::SemiColon => ASTKind::SemiColon,
             FunctionLevelToken::DoubleQuote => ASTKind::DoubleQuote,
+            FunctionLevelToken::SingleQuote => ASTKind::SingleQuote,
             FunctionLevelToken::Equals => ASTKind::Equals,
             FunctionLevelToken::Whitespace => panic!("Whitespace should not be in the function level tokenizer"),
         }
     }
 }
 
-impl Into<ASTKind> for TopLevelToken {
-    fn into(self) -> ASTKind {
-        match self {
+impl From<TopLevelToken> for ASTKind {
+    fn from(val: TopLevelToken) -> Self {
+        match val {
             TopLevelToken::Ampersand => ASTKind::Ampersand,
             TopLevelToken::Pipe => ASTKind::Pipe,
+            TopLevelToken::DoubleAmpersand => ASTKind::DoubleAmpersand,
+            TopLevelToken::DoublePipe => ASTKind::DoublePipe,
+            TopLevelToken::Greater => ASTKind::Greater,
+            TopLevelToken::DoubleGreater => ASTKind::DoubleGreater,
+            TopLevelToken::ErrGreater => ASTKind::ErrGreater,
+            TopLevelToken::Less => ASTKind::Less,
             TopLevelToken::SemiColon => ASTKind::SemiColon,
             TopLevelToken::Dollar => ASTKind::Dollar,
             TopLevelToken::Error => ASTKind::Error,
             TopLevelToken::Literal => ASTKind::Literal,
             TopLevelToken::RightBrace => ASTKind::CloseBrace,
             TopLevelToken::DoubleQuote => ASTKind::DoubleQuote,
+            TopLevelToken::SingleQuote => ASTKind::SingleQuote,
             TopLevelToken::Whitespace => panic!("Whitespace should not be in the top level tokenizer"),
+            TopLevelToken::Comment => panic!("Comment should not be in the top level tokenizer"),
         }
     }
 }
 
 
+#[allow(clippy::enum_variant_names)]
 enum TokenizerState<'a> {
     TopLevel(Lexer<'a, TopLevelToken>),
     FunctionLevel(Lexer<'a, FunctionLevelToken>),
     StringLevel(Lexer<'a, StringLevelToken>),
+    RawStringLevel(Lexer<'a, RawStringLevelToken>),
 }
 
 pub struct Tokenizer<'a> {
     offset: usize,
     stack: Vec<TokenizerState<'a>>,
     state: TokenizerState<'a>,
+    // previous token was `$`
+    after_dollar: bool,
+    // kind and end of the previous token
+    previous: Option<(ASTKind, usize)>,
+    // second half of a token which was split in two
+    pending: Option<(usize, ASTKind, usize)>,
 }
 
 impl<'a> Tokenizer<'a> {
@@ -161,6 +308,9 @@ impl<'a> Tokenizer<'a> {
             offset: 0,
             stack: Default::default(),
             state: TokenizerState::TopLevel(TopLevelToken::lexer(s)),
+            after_dollar: false,
+            previous: None,
+            pending: None,
         }
     }
 
@@ -173,13 +323,7 @@ impl<'a> Tokenizer<'a> {
     }
 
     fn pop_state(&mut self, remainder: &'a str) {
-        let state = self.stack.pop();
-
-        if state.is_none() {
-            self.state = TokenizerState::TopLevel(TopLevelToken::lexer(remainder));
-        } else {
-            let state = state.unwrap();
-
+        if let Some(state) = self.stack.pop() {
             self.state = match state {
                 TokenizerState::TopLevel(_) => {
                     TokenizerState::TopLevel(TopLevelToken::lexer(remainder))
@@ -190,48 +334,114 @@ impl<'a> Tokenizer<'a> {
                 TokenizerState::StringLevel(_) => {
                     TokenizerState::StringLevel(StringLevelToken::lexer(remainder))
                 }
+                TokenizerState::RawStringLevel(_) => {
+                    TokenizerState::RawStringLevel(RawStringLevelToken::lexer(remainder))
+                }
             }
+        } else {
+            self.state = TokenizerState::TopLevel(TopLevelToken::lexer(remainder));
         }
     }
 }
 
+fn is_env_variable_name(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+        && name.chars().any(|c| c.is_ascii_uppercase())
+}
+
+// nothing but the end of the line, block or statement follows
+fn is_trailing(remainder: &str) -> bool {
+    let remainder = remainder.trim_start();
+    remainder.is_empty() || remainder.starts_with(';') || remainder.starts_with('}')
+}
+
+// unlike &, `a ; ;` is not two delimited commands, so only the end of the line or block counts
+fn is_trailing_semicolon(remainder: &str) -> bool {
+    let remainder = remainder.trim_start();
+    remainder.is_empty() || remainder.starts_with('}')
+}
+
 pub type Spanned<Tok, Loc, Error> = Result<(Loc, Tok, Loc), Error>;
 
 impl<'a> Iterator for Tokenizer<'a> {
     type Item = Spanned<ASTKind, usize, (usize, usize)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let (token, span) = match self.state {
+        if let Some((start, token, end)) = self.pending.take() {
+            self.previous = Some((token, end));
+            return Some(Ok((start, token, end)));
+        }
+
+        let (token, span, text) = match self.state {
             TokenizerState::TopLevel(ref mut lexer) => {
-                (lexer.next().map(|token| token.into()), lexer.span())
+                {
+                    let token = lexer.next().map(|token| token.into());
+                    (token, lexer.span(), lexer.slice())
+                }
             }
             TokenizerState::FunctionLevel(ref mut lexer) => {
-                (lexer.next().map(|token| token.into()), lexer.span())
+                {
+                    let token = lexer.next().map(|token| token.into());
+                    (token, lexer.span(), lexer.slice())
+                }
             }
             TokenizerState::StringLevel(ref mut lexer) => {
-                (lexer.next().map(|token| token.into()), lexer.span())
+                {
+                    let token = lexer.next().map(|token| token.into());
+                    (token, lexer.span(), lexer.slice())
+                }
+            }
+            TokenizerState::RawStringLevel(ref mut lexer) => {
+                {
+                    let token = lexer.next().map(|token| token.into());
+                    (token, lexer.span(), lexer.slice())
+                }
             }
         };
 
+        // `$NAME` in upper case refers to environment variable rather than a property
+        let token = match token {
+            Some(ASTKind::Identifier) if self.after_dollar && is_env_variable_name(text) => {
+                Some(ASTKind::EnvironmentVariable)
+            }
+            t => t,
+        };
+        self.after_dollar = matches!(token, Some(ASTKind::Dollar));
 
         let slice = match &self.state {
             TokenizerState::TopLevel(l) => l.remainder(),
             TokenizerState::FunctionLevel(l) => l.remainder(),
             TokenizerState::StringLevel(l) => l.remainder(),
+            TokenizerState::RawStringLevel(l) => l.remainder(),
+        };
+
+        // `cmd &` and `cmd ;` at the end of a line. The grammar can't tell them from a missing command
+        // after the delimiter because of error recovery, so it is decided here by looking ahead
+        let token = match token {
+            Some(ASTKind::Ampersand) if is_trailing(slice) => Some(ASTKind::TrailingAmpersand),
+            Some(ASTKind::SemiColon) if is_trailing_semicolon(slice) => Some(ASTKind::TrailingSemiColon),
+            t => t,
         };
 
         let span = span.start + self.offset..span.end + self.offset;
 
+        // `2-3` is a subtraction, while `2 -3` stays two numbers as arguments are separated by spaces
+        let is_subtraction = matches!(token, Some(ASTKind::NumberLiteral)) && text.starts_with('-')
+            && matches!(self.previous, Some((ASTKind::NumberLiteral | ASTKind::Identifier | ASTKind::CloseParen, end)) if end == span.start);
+        self.previous = token.map(|t| (t, span.end));
+
         if matches!(token, Some(ASTKind::Dollar)) {
             self.offset = span.end;
             self.state = TokenizerState::FunctionLevel(FunctionLevelToken::lexer(slice));
         }
 
-        if matches!(token, Some(ASTKind::SemiColon) | Some(ASTKind::Pipe) | Some(ASTKind::Ampersand)) {
-            if matches!(self.state, TokenizerState::FunctionLevel(_)) {
-                self.offset = span.end;
-                self.state = TokenizerState::TopLevel(TopLevelToken::lexer(slice));
-            }
+        if matches!(token, Some(ASTKind::SemiColon) | Some(ASTKind::TrailingSemiColon) | Some(ASTKind::Pipe) | Some(ASTKind::Ampersand) | Some(ASTKind::TrailingAmpersand)
+            | Some(ASTKind::DoubleAmpersand) | Some(ASTKind::DoublePipe)
+            | Some(ASTKind::Greater) | Some(ASTKind::DoubleGreater) | Some(ASTKind::ErrGreater)
+            | Some(ASTKind::Less))
+            && matches!(self.state, TokenizerState::FunctionLevel(_)) {
+            self.offset = span.end;
+            self.state = TokenizerState::TopLevel(TopLevelToken::lexer(slice));
         }
 
         if matches!(token, Some(ASTKind::DoubleQuote)) {
@@ -243,6 +453,15 @@ impl<'a> Iterator for Tokenizer<'a> {
             }
         }
 
+        if matches!(token, Some(ASTKind::SingleQuote)) {
+            self.offset = span.end;
+            if matches!(&self.state, TokenizerState::RawStringLevel(_)) {
+                self.pop_state(slice)
+            } else {
+                self.push_state(TokenizerState::RawStringLevel(RawStringLevelToken::lexer(slice)));
+            }
+        }
+
         if matches!(token, Some(ASTKind::CloseBrace)) {
             self.offset = span.end;
             self.pop_state(slice)
@@ -254,6 +473,10 @@ impl<'a> Iterator for Tokenizer<'a> {
         }
 
         match token {
+            Some(v) if is_subtraction => {
+                self.pending = Some((span.start + 1, v, span.end));
+                Some(Ok((span.start, ASTKind::Minus, span.start + 1)))
+            }
             Some(v) => Some(Ok((span.start, v, span.end))),
             None => None
         }
@@ -262,12 +485,11 @@ impl<'a> Iterator for Tokenizer<'a> {
 
 #[cfg(test)]
 pub mod tests {
-    use std::any::type_name;
     use std::fmt::Debug;
     pub use super::*;
 
 
-    pub fn tokenize<'a, T: Logos<'a, Source=str, Extras=()>>(s: &'a str) -> Vec<ASTKind>
+    pub fn tokenize<'a, T>(s: &'a str) -> Vec<ASTKind>
         where T: Logos<'a, Source=str, Extras=()>, T: Into<ASTKind>, T: Debug
     {
         let lexer = T::lexer(s);
@@ -289,23 +511,15 @@ pub mod tests {
         tokenizer.map(|e| e.unwrap().1).collect()
     }
 
-    pub fn tokenize_string_level(s: &str) -> Vec<ASTKind> {
-        let mut tokenizer = Tokenizer::new(s);
-        tokenizer.state = TokenizerState::StringLevel(StringLevelToken::lexer(s));
-
-        tokenizer.map(|e| e.unwrap().1).collect()
-    }
-
-    fn token<'a, T: Logos<'a, Source=str, Extras=()>>(s: &'a str) -> ASTKind
+    fn token<'a, T>(s: &'a str) -> ASTKind
         where T: Logos<'a, Source=str, Extras=()>, T: Into<ASTKind>, T: Debug
     {
         let tokens: Vec<ASTKind> = tokenize::<'a, T>(s);
         assert_eq!(tokens.len(), 1, "Expected one token, got {:?}", &tokens);
-        let token = tokens.into_iter().next().unwrap();
-        token.into()
+        tokens.into_iter().next().unwrap()
     }
 
-    fn expect_tokens<'a, T: Logos<'a, Source=str, Extras=()>>(s: &'a str, expected: &[ASTKind])
+    fn expect_tokens<'a, T>(s: &'a str, expected: &[ASTKind])
         where T: Logos<'a, Source=str, Extras=()>, T: Into<ASTKind>, T: Debug
     {
         let tokens: Vec<ASTKind> = tokenize::<'a, T>(s);
@@ -373,6 +587,14 @@ pub mod tests {
         ]);
     }
 
+    #[test]
+    fn test_escaped_literal() {
+        expect_tokens_full(r"a\ b", &[ASTKind::Literal]);
+        expect_tokens_full(r"cat my\ file\;x \#kek", &[ASTKind::Literal, ASTKind::Literal, ASTKind::Literal]);
+        expect_tokens_full(r"echo a\", &[ASTKind::Literal, ASTKind::Literal]);
+        expect_tokens_full(r"echo \", &[ASTKind::Literal, ASTKind::Literal]);
+    }
+
 
     fn expect_tokens_full(s: &str, expected: &[ASTKind]) {
         let tokenizer = Tokenizer::new(s);
@@ -403,13 +625,37 @@ pub mod tests {
         let tokens = tokenizer.collect::<Result<Vec<_>, _>>().unwrap();
         let expected = vec![
             (0, ASTKind::Literal, 4),
-            (5, ASTKind::Literal, 11),
-            (12, ASTKind::Literal, 18),
+            (5, ASTKind::SingleQuote, 6),
+            (6, ASTKind::Literal, 17),
+            (17, ASTKind::SingleQuote, 18),
         ];
 
         assert_eq!(tokens, expected);
     }
 
+    #[test]
+    fn test_comment() {
+        let tokenizer = Tokenizer::new("echo hi # comment | kek");
+        let tokens = tokenizer.collect::<Result<Vec<_>, _>>().unwrap();
+        let expected = vec![
+            (0, ASTKind::Literal, 4),
+            (5, ASTKind::Literal, 7),
+        ];
+        assert_eq!(tokens, expected);
+
+        expect_tokens_full("#comment\necho", &[ASTKind::Literal]);
+        expect_tokens_full("echo a#b", &[ASTKind::Literal, ASTKind::Literal]);
+        expect_tokens_full(r##"$foo("#")"##, &[
+            ASTKind::Dollar,
+            ASTKind::Identifier,
+            ASTKind::OpenParen,
+            ASTKind::DoubleQuote,
+            ASTKind::Literal,
+            ASTKind::DoubleQuote,
+            ASTKind::CloseParen,
+        ]);
+    }
+
     #[test]
     fn test_string_tokenization() {
         let tokenizer = Tokenizer::new(r#""fd d""#);
@@ -424,6 +670,127 @@ pub mod tests {
         assert_eq!(tokens, expected);
     }
 
+    #[test]
+    fn test_raw_string_tokenization() {
+        let tokenizer = Tokenizer::new(r#"$foo('raw $ "text')"#);
+        let tokens = tokenizer.collect::<Result<Vec<_>, _>>().unwrap();
+
+        let expected = vec![
+            (0, ASTKind::Dollar, 1),
+            (1, ASTKind::Identifier, 4),
+            (4, ASTKind::OpenParen, 5),
+            (5, ASTKind::SingleQuote, 6),
+            (6, ASTKind::Literal, 17),
+            (17, ASTKind::SingleQuote, 18),
+            (18, ASTKind::CloseParen, 19),
+        ];
+
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        expect_tokens_full("a && b || c", &[
+            ASTKind::Literal,
+            ASTKind::DoubleAmpersand,
+            ASTKind::Literal,
+            ASTKind::DoublePipe,
+            ASTKind::Literal
+        ]);
+        expect_tokens_full("$pwd()&&b|c", &[
+            ASTKind::Dollar,
+            ASTKind::Identifier,
+            ASTKind::OpenParen,
+            ASTKind::CloseParen,
+            ASTKind::DoubleAmpersand,
+            ASTKind::Literal,
+            ASTKind::Pipe,
+            ASTKind::Literal
+        ]);
+    }
+
+    #[test]
+    fn test_trailing_ampersand() {
+        expect_tokens_full("a & b &", &[
+            ASTKind::Literal,
+            ASTKind::Ampersand,
+            ASTKind::Literal,
+            ASTKind::TrailingAmpersand
+        ]);
+        expect_tokens_full("${a &} & ; b", &[
+            ASTKind::Dollar,
+            ASTKind::OpenBrace,
+            ASTKind::Literal,
+            ASTKind::TrailingAmpersand,
+            ASTKind::CloseBrace,
+            ASTKind::TrailingAmpersand,
+            ASTKind::SemiColon,
+            ASTKind::Literal
+        ]);
+    }
+
+    #[test]
+    fn test_trailing_semicolon() {
+        expect_tokens_full("a ; b ;", &[
+            ASTKind::Literal,
+            ASTKind::SemiColon,
+            ASTKind::Literal,
+            ASTKind::TrailingSemiColon
+        ]);
+        expect_tokens_full("${a ;} ; ;", &[
+            ASTKind::Dollar,
+            ASTKind::OpenBrace,
+            ASTKind::Literal,
+            ASTKind::TrailingSemiColon,
+            ASTKind::CloseBrace,
+            ASTKind::SemiColon,
+            ASTKind::TrailingSemiColon
+        ]);
+    }
+
+    #[test]
+    fn test_redirections() {
+        expect_tokens_full("cat<in >out 2> err >> log", &[
+            ASTKind::Literal,
+            ASTKind::Less,
+            ASTKind::Literal,
+            ASTKind::Greater,
+            ASTKind::Literal,
+            ASTKind::ErrGreater,
+            ASTKind::Literal,
+            ASTKind::DoubleGreater,
+            ASTKind::Literal
+        ]);
+        expect_tokens_full("$pwd() > out", &[
+            ASTKind::Dollar,
+            ASTKind::Identifier,
+            ASTKind::OpenParen,
+            ASTKind::CloseParen,
+            ASTKind::Greater,
+            ASTKind::Literal
+        ]);
+    }
+
+    #[test]
+    fn test_env_variable() {
+        expect_tokens_full("$PATH", &[ASTKind::Dollar, ASTKind::EnvironmentVariable]);
+        expect_tokens_full("$MY_VAR2.len", &[
+            ASTKind::Dollar,
+            ASTKind::EnvironmentVariable,
+            ASTKind::Dot,
+            ASTKind::Identifier
+        ]);
+        expect_tokens_full("$Path", &[ASTKind::Dollar, ASTKind::Identifier]);
+        expect_tokens_full("$?", &[ASTKind::Dollar, ASTKind::Identifier]);
+        expect_tokens_full("$foo(PATH)", &[
+            ASTKind::Dollar,
+            ASTKind::Identifier,
+            ASTKind::OpenParen,
+            ASTKind::Identifier,
+            ASTKind::CloseParen
+        ]);
+    }
+
     #[test]
     fn test_braced_cmd() {
         let tokenizer = Tokenizer::new(r#"${kek}"#);
@@ -455,11 +822,108 @@ pub mod tests {
             (12, ASTKind::CloseParen, 13),
             (13, ASTKind::Dot, 14),
             (14, ASTKind::Identifier, 18),
-            (18, ASTKind::SemiColon, 19),
+            (18, ASTKind::TrailingSemiColon, 19),
         ];
         assert_eq!(tokens, expected);
     }
 
+    #[test]
+    fn test_number_literals() {
+        expect_function_token!("42", ASTKind::NumberLiteral);
+        expect_function_token!("-5", ASTKind::NumberLiteral);
+        expect_function_token!("3e10", ASTKind::NumberLiteral);
+        expect_function_token!("-1.5e-3", ASTKind::NumberLiteral);
+        expect_function_token!("2.5E+3", ASTKind::NumberLiteral);
+
+        expect_function_token!(".5", ASTKind::NumberLiteral);
+        expect_function_token!("-.5e2", ASTKind::NumberLiteral);
+
+        assert_eq!(tokenize_function_level(""), vec![]);
+        assert_eq!(tokenize_function_level("3e"), vec![ASTKind::NumberLiteral, ASTKind::Identifier]);
+        // no empty number before identifiers or dots
+        assert_eq!(tokenize_function_level("kek"), vec![ASTKind::Identifier]);
+        assert_eq!(tokenize_function_level("kek.lol"), vec![ASTKind::Identifier, ASTKind::Dot, ASTKind::Identifier]);
+        assert_eq!(tokenize_function_level("1 .5"), vec![ASTKind::NumberLiteral, ASTKind::NumberLiteral]);
+    }
+
+    #[test]
+    fn test_arithmetic_operators() {
+        expect_tokens_full("$(2+3*4 - 1/2 % 3)", &[
+            ASTKind::Dollar,
+            ASTKind::OpenParen,
+            ASTKind::NumberLiteral,
+            ASTKind::Plus,
+            ASTKind::NumberLiteral,
+            ASTKind::Star,
+            ASTKind::NumberLiteral,
+            ASTKind::Minus,
+            ASTKind::NumberLiteral,
+            ASTKind::Slash,
+            ASTKind::NumberLiteral,
+            ASTKind::Percent,
+            ASTKind::NumberLiteral,
+            ASTKind::CloseParen
+        ]);
+        expect_tokens_full("$(2-3 - -1)", &[
+            ASTKind::Dollar,
+            ASTKind::OpenParen,
+            ASTKind::NumberLiteral,
+            ASTKind::Minus,
+            ASTKind::NumberLiteral,
+            ASTKind::Minus,
+            ASTKind::NumberLiteral,
+            ASTKind::CloseParen
+        ]);
+        expect_tokens_full("$foo(2 -3)", &[
+            ASTKind::Dollar,
+            ASTKind::Identifier,
+            ASTKind::OpenParen,
+            ASTKind::NumberLiteral,
+            ASTKind::NumberLiteral,
+            ASTKind::CloseParen
+        ]);
+    }
+
+    #[test]
+    fn test_list_literal() {
+        expect_tokens_full("$[1 \"a\"].at(0)", &[
+            ASTKind::Dollar,
+            ASTKind::OpenBracket,
+            ASTKind::NumberLiteral,
+            ASTKind::DoubleQuote,
+            ASTKind::Literal,
+            ASTKind::DoubleQuote,
+            ASTKind::CloseBracket,
+            ASTKind::Dot,
+            ASTKind::Identifier,
+            ASTKind::OpenParen,
+            ASTKind::NumberLiteral,
+            ASTKind::CloseParen
+        ]);
+    }
+
+    #[test]
+    fn test_bool_literals() {
+        expect_function_token!("true", ASTKind::BoolLiteral);
+        expect_function_token!("false", ASTKind::BoolLiteral);
+        expect_function_token!("trueish", ASTKind::Identifier);
+    }
+
+    #[test]
+    fn test_conditional_keywords() {
+        expect_function_token!("if", ASTKind::If);
+        expect_function_token!("else", ASTKind::Else);
+        expect_function_token!("iffy", ASTKind::Identifier);
+        expect_function_token!("while", ASTKind::While);
+
+        assert_eq!(tokenize_top_level("$if x { a } else { b }"), vec![
+            ASTKind::Dollar, ASTKind::If, ASTKind::Identifier,
+            ASTKind::OpenBrace, ASTKind::Literal, ASTKind::CloseBrace,
+            ASTKind::Else,
+            ASTKind::OpenBrace, ASTKind::Literal, ASTKind::CloseBrace,
+        ]);
+    }
+
     #[test]
     fn test_uncompleted_str() {
         let tokenizer = Tokenizer::new(r#"$ "fdfdf"#);
@@ -1,66 +1,214 @@
-use std::borrow::Borrow;
 use std::cell::RefCell;
 use std::collections::VecDeque;
-use std::fs::File;
-use std::future::Future;
-use std::io::{Error, Read, stderr, stdin, stdout, Write};
-use std::mem::ManuallyDrop;
-use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, stderr, stdin, stdout, Write};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::os::unix::io::FromRawFd;
 use std::os::unix::prelude::{AsFd, OwnedFd};
-use std::pin::Pin;
-use std::task::{Context, Poll};
-use futures::future::BoxFuture;
-use nix::unistd::pipe;
-use termion::input::TermReadEventsAndRaw;
-use fosh::error_printer::ErrorType;
+use std::thread::JoinHandle;
+use nix::fcntl::OFlag;
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::{fork, ForkResult, getpid, pipe2, Pid, setpgid};
+use crate::error_printer::ErrorType;
 use crate::builtin::engine::parse_tree::PTNode;
-use crate::parser::ast::{ASTKind, downcast_to_typed};
-use crate::{construct_error_report, entities, EntitiesManager, report, TUI};
-use crate::builtin::engine::entities::{AwaitableFuture, Entity, EntityExecutionError, EntityRef, Execution, ExecutionConfig, FoshEntity, FoshResult};
-use crate::builtin::engine::{Argument, Value};
+use crate::parser::ast::{ASTKind, Conditional, downcast_to_typed, ListLiteral, Redirection, WhileLoop};
+use crate::builtin::paths::expand_tilde;
+use crate::{entities, report};
+use crate::builtin::engine::entities::{EntityExecutionError, EntityRef, Execution, ExecutionConfig, FoshEntity, FoshResult};
+use crate::builtin::engine::Value;
+use crate::runtime::terminal::ForegroundGuard;
 
 pub fn execute<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
     execute_delimited(command, execution)
 }
 
+// Executes the command turning a panic into an internal error, so a single bad command can't kill the shell.
+//
+// Entities are Rc/RefCell, so the closure is not UnwindSafe and has to be asserted. It is fine because:
+// - RefCell borrows are guards living in the unwound frames, they are released during unwinding
+// - nothing is shared with other threads, so no one can observe half-updated state concurrently
+// - fds and foreground process group are restored by Drop of ExecutionConfig and ForegroundGuard
+// The worst we can get is an entity which was only partially updated by the panicking builtin.
+pub fn execute_catching<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> FoshResult<EntityRef> {
+    match catch_unwind(AssertUnwindSafe(|| execute(command, execution).execute())) {
+        Ok(r) => r,
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+
+            Err(EntityExecutionError::new_single(command.id(), ErrorType::Internal, format!("Internal error: {}", message)))
+        }
+    }
+}
+
 fn execute_delimited<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
     if command.kind != ASTKind::Delimited {
-        execute_sequenced(command, execution)
+        execute_background(command, execution)
     } else {
-        let children = command.children().iter()
-            .filter(|c| c.kind != ASTKind::SemiColon)
-            .map(|c| *c)
-            .collect::<Vec<_>>();
+        let mut stages = Vec::new();
+        collect_stages(command, ASTKind::Delimited, &mut stages);
 
-        for node in 0..children.len() - 1 {
-            let r = execute_sequenced(children[node], execution).execute();
+        let (last, rest) = match stages.split_last() {
+            Some(split) => split,
+            None => return Ok(nothing_executed()).into(),
+        };
+        for node in rest {
+            let r = execute_background(node, execution).execute();
             if let Err(e) = &r {
-                report(command.root(), e);
+                report(command.root(), e, execution);
             }
         }
 
-        execute_sequenced(children.last().unwrap(), execution)
+        execute_background(last, execution)
     }
 }
 
-fn execute_sequenced<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
-    if command.kind != ASTKind::Sequenced {
-        execute_piped(command, execution)
+fn execute_background<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
+    if command.kind != ASTKind::Background {
+        execute_logical(command, execution)
     } else {
-        let children = command.children().iter()
-            .filter(|c| c.kind != ASTKind::SemiColon)
-            .map(|c| *c)
-            .collect::<Vec<_>>();
+        let mut stages = Vec::new();
+        collect_stages(command, ASTKind::Background, &mut stages);
+
+        // every stage followed by & goes to background, only the last one may not be followed by it
+        let foreground = if command.children().last().is_some_and(|c| c.kind == ASTKind::TrailingAmpersand) {
+            None
+        } else {
+            stages.pop()
+        };
+
+        let mut last = None;
+        for stage in stages {
+            let r = spawn_background(stage, execution);
+            if let Err(e) = &r {
+                report(command.root(), e, execution);
+            }
+            last = Some(r);
+        }
+
+        match foreground {
+            Some(stage) => execute_logical(stage, execution),
+            None => last.unwrap_or_else(|| Ok(nothing_executed())).into(),
+        }
+    }
+}
+
+// Result of a chain with no commands left after dropping delimiters
+fn nothing_executed() -> EntityRef {
+    entities().make_entity("Nothing to execute".to_string())
+}
+
+// Spawns the stage and registers it as a job without waiting for it.
+// Anything but a single process runs in a forked copy of the shell, see fork_job
+fn spawn_background<'a>(stage: &'a PTNode<'a>, execution: &ExecutionConfig) -> FoshResult<EntityRef> {
+    // terminal belongs to the foreground, a background job reading it would be stopped or steal the input
+    let execution = match execution.try_clone() {
+        Ok(c) => ExecutionConfig { null_stdin: true, ..c },
+        Err(e) => {
+            return Err(EntityExecutionError::new_single(stage.id(), ErrorType::CannotCloneFd, format!("Cannot clone execution config: {}", e)));
+        }
+    };
+    let execution = &execution;
+    let pid = match stage.kind {
+        // stages of pipelines and chains are spawned one by one, so the shell has to stay around for them
+        ASTKind::Piped | ASTKind::And | ASTKind::Or => {
+            fork_job(stage, execution, |config| execute_logical(stage, config).execute())?
+        }
+        _ => match execute_logical(stage, execution) {
+            // child is reaped through the job table, see EntitiesManager::reap_jobs
+            ExecutionState::Execution(Execution::Process(process)) => process.pid(),
+            ExecutionState::Execution(pseudo) => fork_job(stage, execution, |_| pseudo.execute())?,
+            ExecutionState::Value(value) => return value,
+        },
+    };
+    let id = entities().add_job(stage.data.to_string(), pid);
+
+    // like sh, job id goes to stderr so it does not mix with the output
+    let stderr = stderr();
+    let err = execution.std_err.as_ref()
+        .map(|e| e.try_clone())
+        .unwrap_or_else(|| stderr.as_fd().try_clone_to_owned());
+    if let Ok(err) = err {
+        let _ = writeln!(File::from(err), "[{}] {}", id, pid);
+    }
 
-        for node in 0..children.len() - 1 {
-            let r = execute_piped(children[node], execution).execute();
-            if r.is_err() { return r.into(); }
+    Ok(entities().make_entity("Job".to_string())
+        .with_property("id", Value::Number(id as f64).into_entity()))
+}
+
+// Runs the job in a forked copy of the shell leading a process group, which everything it spawns joins.
+// The copy reports errors itself and exits with the status of the job, so nothing of it gets back to the shell
+fn fork_job<'a, F>(stage: &'a PTNode<'a>, execution: &ExecutionConfig, job: F) -> FoshResult<Pid>
+    where F: FnOnce(&ExecutionConfig) -> FoshResult<EntityRef>
+{
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+            let status = match execution.try_clone() {
+                Ok(config) => {
+                    let config = ExecutionConfig { pgid: Some(getpid()), ..config };
+                    // a panic must not unwind into the code of the shell we are a copy of
+                    match catch_unwind(AssertUnwindSafe(|| job(&config))) {
+                        Ok(Ok(_)) => 0,
+                        Ok(Err(e)) => {
+                            report(stage.root(), &e, &config);
+                            e.status.unwrap_or(1)
+                        }
+                        Err(_) => 1,
+                    }
+                }
+                Err(_) => 1,
+            };
+            // exit handlers and buffers belong to the shell, they must not run or be flushed twice
+            unsafe { nix::libc::_exit(status) }
+        }
+        Ok(ForkResult::Parent { child }) => {
+            // same in the child, so the group exists whichever of us gets there first
+            let _ = setpgid(child, child);
+            Ok(child)
+        }
+        Err(e) => {
+            Err(EntityExecutionError::new_single(stage.id(), ErrorType::Execution, format!("Cannot fork: {}", e)))
         }
+    }
+}
+
+fn execute_logical<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
+    match command.kind {
+        ASTKind::And => execute_and(command, execution),
+        ASTKind::Or => execute_or(command, execution),
+        _ => execute_piped(command, execution),
+    }
+}
 
-        execute_piped(children.last().unwrap(), execution)
+fn is_success(result: &FoshResult<EntityRef>) -> bool {
+    match result {
+        Ok(entity) => entity.is_truthy(),
+        Err(_) => false,
     }
 }
 
+// right side is not even spawned unless left one succeeded
+fn execute_and<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
+    let left = execute_logical(command.children()[0], execution).execute();
+    if !is_success(&left) {
+        return left.into();
+    }
+
+    execute_piped(command.children()[2], execution)
+}
+
+// right side is not even spawned unless left one failed
+fn execute_or<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
+    let left = execute_logical(command.children()[0], execution).execute();
+    if is_success(&left) {
+        return left.into();
+    }
+
+    execute_piped(command.children()[2], execution)
+}
+
 fn execute_piped<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
     if command.kind != ASTKind::Piped {
         execute_command_or_function(command, execution)
@@ -73,10 +221,12 @@ fn execute_piped<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> Ex
             .unwrap_or_else(|| stderr.as_fd().try_clone_to_owned());
         let final_out = execution.std_out.as_ref()
             .map(|e| e.try_clone())
-            .unwrap_or_else(|| stderr.as_fd().try_clone_to_owned());
-        let first_in = execution.std_in.as_ref()
-            .map(|e| e.try_clone())
-            .unwrap_or_else(|| stderr.as_fd().try_clone_to_owned());
+            .unwrap_or_else(|| stdout.as_fd().try_clone_to_owned());
+        let first_in = match execution.std_in.as_ref() {
+            Some(e) => e.try_clone(),
+            None if execution.null_stdin => File::open("/dev/null").map(OwnedFd::from),
+            None => stdin.as_fd().try_clone_to_owned(),
+        };
 
         if final_err.is_err() {
             return Err(EntityExecutionError::new_single(
@@ -105,17 +255,18 @@ fn execute_piped<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> Ex
         }
         let first_in = first_in.unwrap();
 
-        let children = command.children().iter()
-            .filter(|c| c.kind != ASTKind::Pipe)
-            .map(|c| *c)
-            .collect::<Vec<_>>();
+        let mut children = Vec::new();
+        collect_stages(command, ASTKind::Piped, &mut children);
 
         let mut last_read = first_in;
         let mut executions = VecDeque::new();
+        // stages join the group of the first process, so the terminal and Ctrl+C reach all of them at once
+        let mut pgid = execution.pgid;
         for i in 0..children.len() {
             let child = children[i];
             let config = if i != children.len() - 1 {
-                let pipe_result = pipe();
+                // children must not inherit raw ends, or the pipe never sees EOF or SIGPIPE
+                let pipe_result = pipe2(OFlag::O_CLOEXEC);
 
                 if let Err(e) = pipe_result {
                     return Err(EntityExecutionError::new_single(
@@ -135,6 +286,8 @@ fn execute_piped<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> Ex
                     &write,
                     &final_err
                 );
+                // the stage got its own copy, keeping ours would leave the next stage without EOF
+                drop(write);
                 last_read = read;
                 config
             } else {
@@ -147,7 +300,7 @@ fn execute_piped<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> Ex
             };
 
             let config = match config {
-                Ok(c) => c,
+                Ok(c) => ExecutionConfig { deadline: execution.deadline, pgid, ..c },
                 Err(e) => return {
                     Err(EntityExecutionError::new_single(
                         command.id(),
@@ -159,16 +312,59 @@ fn execute_piped<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> Ex
 
             let r = execute_command_or_function(child, &config);
             std::mem::drop(config);
+            if let (None, ExecutionState::Execution(Execution::Process(process))) = (pgid, &r) {
+                pgid = Some(process.pid());
+            }
 
             executions.push_back(r);
         }
+        // last stage already has its copy, holding read end would keep producers alive after reader exits
+        drop(last_read);
+        drop(final_out);
+        drop(final_err);
 
-        let mut last = None;
-        while !executions.is_empty() {
-            last = Some(executions.pop_front().unwrap().execute());
+        wait_stages(executions, pgid).into()
+    }
+}
+
+// Processes of the pipeline are already running at this point. Pseudo executions are run first,
+// so a process on either side of them is never waited on while it still needs them to read or write.
+// Waiting for processes is left for the end, when the data has already flown through.
+// Two adjacent pseudo executions still share a pipe buffer since they can't run concurrently.
+// All processes are in the pgid group, which gets the terminal once for the whole wait.
+fn wait_stages(executions: VecDeque<ExecutionState>, pgid: Option<Pid>) -> FoshResult<EntityRef> {
+    let mut results = Vec::with_capacity(executions.len());
+    let mut processes = Vec::new();
+    for (i, execution) in executions.into_iter().enumerate() {
+        match execution {
+            ExecutionState::Execution(Execution::Process(process)) => {
+                processes.push((i, process));
+                results.push(None);
+            }
+            other => results.push(Some(other.execute())),
         }
+    }
 
-        return last.unwrap().into();
+    let _foreground = pgid.map(ForegroundGuard::new);
+    // a stage touching the terminal while pseudo executions ran was stopped with SIGTTOU
+    if let Some(pgid) = pgid {
+        let _ = killpg(pgid, Signal::SIGCONT);
+    }
+    for (i, process) in processes {
+        results[i] = Some(process.execute());
+    }
+
+    results.pop().unwrap().unwrap()
+}
+
+// left recursive grammar nests nodes of the same kind, so flatten them into one list
+pub fn collect_stages<'a>(node: &'a PTNode<'a>, kind: ASTKind, stages: &mut Vec<&'a PTNode<'a>>) {
+    for child in node.children().iter() {
+        if child.kind == kind {
+            collect_stages(child, kind, stages);
+        } else if !matches!(child.kind, ASTKind::SemiColon | ASTKind::TrailingSemiColon | ASTKind::Ampersand | ASTKind::TrailingAmpersand | ASTKind::Pipe) {
+            stages.push(child);
+        }
     }
 }
 
@@ -178,7 +374,7 @@ fn execute_command_or_function<'a>(command: &'a PTNode<'a>, execution: &Executio
             execute_function(command, execution)
         }
         ASTKind::Command => {
-            return match downcast_to_typed(command).unwrap().infer_value(command) {
+            match downcast_to_typed(command).unwrap().infer_value(command) {
                 None => {
                     Err(EntityExecutionError::new_single(command.id(), ErrorType::Semantic, "Could not infer execution value")).into()
                 }
@@ -195,7 +391,7 @@ fn execute_command_or_function<'a>(command: &'a PTNode<'a>, execution: &Executio
                                 }
                             };
                             match (exe.callee)(e.clone(), &[], config) {
-                                Ok(mut e) => {
+                                Ok(e) => {
                                     e.into()
                                 }
                                 Err(e) => {
@@ -205,20 +401,104 @@ fn execute_command_or_function<'a>(command: &'a PTNode<'a>, execution: &Executio
                         }
                     }
                 }
+            }
+        }
+        ASTKind::Redirected => {
+            let config = match redirect(command, execution) {
+                Ok(c) => c,
+                Err(e) => return Err(e).into(),
             };
+            execute_command_or_function(command.children()[0], &config)
         }
         _ => { panic!("Expected command or function, got {:?}", command.kind) }
     }
 }
 
+// Opens redirection targets replacing corresponding fds of the config
+fn redirect<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> FoshResult<ExecutionConfig> {
+    let mut config = execution.try_clone().map_err(|e| {
+        EntityExecutionError::new_single(command.id(), ErrorType::CannotCloneFd, format!("Cannot clone execution config: {}", e))
+    })?;
+
+    for redirection in command.children().iter().skip(1) {
+        let operator = redirection.children()[0].kind;
+        let target = redirection.children()[1];
+        let path = expand_tilde(redirection.value::<Redirection>().get_target(redirection));
+
+        let mut options = OpenOptions::new();
+        match operator {
+            ASTKind::Less => options.read(true),
+            ASTKind::DoubleGreater => options.create(true).append(true),
+            _ => options.create(true).write(true).truncate(true),
+        };
+        let file = options.open(path.as_ref()).map_err(|e| {
+            EntityExecutionError::new_single(target.id(), ErrorType::Execution, format!("Cannot open {}: {}", path, e))
+        })?;
+
+        match operator {
+            ASTKind::Less => config.std_in = Some(OwnedFd::from(file)),
+            ASTKind::ErrGreater => config.std_err = Some(OwnedFd::from(file)),
+            _ => config.std_out = Some(OwnedFd::from(file)),
+        }
+    }
+
+    Ok(config)
+}
+
 fn execute_function<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
     let node = command.children()[1];
-    execute_value(node, execution)
+    match command.children().get(2) {
+        Some(block) if block.kind == ASTKind::BracedCommand => execute_with_input(node, block, execution),
+        _ => execute_value(node, execution),
+    }
+}
+
+// Executes block with string value of the call as its stdin
+fn execute_with_input<'a>(call: &'a PTNode<'a>, block: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
+    let input = execute_value(call, execution).execute();
+    if input.is_err() { return input.into(); }
+    let input = match input.unwrap().try_as_string() {
+        Some(s) => s,
+        None => {
+            return Err(EntityExecutionError::new_single(call.id(), ErrorType::Semantic, "Input is not a string")).into();
+        }
+    };
+
+    let (read, write) = match pipe2(OFlag::O_CLOEXEC) {
+        Ok(p) => p,
+        Err(e) => {
+            return Err(EntityExecutionError::new_single(
+                call.id(),
+                ErrorType::CannotCreatePipe,
+                format!("Cannot create pipe: {}", e),
+            )).into();
+        }
+    };
+    let read = unsafe { OwnedFd::from_raw_fd(read) };
+    let mut write = unsafe { File::from_raw_fd(write) };
+
+    // writing from another thread, so input bigger than pipe buffer doesn't block us
+    let writer = std::thread::spawn(move || {
+        // reader may exit without consuming everything, that's fine
+        let _ = write.write_all(input.as_bytes());
+    });
+
+    let config = match execution.try_clone() {
+        Ok(c) => ExecutionConfig { std_in: Some(read), ..c },
+        Err(e) => {
+            return Err(EntityExecutionError::new_single(block.id(), ErrorType::CannotCloneFd, format!("Cannot clone execution config: {}", e))).into();
+        }
+    };
+    let result = execute_braced_command(block, &config).execute();
+    drop(config);
+    let _ = writer.join();
+
+    result.into()
 }
 
 fn execute_value<'a>(node: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
     match node.kind {
-        ASTKind::StringLiteral | ASTKind::NumberLiteral => {
+        ASTKind::StringLiteral | ASTKind::NumberLiteral | ASTKind::BoolLiteral | ASTKind::EnvironmentVariable => {
             execute_primitive(node)
         }
         ASTKind::BracedCommand => {
@@ -230,39 +510,243 @@ fn execute_value<'a>(node: &'a PTNode<'a>, execution: &ExecutionConfig) -> Execu
         ASTKind::PropertyCall => {
             execute_property_call(node, execution)
         }
+        ASTKind::Assignation => {
+            execute_assignation(node, execution)
+        }
+        ASTKind::Arithmetic => {
+            execute_value(node.children()[1], execution)
+        }
+        ASTKind::ListLiteral => {
+            execute_list(node, execution)
+        }
+        ASTKind::BinaryOperation => {
+            execute_binary_operation(node, execution)
+        }
+        ASTKind::Conditional => {
+            execute_if(node, execution)
+        }
+        ASTKind::WhileLoop => {
+            execute_while(node, execution)
+        }
         _ => {
             panic!("Unexpected function node {:?}", node.kind)
         }
     }
 }
 
+// Only the chosen branch is executed. Without else branch nothing happens when the condition is falsy
+fn execute_if<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
+    let conditional = command.value::<Conditional>();
+
+    let condition = match execute_value(conditional.get_condition(command), execution).execute() {
+        Ok(value) => value.is_truthy(),
+        // command exited with non-zero status, that's just a false condition
+        Err(e) if e.status.is_some() => false,
+        Err(e) => return Err(e).into(),
+    };
+
+    if condition {
+        execute_braced_command(conditional.get_then(command), execution)
+    } else if let Some(otherwise) = conditional.get_else(command) {
+        execute_braced_command(otherwise, execution)
+    } else {
+        Ok(entities().make_entity("No branch taken".to_string())).into()
+    }
+}
+
+// Condition is evaluated before every iteration like in execute_if.
+// Failing body stops the loop, that's also how Ctrl+C killing a command inside it ends the loop
+fn execute_while<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
+    let while_loop = command.value::<WhileLoop>();
+
+    let mut last = None;
+    loop {
+        let condition = match execute_value(while_loop.get_condition(command), execution).execute() {
+            Ok(value) => value.is_truthy(),
+            Err(e) if e.status.is_some() => false,
+            Err(e) => return Err(e).into(),
+        };
+        if !condition { break; }
+
+        match execute_braced_command(while_loop.get_body(command), execution).execute() {
+            Ok(value) => last = Some(value),
+            Err(e) => return Err(e).into(),
+        }
+    }
+
+    Ok(last.unwrap_or_else(|| entities().make_entity("Loop never ran".to_string()))).into()
+}
+
+fn execute_list<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
+    let mut items = Vec::new();
+    for element in command.value::<ListLiteral>().get_elements(command) {
+        match execute_argument(element, execution).execute() {
+            Ok(v) => items.push(v),
+            Err(e) => return Err(e).into(),
+        }
+    }
+
+    Ok(Value::List(items).into_entity()).into()
+}
+
+fn execute_binary_operation<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
+    let operator = command.children()[1];
+
+    let mut operands = [0.0; 2];
+    for (i, node) in [command.children()[0], command.children()[2]].iter().enumerate() {
+        let value = match execute_value(node, execution).execute() {
+            Ok(v) => v,
+            Err(e) => return Err(e).into(),
+        };
+        operands[i] = match value.try_as_number() {
+            Some(n) => n,
+            None => {
+                return Err(EntityExecutionError::new_single(node.id(), ErrorType::Semantic, format!("Operand of {} is not a number", operator.data))).into();
+            }
+        };
+    }
+    let [left, right] = operands;
+
+    let result = match operator.kind {
+        ASTKind::Plus => left + right,
+        ASTKind::Minus => left - right,
+        ASTKind::Star => left * right,
+        ASTKind::Slash | ASTKind::Percent if right == 0.0 => {
+            return Err(EntityExecutionError::new_single(operator.id(), ErrorType::Execution, "Division by zero")).into();
+        }
+        ASTKind::Slash => left / right,
+        ASTKind::Percent => left % right,
+        _ => panic!("Unexpected operator {:?}", operator.kind),
+    };
+
+    Ok(Value::Number(result).into_entity()).into()
+}
+
+// Stores value as a variable, so later `$name` resolves it
+fn execute_assignation<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
+    let name = command.children()[0].data;
+    let value = execute_value(command.children()[2], execution).execute();
+    if value.is_err() { return value.into(); }
+    let value = value.unwrap();
+
+    entities().set_variable(name, value.clone());
+
+    Ok(value).into()
+}
+
 fn execute_property_insn<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
     let (left, name) = if command.children().len() > 1 {
         let v = execute_value(command.children()[0], execution).execute();
         if v.is_err() { return v.into(); }
         (v.unwrap(), command.children()[2])
     } else {
+        if let Some(variable) = entities().get_variable(command.children()[0].data) {
+            return Ok(variable).into();
+        }
         (entities().global(), command.children()[0])
     };
 
-    let x = RefCell::borrow(&left);
-    match x.properties().get(name.data) {
+    let property = match entities().lookup_property(&left, name.data) {
+        Some(property) => property,
         None => {
-            Err(EntityExecutionError::new_single(command.id(), ErrorType::Semantic, format!("Property {} does not exist in {}", name.data, left.name())))
+            return Err(EntityExecutionError::new_single(command.id(), ErrorType::Semantic, format!("Property {} does not exist in {}", name.data, left.name()))).into();
         }
-        Some(e) => {
-            Ok(e.clone())
+    };
+
+    // getters are computed for the entity on access
+    let getter = RefCell::borrow(&property);
+    let exe = match getter.callee() {
+        Some(exe) if exe.is_getter => exe,
+        _ => return Ok(property.clone()).into(),
+    };
+    let config = match execution.try_clone() {
+        Ok(c) => ExecutionConfig { pt: command.id(), ..c },
+        Err(e) => {
+            return Err(EntityExecutionError::new_single(command.id(), ErrorType::CannotCloneFd, format!("Cannot clone execution config: {}", e))).into();
         }
-    }.into()
+    };
+    match (exe.callee)(left.clone(), &[], config) {
+        Ok(e) => e.into(),
+        Err(e) => Err(e).into(),
+    }
 }
 
-fn validate_types(arg: Argument, value: &EntityRef) -> bool {
-    let r = RefCell::borrow(&value);
-    for x in arg.possible_types {
-        if r.implicits().contains_key(&x) { return true; }
+// Command substitution: executes block with stdout captured into a string entity.
+// Trailing newline is trimmed, like shells do for `$(cmd)`
+fn capture_braced_command<'a>(block: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
+    let (read, write) = match pipe2(OFlag::O_CLOEXEC) {
+        Ok(p) => p,
+        Err(e) => {
+            return Err(EntityExecutionError::new_single(
+                block.id(),
+                ErrorType::CannotCreatePipe,
+                format!("Cannot create pipe: {}", e),
+            )).into();
+        }
+    };
+    let read = unsafe { File::from_raw_fd(read) };
+    let write = unsafe { OwnedFd::from_raw_fd(write) };
+
+    // reading from another thread, so output bigger than pipe buffer doesn't block the command
+    let hook = execution.output_hook.clone();
+    let reader = read_output(read, move |chunk| {
+        if let Some(hook) = &hook {
+            (hook.0)(chunk);
+        }
+    });
+
+    let config = match execution.try_clone() {
+        Ok(c) => ExecutionConfig { std_out: Some(write), ..c },
+        Err(e) => {
+            return Err(EntityExecutionError::new_single(block.id(), ErrorType::CannotCloneFd, format!("Cannot clone execution config: {}", e))).into();
+        }
+    };
+    let result = execute_braced_command(block, &config).execute();
+    // reader gets EOF only when every write end is closed
+    drop(config);
+    let output = reader.join().unwrap_or_default();
+    if let Err(e) = result {
+        return Err(e).into();
+    }
+
+    let mut output = String::from_utf8_lossy(&output).into_owned();
+    if output.ends_with('\n') {
+        output.pop();
     }
 
-    return false;
+    Ok(Value::String(output).into_entity()).into()
+}
+
+// Reads the pipe on its own thread handing every chunk to the sink as soon as it arrives, so output can be
+// shown while the writer is still running. Joining gives everything that was read. Output written before
+// the writer died, killed or not, is still delivered since the pipe keeps it until EOF
+pub fn read_output<F>(mut read: File, mut sink: F) -> JoinHandle<Vec<u8>>
+    where F: FnMut(&[u8]) + Send + 'static
+{
+    std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match read.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    sink(&buf[..n]);
+                    output.extend_from_slice(&buf[..n]);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+        output
+    })
+}
+
+// Arguments and list elements are values, so braced commands among them are substituted with their output
+fn execute_argument<'a>(node: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
+    match node.kind {
+        ASTKind::BracedCommand => capture_braced_command(node, execution),
+        _ => execute_value(node, execution),
+    }
 }
 
 fn property_call_execution<'a>(command: &'a PTNode<'a>, execution: &ExecutionConfig) -> ExecutionState {
@@ -276,7 +760,7 @@ fn property_call_execution<'a>(command: &'a PTNode<'a>, execution: &ExecutionCon
     let mut args = Vec::new();
     for x in parenthesis.children().iter().skip(1) {
         if x.kind == ASTKind::Parameter {
-            let r = execute_value(x.children()[0], execution).execute();
+            let r = execute_argument(x.children()[0], execution).execute();
 
             match r {
                 Ok(v) => args.push(v),
@@ -291,12 +775,21 @@ fn property_call_execution<'a>(command: &'a PTNode<'a>, execution: &ExecutionCon
             Err(EntityExecutionError::new_single(command.id(), ErrorType::Semantic, format!("Property {} is not callable", left.name()))).into()
         }
         Some(exe) => {
-            if exe.arguments.len() != args.len() {
-                return Err(EntityExecutionError::new_single(parenthesis.id(), ErrorType::Semantic, format!("Expected {} arguments, got {}", exe.arguments.len(), args.len()))).into();
+            if !exe.accepts_arity(args.len()) {
+                let expected = if exe.is_variadic {
+                    format!("at least {}", exe.arguments.len() - 1)
+                } else if exe.optional > 0 {
+                    format!("from {} to {}", exe.arguments.len() - exe.optional, exe.arguments.len())
+                } else {
+                    exe.arguments.len().to_string()
+                };
+                return Err(EntityExecutionError::new_single(parenthesis.id(), ErrorType::Semantic, format!("Expected {} arguments, got {}", expected, args.len()))).into();
             }
             for i in 0..args.len() {
-                if !validate_types(exe.arguments[i].clone(), &args[i]) {
-                    return Err(EntityExecutionError::new_single(parenthesis.children()[1 + i].id(), ErrorType::Semantic, format!("Argument is not of type {:?}", exe.arguments[i].possible_types[0]))).into();
+                let argument = exe.argument_at(i).unwrap();
+                if !argument.accepts(&args[i]) {
+                    let expected = argument.expected_types();
+                    return Err(EntityExecutionError::new_single(parenthesis.children()[1 + i].id(), ErrorType::Semantic, format!("Argument {} is not of type {}", argument.name, expected))).into();
                 }
             }
 
@@ -308,7 +801,7 @@ fn property_call_execution<'a>(command: &'a PTNode<'a>, execution: &ExecutionCon
             };
             match (exe.callee)(left.clone(), &args, config) {
                 Ok(e) => {
-                    return e.into();
+                    e.into()
                 }
                 Err(e) => {
                     Err(e).into()
@@ -328,12 +821,12 @@ fn execute_braced_command<'a>(command: &'a PTNode<'a>, execution: &ExecutionConf
 }
 
 fn execute_primitive<'a>(command: &'a PTNode<'a>) -> ExecutionState {
-    return match downcast_to_typed(command).unwrap().infer_value(command) {
+    match downcast_to_typed(command).unwrap().infer_value(command) {
         None => {
             Err(EntityExecutionError::new_single(command.id(), ErrorType::Semantic, "Could not infer value")).into()
         }
         Some(e) => Ok(e).into()
-    };
+    }
 }
 
 pub enum ExecutionState {
@@ -350,14 +843,643 @@ impl ExecutionState {
     }
 }
 
-impl Into<ExecutionState> for FoshResult<EntityRef> {
-    fn into(self) -> ExecutionState {
-        ExecutionState::Value(self)
+impl From<FoshResult<EntityRef>> for ExecutionState {
+    fn from(val: FoshResult<EntityRef>) -> Self {
+        ExecutionState::Value(val)
+    }
+}
+
+impl From<Execution> for ExecutionState {
+    fn from(val: Execution) -> Self {
+        ExecutionState::Execution(val)
     }
 }
 
-impl Into<ExecutionState> for Execution {
-    fn into(self) -> ExecutionState {
-        ExecutionState::Execution(self)
+#[cfg(test)]
+pub mod tests {
+    use crate::builtin::paths::tests::TempDir;
+    use crate::builtin::annotator::tests::init_entities;
+    use crate::builtin::engine::parse_tree::parse_line;
+    use crate::builtin::engine::entities::{FoshEntity, OutputHook, ProcessExecution};
+    use super::*;
+
+    // executes line with given stdin and returns result with captured stdout
+    pub fn execute_with_input(line: &str, input: &str) -> (FoshResult<EntityRef>, String) {
+        init_entities();
+        let tree = parse_line(line).unwrap();
+
+        let (in_read, in_write) = pipe2(OFlag::O_CLOEXEC).unwrap();
+        let (out_read, out_write) = pipe2(OFlag::O_CLOEXEC).unwrap();
+
+        let mut in_write = unsafe { File::from_raw_fd(in_write) };
+        in_write.write_all(input.as_bytes()).unwrap();
+        drop(in_write);
+
+        let config = ExecutionConfig {
+            pt: tree.root().id(),
+            ..ExecutionConfig::new(Some(unsafe { OwnedFd::from_raw_fd(in_read) }), Some(unsafe { OwnedFd::from_raw_fd(out_write) }), None)
+        };
+        let result = execute(tree.root(), &config).execute();
+        drop(config);
+
+        let mut output = String::new();
+        unsafe { File::from_raw_fd(out_read) }.read_to_string(&mut output).unwrap();
+
+        (result, output)
+    }
+
+    #[test]
+    fn test_env_variable_expansion() {
+        let (result, _) = execute_with_input("$PATH", "");
+        assert_eq!(result.unwrap().try_as_string(), Some(std::env::var("PATH").unwrap()));
+
+        let (result, _) = execute_with_input("$FOSH_SURELY_UNSET_VARIABLE", "");
+        assert_eq!(result.unwrap().try_as_string(), Some("".to_string()));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let evaluate = |line: &str| execute_with_input(line, "").0.unwrap().try_as_number().unwrap();
+
+        assert_eq!(evaluate("$(2 + 3 * 4)"), 14.0);
+        assert_eq!(evaluate("$((2 + 3) * 4)"), 20.0);
+        assert_eq!(evaluate("$(10 - 4 - 3)"), 3.0);
+        assert_eq!(evaluate("$(2-3)"), -1.0);
+        assert_eq!(evaluate("$(7 % 4 / 2)"), 1.5);
+    }
+
+    #[test]
+    fn test_arithmetic_errors() {
+        assert_eq!(error_notes("$(1 / 0)"), vec!["Division by zero"]);
+        assert_eq!(error_notes("$(1 % (2 - 2))"), vec!["Division by zero"]);
+        assert_eq!(error_notes(r#"$("a" + 1)"#), vec!["Operand of + is not a number"]);
+    }
+
+    #[test]
+    fn test_list() {
+        let (result, _) = execute_with_input(r#"$[1 "kek" [true]].at(1)"#, "");
+        assert_eq!(result.unwrap().try_as_string(), Some("kek".to_string()));
+
+        let (result, _) = execute_with_input(r#"$[1 "kek" [true]].length"#, "");
+        assert_eq!(result.unwrap().try_as_number(), Some(3.0));
+
+        let (result, _) = execute_with_input("$[1 2]", "");
+        let items = result.unwrap().try_as_list().unwrap();
+        assert_eq!(items.iter().map(|i| i.try_as_number().unwrap()).collect::<Vec<_>>(), vec![1.0, 2.0]);
+
+        assert_eq!(error_notes("$[1 2].at(2)"), vec!["Index 2 is out of bounds for list of length 2"]);
+    }
+
+    #[test]
+    fn test_bool_literals() {
+        let (result, _) = execute_with_input("$true", "");
+        assert_eq!(result.unwrap().try_as_bool(), Some(true));
+
+        let (result, _) = execute_with_input("$false", "");
+        let result = result.unwrap();
+        assert_eq!(result.try_as_bool(), Some(false));
+        assert_eq!(result.try_as_number(), None);
+    }
+
+    #[test]
+    fn test_with_input_feeds_block() {
+        let (result, output) = execute_with_input(r#"$with_input("kek lol") { cat }"#, "");
+
+        assert!(result.is_ok());
+        assert_eq!(output, "kek lol");
+    }
+
+    #[test]
+    fn test_command_substitution() {
+        let (result, output) = execute_with_input("$echo({ echo kek | tr a-z A-Z } \"lol\")", "");
+        assert!(result.is_ok());
+        assert_eq!(output, "KEK lol\n");
+
+        let (result, _) = execute_with_input("$[{ printf 'a\\n\\n' } {pwd}]", "");
+        let items = result.ok().unwrap().try_as_list().unwrap();
+        assert_eq!(items[0].try_as_string(), Some("a\n".to_string()));
+        assert_eq!(items[1].try_as_string(), Some(std::env::current_dir().unwrap().to_str().unwrap().to_string()));
+    }
+
+    #[test]
+    fn test_output_is_read_incrementally() {
+        let (read, write) = pipe2(OFlag::O_CLOEXEC).unwrap();
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "printf kek; exec sleep 5"])
+            .stdout(unsafe { OwnedFd::from_raw_fd(write) })
+            .spawn()
+            .unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let reader = read_output(unsafe { File::from_raw_fd(read) }, move |chunk| {
+            let _ = sender.send(chunk.to_vec());
+        });
+
+        // the first chunk comes while the process is still running
+        let chunk = receiver.recv_timeout(std::time::Duration::from_secs(3)).unwrap();
+        assert_eq!(chunk, b"kek");
+        assert!(child.try_wait().unwrap().is_none());
+
+        // killed process still gives what it has written
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(reader.join().unwrap(), b"kek");
+    }
+
+    #[test]
+    fn test_captured_output_goes_to_hook() {
+        init_entities();
+        // the command goes on only after the hook got its first output
+        let tree = parse_line(r#"$echo({ sh -c "printf kek; read x; printf lol" })"#).unwrap();
+        let (in_read, in_write) = pipe2(OFlag::O_CLOEXEC).unwrap();
+        let in_write = std::sync::Mutex::new(Some(unsafe { File::from_raw_fd(in_write) }));
+        let chunks = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let received = chunks.clone();
+        let config = ExecutionConfig {
+            pt: tree.root().id(),
+            // hangs are turned into a failure
+            deadline: Some(std::time::Instant::now() + std::time::Duration::from_secs(5)),
+            output_hook: Some(OutputHook(std::sync::Arc::new(move |chunk: &[u8]| {
+                received.lock().unwrap().push(chunk.to_vec());
+                if let Some(mut input) = in_write.lock().unwrap().take() {
+                    let _ = input.write_all(b"\n");
+                }
+            }))),
+            ..ExecutionConfig::new(Some(unsafe { OwnedFd::from_raw_fd(in_read) }), None, None)
+        };
+
+        let result = execute(tree.root(), &config).execute();
+        assert_eq!(result.ok().unwrap().try_as_string(), Some("keklol".to_string()));
+        assert_eq!(chunks.lock().unwrap().concat(), b"keklol");
+    }
+
+    #[test]
+    fn test_keys() {
+        let (result, _) = execute_with_input("$[1 2].keys", "");
+        let keys: Vec<String> = result.unwrap().try_as_list().unwrap().iter().map(|k| k.try_as_string().unwrap()).collect();
+        assert_eq!(keys, vec!["at", "length"]);
+
+        let (result, _) = execute_with_input(r#"$"kek".keys.length"#, "");
+        assert_eq!(result.unwrap().try_as_number(), Some(0.0));
+
+        let (result, _) = execute_with_input("$[1].nothing", "");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_prototype_property() {
+        init_entities();
+        entities().any().add_property("fosh_answer", Value::Number(42.0).into_entity());
+
+        let (result, _) = execute_with_input(r#"$"kek".fosh_answer"#, "");
+        assert_eq!(result.unwrap().try_as_number(), Some(42.0));
+        let (result, _) = execute_with_input("$[1 2].fosh_answer", "");
+        assert_eq!(result.unwrap().try_as_number(), Some(42.0));
+    }
+
+    #[test]
+    fn test_if_else() {
+        let branch = |condition: &str| {
+            let (result, output) = execute_with_input(&format!("$if {} {{ echo then }} else {{ echo else }}", condition), "");
+            assert!(result.is_ok(), "{}", condition);
+            output
+        };
+
+        assert_eq!(branch("true"), "then\n");
+        assert_eq!(branch("false"), "else\n");
+
+        assert_eq!(branch("1"), "then\n");
+        assert_eq!(branch("-0.5"), "then\n");
+        assert_eq!(branch("0"), "else\n");
+
+        assert_eq!(branch(r#""kek""#), "then\n");
+        assert_eq!(branch(r#""""#), "else\n");
+
+        assert_eq!(branch("{ true }"), "then\n");
+        assert_eq!(branch("{ false }"), "else\n");
+        assert_eq!(branch("{ sh -c 'exit 3' }"), "else\n");
+
+        assert_eq!(branch("[1]"), "then\n");
+        assert_eq!(branch("1.eq(1)"), "then\n");
+    }
+
+    #[test]
+    fn test_truthiness() {
+        init_entities();
+        let truthy = |v: Value| v.into_entity().is_truthy();
+
+        assert!(truthy(Value::Bool(true)));
+        assert!(!truthy(Value::Bool(false)));
+
+        assert!(truthy(Value::Number(-1.0)));
+        assert!(!truthy(Value::Number(0.0)));
+        assert!(!truthy(Value::Number(-0.0)));
+        assert!(!truthy(Value::Number(f64::NAN)));
+        assert!(!truthy(Value::Number(f64::INFINITY)));
+
+        assert!(truthy(Value::String(" ".to_string())));
+        assert!(!truthy(Value::String("".to_string())));
+        // strings are not parsed
+        assert!(truthy(Value::String("0".to_string())));
+
+        assert!(truthy(Value::List(vec![])));
+
+        let status = |s: f64| entities().make_entity("result".to_string())
+            .with_property("status", Value::Number(s).into_entity());
+        assert!(status(0.0).is_truthy());
+        assert!(!status(2.0).is_truthy());
+        assert!(entities().make_entity("cd success".to_string()).is_truthy());
+    }
+
+    #[test]
+    fn test_logical_uses_truthiness() {
+        let (_, output) = execute_with_input(r#"$"" && echo x || echo y"#, "");
+        assert_eq!(output, "y\n");
+
+        let (_, output) = execute_with_input("$1.eq(1) && echo x", "");
+        assert_eq!(output, "x\n");
+    }
+
+    #[test]
+    fn test_if_without_else() {
+        let (result, output) = execute_with_input("$if false { echo then }", "");
+        assert!(result.is_ok());
+        assert_eq!(output, "");
+
+        let (_, output) = execute_with_input("$if 2 { echo then }", "");
+        assert_eq!(output, "then\n");
+    }
+
+    #[test]
+    fn test_while() {
+        let (result, output) = execute_with_input("$fosh_i = 3 ; $while fosh_i { echo x ; $fosh_i = { $(fosh_i - 1) } }", "");
+        assert_eq!(output, "x\nx\nx\n");
+        // value of the last body evaluation
+        assert_eq!(result.unwrap().try_as_number(), Some(0.0));
+
+        let (result, output) = execute_with_input("$while false { echo x }", "");
+        assert_eq!(output, "");
+        assert!(result.is_ok());
+
+        let (result, output) = execute_with_input("$fosh_j = 2 ; $while fosh_j { $fosh_j = { $(fosh_j - 1) } ; $nothing }", "");
+        assert!(result.is_err());
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn test_panic_is_caught() {
+        init_entities();
+        let tree = parse_line("$foo").unwrap();
+        // not a command, so execution panics
+        let dollar = tree.root().find_child_with_kind_rec(ASTKind::Dollar).unwrap();
+
+        let config = ExecutionConfig { pt: tree.root().id(), ..Default::default() };
+        let error = execute_catching(dollar, &config).err().unwrap();
+        let notes: Vec<&String> = error.errors.values().flat_map(|e| e.notes.iter()).collect();
+        assert_eq!(notes, vec!["Internal error: Expected command or function, got Dollar"]);
+
+        // shell keeps working after that
+        let (result, _) = execute_with_input(r#"$with_input("kek") { cat }"#, "");
+        assert!(result.is_ok());
+    }
+
+    fn error_notes(line: &str) -> Vec<String> {
+        let (result, _) = execute_with_input(line, "");
+
+        let error = result.err().unwrap();
+        error.errors.values().flat_map(|e| e.notes.iter().cloned()).collect()
+    }
+
+    #[test]
+    fn test_fixed_arity_mismatch() {
+        assert_eq!(error_notes(r#"$cd("a" "b")"#), vec!["Expected 1 arguments, got 2"]);
+        assert_eq!(error_notes("$cd()"), vec!["Expected 1 arguments, got 0"]);
+        assert_eq!(error_notes(r#"$ls("a" true 1)"#), vec!["Expected from 0 to 2 arguments, got 3"]);
+    }
+
+    #[test]
+    fn test_variadic_arguments_are_type_checked() {
+        assert_eq!(error_notes(r#"$echo("a" 5)"#), vec!["Argument words is not of type string"]);
+    }
+
+    #[test]
+    fn test_assignation() {
+        let (result, _) = execute_with_input("$assigned_number = 42", "");
+        assert_eq!(result.unwrap().try_as_number(), Some(42.0));
+
+        let (result, _) = execute_with_input("$assigned_number", "");
+        assert_eq!(result.unwrap().try_as_number(), Some(42.0));
+    }
+
+    #[test]
+    fn test_variable_store() {
+        init_entities();
+        assert!(entities().get_variable("stored_variable").is_none());
+
+        let (result, _) = execute_with_input(r#"$stored_variable = "kek""#, "");
+        assert!(result.is_ok());
+        assert!(!RefCell::borrow(&entities().global()).properties().contains_key("stored_variable"));
+
+        let (result, _) = execute_with_input("$stored_variable", "");
+        assert_eq!(result.unwrap().try_as_string(), Some("kek".to_string()));
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        let (result, output) = execute_with_input("false && echo x", "");
+        assert!(result.is_err());
+        assert_eq!(output, "");
+
+        let (_, output) = execute_with_input("true && echo x", "");
+        assert_eq!(output, "x\n");
+
+        let (result, output) = execute_with_input("false || echo y", "");
+        assert!(result.is_ok());
+        assert_eq!(output, "y\n");
+
+        let (_, output) = execute_with_input("true || echo y", "");
+        assert_eq!(output, "");
+
+        let (_, output) = execute_with_input("false && echo x || echo z", "");
+        assert_eq!(output, "z\n");
+    }
+
+    #[test]
+    fn test_output_redirection() {
+        let dir = TempDir::new("redirection");
+        let path = dir.join("output");
+        let path = path.to_str().unwrap();
+
+        let (result, output) = execute_with_input(&format!("echo hi > {}", path), "");
+        assert!(result.is_ok());
+        assert_eq!(output, "");
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "hi\n");
+
+        let (result, _) = execute_with_input(&format!("echo there >> {}", path), "");
+        assert!(result.is_ok());
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "hi\nthere\n");
+
+        let (result, _) = execute_with_input(&format!("sh -c 'echo err >&2' 2> {}", path), "");
+        assert!(result.is_ok());
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "err\n");
+
+    }
+
+    #[test]
+    fn test_input_redirection() {
+        let (result, output) = execute_with_input("cat < Cargo.toml", "");
+
+        assert!(result.is_ok());
+        assert_eq!(output, std::fs::read_to_string("Cargo.toml").unwrap());
+    }
+
+    #[test]
+    fn test_input_redirection_missing_file() {
+        let (result, _) = execute_with_input("cat < surely_missing_file", "");
+
+        let tree = parse_line("cat < surely_missing_file").unwrap();
+        let error = result.err().unwrap();
+        let (node, data) = error.errors.iter().next().unwrap();
+        assert_eq!(tree.root().find_node(*node).unwrap().data, "surely_missing_file");
+        assert!(data.notes[0].starts_with("Cannot open surely_missing_file"), "{:?}", data.notes);
+    }
+
+    #[test]
+    fn test_redirected_command_twice() {
+        let dir = TempDir::new("redirection_twice");
+        let path = dir.join("output");
+        let path = path.to_str().unwrap();
+
+        // fds of the first run must be closed once and not affect the second one
+        for _ in 0..2 {
+            let (result, output) = execute_with_input(&format!("echo hi > {} 2> /dev/null", path), "");
+            assert!(result.is_ok(), "{:?}", result.err().map(|e| e.errors));
+            assert_eq!(output, "");
+
+            let (result, output) = execute_with_input(&format!("cat < {} | cat", path), "");
+            assert!(result.is_ok());
+            assert_eq!(output, "hi\n");
+        }
+
+    }
+
+    #[test]
+    fn test_redirection_to_missing_directory() {
+        let (result, _) = execute_with_input("echo hi > /surely/missing/dir/file", "");
+
+        let error = result.err().unwrap();
+        let notes: Vec<&String> = error.errors.values().flat_map(|e| e.notes.iter()).collect();
+        assert_eq!(notes.len(), 1);
+        assert!(notes[0].starts_with("Cannot open /surely/missing/dir/file"), "{:?}", notes);
+    }
+
+    #[test]
+    fn test_pipeline_output() {
+        let (result, output) = execute_with_input("echo hi | cat", "");
+
+        assert!(result.is_ok());
+        assert_eq!(output.trim_end(), "hi");
+    }
+
+    #[test]
+    fn test_pipeline_bigger_than_pipe_buffer() {
+        // head fills the pipe before match reads anything
+        let (result, output) = execute_with_input(r#"head -c 200000 /dev/zero | $match("n")"#, "");
+
+        assert_eq!(result.unwrap().try_as_string(), Some("".to_string()));
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn test_pipeline_terminates_with_bounded_consumer() {
+        let (result, output) = execute_with_input("yes | head -n 1", "");
+
+        assert!(result.is_ok());
+        assert_eq!(output, "y\n");
+    }
+
+    #[test]
+    fn test_pipeline_input() {
+        let (_, output) = execute_with_input("cat | cat", "kek");
+
+        assert_eq!(output, "kek");
+    }
+
+    #[test]
+    fn test_pipeline_with_null_stdin() {
+        init_entities();
+        let tree = parse_line("cat | cat").unwrap();
+        let (out_read, out_write) = pipe2(OFlag::O_CLOEXEC).unwrap();
+
+        // shell's own stdin is not touched, so the producer sees EOF right away
+        let config = ExecutionConfig {
+            std_out: Some(unsafe { OwnedFd::from_raw_fd(out_write) }),
+            pt: tree.root().id(),
+            null_stdin: true,
+            ..Default::default()
+        };
+        let result = execute(tree.root(), &config).execute();
+        drop(config);
+
+        let mut output = String::new();
+        unsafe { File::from_raw_fd(out_read) }.read_to_string(&mut output).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn test_builtin_with_null_stdin() {
+        init_entities();
+        let tree = parse_line(r#"$match("x")"#).unwrap();
+        let (out_read, out_write) = pipe2(OFlag::O_CLOEXEC).unwrap();
+
+        // builtins don't fall back to the shell's stdin either
+        let config = ExecutionConfig {
+            std_out: Some(unsafe { OwnedFd::from_raw_fd(out_write) }),
+            pt: tree.root().id(),
+            null_stdin: true,
+            ..Default::default()
+        };
+        let result = execute(tree.root(), &config).execute();
+        drop(config);
+
+        let mut output = String::new();
+        unsafe { File::from_raw_fd(out_read) }.read_to_string(&mut output).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(output, "");
+    }
+
+    fn spawn_process(config: &ExecutionConfig, line: &str) -> ProcessExecution {
+        let tree = parse_line(line).unwrap();
+        let config = ExecutionConfig { pt: tree.root().id(), ..config.try_clone().unwrap() };
+        match execute(tree.root(), &config) {
+            ExecutionState::Execution(Execution::Process(process)) => process,
+            _ => panic!("{} is not a process", line),
+        }
+    }
+
+    // polls like a runtime would, until the process is done
+    fn poll_process(process: &mut ProcessExecution) -> FoshResult<EntityRef> {
+        loop {
+            if let Some(result) = process.try_execute() {
+                return result;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_try_execute() {
+        init_entities();
+        let config = ExecutionConfig { null_stdin: true, ..Default::default() };
+
+        let mut process = spawn_process(&config, "sleep 0.3");
+        // does not wait for the process
+        assert!(process.try_execute().is_none());
+        let result = poll_process(&mut process).ok().unwrap();
+        assert_eq!(RefCell::borrow(&result).properties().get("status").unwrap().try_as_number(), Some(0.0));
+
+        let mut process = spawn_process(&config, "false");
+        assert_eq!(poll_process(&mut process).err().unwrap().status, Some(1));
+    }
+
+    #[test]
+    fn test_try_execute_timeout() {
+        init_entities();
+        let config = ExecutionConfig { null_stdin: true, deadline: Some(std::time::Instant::now()), ..Default::default() };
+        // SIGTERM stays ignored after exec, so only SIGKILL stops it
+        let mut process = spawn_process(&config, r#"sh -c "trap '' TERM; exec sleep 5""#);
+
+        // calls past the deadline only send signals, they don't wait for the process to go
+        let start = std::time::Instant::now();
+        assert!(process.try_execute().is_none());
+        assert!(process.try_execute().is_none());
+        assert!(start.elapsed() < std::time::Duration::from_millis(500));
+
+        let error = poll_process(&mut process).err().unwrap();
+        assert_eq!(error.status, Some(124));
+        assert!(start.elapsed() < std::time::Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_background_job() {
+        // the job must not hold the captured stdout, otherwise reading it waits for the job
+        let start = std::time::Instant::now();
+        let (result, output) = execute_with_input("sleep 5 > /dev/null & echo kek", "");
+        assert!(start.elapsed().as_secs() < 4);
+        assert!(result.is_ok());
+        assert_eq!(output, "kek\n");
+
+        let (result, _) = execute_with_input("sleep 5 > /dev/null &", "");
+        assert!(start.elapsed().as_secs() < 4);
+        let job = result.ok().unwrap();
+        let id = RefCell::borrow(&job).properties().get("id").unwrap().try_as_number().unwrap() as usize;
+
+        let jobs = entities().jobs();
+        let job = jobs.iter().find(|j| j.id == id).unwrap();
+        assert_eq!(job.command.trim(), "sleep 5 > /dev/null");
+        assert!(job.status.is_none());
+    }
+
+    #[test]
+    fn test_background_pipeline_and_chain() {
+        init_entities();
+        let tree = parse_line("sleep 5 | cat & sleep 5 && echo done &").unwrap();
+        // with the output captured, reading it would wait for the jobs
+        let config = ExecutionConfig {
+            std_out: Some(File::create("/dev/null").unwrap().into()),
+            std_err: Some(File::create("/dev/null").unwrap().into()),
+            pt: tree.root().id(),
+            ..Default::default()
+        };
+
+        let start = std::time::Instant::now();
+        let result = execute(tree.root(), &config).execute();
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        assert!(result.is_ok());
+
+        for command in ["sleep 5 | cat", "sleep 5 && echo done"] {
+            let pid = entities().jobs().iter().find(|j| j.command.trim() == command).unwrap().pid;
+            // everything the job started is in its group
+            nix::sys::signal::killpg(pid, nix::sys::signal::Signal::SIGKILL).unwrap();
+        }
+        loop {
+            entities().reap_jobs();
+            if entities().jobs().iter().all(|j| j.status.is_some()) {
+                break;
+            }
+            assert!(start.elapsed() < std::time::Duration::from_secs(4), "jobs did not finish");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_trailing_semicolon() {
+        let (result, output) = execute_with_input("echo a ; echo b ;", "");
+        assert!(result.is_ok());
+        assert_eq!(output, "a\nb\n");
+
+        let (result, output) = execute_with_input("${echo a ;} ;", "");
+        assert!(result.is_ok());
+        assert_eq!(output, "a\n");
+    }
+
+    #[test]
+    fn test_only_delimiters() {
+        // these are syntax errors, but executing the recovered tree must not panic
+        for line in [";", "; ;", ";;", "|", "&", "; | &"] {
+            let (_, output) = execute_with_input(line, "");
+            assert_eq!(output, "", "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_type_mismatch_uses_friendly_names() {
+        let (result, _) = execute_with_input("$cd(5)", "");
+
+        let error = result.err().unwrap();
+        let notes: Vec<&String> = error.errors.values().flat_map(|e| e.notes.iter()).collect();
+        assert_eq!(notes, vec!["Argument path is not of type string"]);
     }
 }
@@ -0,0 +1,110 @@
+use std::fmt::Write;
+use crate::builtin::engine::parse_tree::PTNode;
+use crate::parser::ast::{ASTKind, Command, Redirection};
+use crate::runtime::execution::collect_stages;
+
+// Describes how the line would be executed without executing anything.
+// Follows the same decomposition as execute_delimited/execute_background/execute_piped
+pub fn explain<'a>(command: &'a PTNode<'a>) -> String {
+    let mut result = String::new();
+    explain_node(command, 0, &mut result);
+
+    result
+}
+
+fn explain_node<'a>(node: &'a PTNode<'a>, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    match node.kind {
+        ASTKind::Delimited | ASTKind::Background | ASTKind::Piped => {
+            let mut stages = Vec::new();
+            collect_stages(node, node.kind, &mut stages);
+
+            let title = match node.kind {
+                ASTKind::Delimited => "delimited",
+                ASTKind::Background => "background",
+                _ => "piped",
+            };
+            writeln!(out, "{}{} ({} stages):", indent, title, stages.len()).unwrap();
+            for stage in stages {
+                explain_node(stage, depth + 1, out);
+            }
+        }
+        ASTKind::And | ASTKind::Or => {
+            let title = if node.kind == ASTKind::And { "and" } else { "or" };
+            writeln!(out, "{}{}:", indent, title).unwrap();
+            explain_node(node.children()[0], depth + 1, out);
+            explain_node(node.children()[2], depth + 1, out);
+        }
+        ASTKind::Redirected => {
+            explain_node(node.children()[0], depth, out);
+            for redirection in node.children().iter().skip(1) {
+                let target = redirection.value::<Redirection>().get_target(redirection);
+                writeln!(out, "{}  redirect {} {}", indent, redirection.children()[0].data, target).unwrap();
+            }
+        }
+        ASTKind::Command => {
+            let command = node.value::<Command>();
+            writeln!(out, "{}command {} {:?}", indent, command.get_name(node), command.get_arguments(node)).unwrap();
+        }
+        ASTKind::Function => {
+            if let Some(block) = node.children().get(2).filter(|b| b.kind == ASTKind::BracedCommand) {
+                writeln!(out, "{}with input:", indent).unwrap();
+                explain_value(node.children()[1], depth + 1, out);
+                explain_value(block, depth, out);
+            } else {
+                explain_value(node.children()[1], depth, out);
+            }
+        }
+        _ => {
+            writeln!(out, "{}{} `{}`", indent, node.kind, node.data).unwrap();
+        }
+    }
+}
+
+fn explain_value<'a>(node: &'a PTNode<'a>, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    match node.kind {
+        ASTKind::StringLiteral | ASTKind::NumberLiteral | ASTKind::BoolLiteral => {
+            writeln!(out, "{}value {}", indent, node.data).unwrap();
+        }
+        ASTKind::EnvironmentVariable => {
+            writeln!(out, "{}environment variable {}", indent, node.data).unwrap();
+        }
+        ASTKind::PropertyInsn => {
+            writeln!(out, "{}property {}", indent, node.data).unwrap();
+        }
+        ASTKind::PropertyCall => {
+            let args = node.find_child_with_kind(ASTKind::ParenthesizedArgumentsList)
+                .map(|x| x.children().iter().filter(|a| a.kind == ASTKind::Parameter).count())
+                .unwrap_or(0);
+            writeln!(out, "{}call {} with {} arguments", indent, node.children()[0].data, args).unwrap();
+        }
+        ASTKind::BracedCommand => {
+            writeln!(out, "{}braced command:", indent).unwrap();
+            explain_node(node.children()[1], depth + 1, out);
+        }
+        _ => {
+            writeln!(out, "{}{} `{}`", indent, node.kind, node.data).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtin::engine::parse_tree::parse_line;
+    use super::*;
+
+    #[test]
+    fn test_explain_pipeline_and_sequence() {
+        let tree = parse_line("a | b ; c").unwrap();
+
+        let expected = "\
+delimited (2 stages):
+  piped (2 stages):
+    command a []
+    command b []
+  command c []
+";
+        assert_eq!(explain(tree.root()), expected);
+    }
+}
@@ -1 +1,3 @@
-pub mod execution;
\ No newline at end of file
+pub mod execution;
+pub mod explain;
+pub mod terminal;
\ No newline at end of file
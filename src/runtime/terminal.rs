@@ -0,0 +1,89 @@
+use nix::libc::STDIN_FILENO;
+use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::unistd::{getpgrp, isatty, Pid, tcgetpgrp, tcsetpgrp};
+
+// Hands the terminal to the given process group and gives it back to the shell on drop,
+// so the shell never loses the terminal even if waiting fails or panics.
+// Does nothing if stdin is not a terminal or the terminal is not ours to give, like in a background job
+pub struct ForegroundGuard {
+    shell: Option<Pid>,
+}
+
+impl ForegroundGuard {
+    pub fn new(pgid: Pid) -> Self {
+        if !isatty(STDIN_FILENO).unwrap_or(false) {
+            return Self { shell: None };
+        }
+        let shell = match tcgetpgrp(STDIN_FILENO) {
+            Ok(p) => p,
+            Err(_) => return Self { shell: None },
+        };
+        if shell == pgid || shell != getpgrp() || tcsetpgrp(STDIN_FILENO, pgid).is_err() {
+            return Self { shell: None };
+        }
+
+        Self { shell: Some(shell) }
+    }
+}
+
+impl Drop for ForegroundGuard {
+    fn drop(&mut self) {
+        if let Some(shell) = self.shell {
+            // shell is in background at this point so tcsetpgrp would stop it with SIGTTOU
+            let old = unsafe { signal(Signal::SIGTTOU, SigHandler::SigIgn) };
+            let _ = tcsetpgrp(STDIN_FILENO, shell);
+            if let Ok(old) = old {
+                unsafe { let _ = signal(Signal::SIGTTOU, old); }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::execution::tests::execute_with_input;
+    use super::*;
+
+    #[test]
+    fn test_foreground_restored_after_command() {
+        let before = tcgetpgrp(STDIN_FILENO).ok();
+
+        assert!(execute_with_input("true", "").0.is_ok());
+        assert_eq!(tcgetpgrp(STDIN_FILENO).ok(), before);
+
+        assert!(execute_with_input("false", "").0.is_err());
+        assert_eq!(tcgetpgrp(STDIN_FILENO).ok(), before);
+    }
+
+    #[test]
+    fn test_command_gets_own_process_group() {
+        let (result, output) = execute_with_input(r#"sh -c 'echo $$ $(cut -d" " -f5 /proc/$$/stat)'"#, "");
+        assert!(result.is_ok());
+
+        let ids: Vec<&str> = output.split_whitespace().collect();
+        assert_eq!(ids.len(), 2, "{:?}", output);
+        // group leader of its own group
+        assert_eq!(ids[0], ids[1]);
+        assert_ne!(ids[1], getpgrp().to_string());
+    }
+
+    #[test]
+    fn test_pipeline_shares_process_group() {
+        let stat = r#"cut -d" " -f5 /proc/$$/stat"#;
+        let (result, output) = execute_with_input(&format!("sh -c '{0}' | sh -c 'cat; {0}' | sh -c 'cat; {0}'", stat), "");
+        assert!(result.is_ok());
+
+        let groups: Vec<&str> = output.lines().collect();
+        assert_eq!(groups.len(), 3, "{:?}", output);
+        assert!(groups.iter().all(|g| *g == groups[0]), "{:?}", groups);
+        assert_ne!(groups[0], getpgrp().to_string());
+    }
+
+    #[test]
+    fn test_guard_for_own_group_is_noop() {
+        let before = tcgetpgrp(STDIN_FILENO).ok();
+        drop(ForegroundGuard::new(getpgrp()));
+
+        assert_eq!(tcgetpgrp(STDIN_FILENO).ok(), before);
+    }
+}
@@ -0,0 +1,486 @@
+use std::borrow::Cow;
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::ops::Range;
+use crate::error_printer::{ErrorReport, ErrorType};
+use crate::builtin::annotator::{annotate_absent_argument, downcast_to_annotator};
+use crate::builtin::engine::annotator::{AnnotationsSink, Completion};
+use crate::builtin::engine::entities::{EntitiesManager, EntityExecutionError, EntityRef, ExecutionConfig, FoshEntity, FoshResult};
+use crate::builtin::engine::Value;
+use crate::builtin::engine::parse_tree::{parse_line, ParseMode, ParseTree, PTNode, PTNodeId, syntax_errors};
+use crate::builtin::entities::initialize_universe;
+use crate::parser::ast::ASTKind;
+use crate::runtime::execution::execute_catching;
+use crate::ui::settings::TUISettings;
+use crate::{construct_error_report, set_entities};
+
+pub enum RunError<'a> {
+    Syntax(Vec<ErrorReport<'a>>),
+    Execution(Vec<ErrorReport<'a>>),
+}
+
+impl<'a> RunError<'a> {
+    pub fn reports(&self) -> &Vec<ErrorReport<'a>> {
+        match self {
+            RunError::Syntax(r) => r,
+            RunError::Execution(r) => r,
+        }
+    }
+
+    pub fn into_reports(self) -> Vec<ErrorReport<'a>> {
+        match self {
+            RunError::Syntax(r) => r,
+            RunError::Execution(r) => r,
+        }
+    }
+
+    pub fn into_owned(self) -> RunError<'static> {
+        match self {
+            RunError::Syntax(r) => RunError::Syntax(r.into_iter().map(ErrorReport::into_owned).collect()),
+            RunError::Execution(r) => RunError::Execution(r.into_iter().map(ErrorReport::into_owned).collect()),
+        }
+    }
+}
+
+// Parse -> annotate -> highlight -> execute pipeline in one place.
+// TUI and anything else driving the shell should go through it
+pub struct Session {
+    entities: &'static EntitiesManager,
+    settings: RefCell<TUISettings>,
+    history: RefCell<Vec<String>>,
+    mode: ParseMode,
+    null_stdin: bool,
+}
+
+// same as Session::new, so it leaks a universe too
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+impl Session {
+    // Creates a fresh universe and makes it current on this thread only, other threads keep theirs.
+    // Every session makes its own universe current again before it parses or executes, so creating
+    // another one doesn't take over existing sessions. The universe lives as long as the program,
+    // because entities are handed out as &'static: each call leaks one, so make a session per shell, not per line
+    pub fn new() -> Self {
+        let entities: &'static EntitiesManager = Box::leak(Box::new(EntitiesManager::new()));
+        set_entities(entities);
+        initialize_universe(entities);
+
+        Self::with_entities(entities)
+    }
+
+    pub fn with_entities(entities: &'static EntitiesManager) -> Self {
+        Self {
+            entities,
+            settings: RefCell::new(TUISettings::new()),
+            history: RefCell::new(Vec::new()),
+            mode: ParseMode::Lenient,
+            null_stdin: false,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    // Commands executed by run() read /dev/null instead of the shell's stdin, see ExecutionConfig::null_stdin
+    pub fn with_null_stdin(mut self, null_stdin: bool) -> Self {
+        self.null_stdin = null_stdin;
+        self
+    }
+
+    pub fn entities(&self) -> &'static EntitiesManager {
+        self.entities
+    }
+
+    pub fn settings(&self) -> &RefCell<TUISettings> {
+        &self.settings
+    }
+
+    pub fn history(&self) -> Ref<'_, Vec<String>> {
+        self.history.borrow()
+    }
+
+    pub fn mode(&self) -> ParseMode {
+        self.mode
+    }
+
+    // entities() is one per thread, so another session created on this thread since must not be the one
+    // this session parses and executes against
+    fn make_current(&self) {
+        set_entities(self.entities);
+    }
+
+    // returns span of the completed node and its completions
+    pub fn complete(&self, line: &str, pos: usize) -> Option<(Range<usize>, Vec<Completion>)> {
+        let tree = parse_line(line)?;
+        self.complete_tree(&tree, pos)
+    }
+
+    // Same as complete for a line which is already parsed
+    pub fn complete_tree<'b>(&self, tree: &'b ParseTree<'b>, pos: usize) -> Option<(Range<usize>, Vec<Completion>)> {
+        self.make_current();
+        let mut nodes = Vec::new();
+        tree.collect(&mut nodes, |a| a.origin.span.start() <= pos && a.origin.span.end() >= pos);
+
+        // deepest nodes come last
+        for node in nodes.into_iter().rev() {
+            let sink = annotate(node);
+            if !sink.completions.is_empty() {
+                return self.limit_completions(node.origin.span.as_range(), sink);
+            }
+        }
+
+        // In whitespace like after `$cd( ` no node is under the cursor. The nearest leaf before it tells
+        // which arguments list is still open there, and a new argument is inserted right at the cursor
+        let mut node = tree.root().find_nearest_leaf(pos);
+        while let Some(current) = node {
+            if current.kind == ASTKind::ParenthesizedArgumentsList && is_open_at(current, pos) {
+                let mut sink = AnnotationsSink::new();
+                annotate_absent_argument(current, pos, &mut sink);
+                if sink.completions.is_empty() {
+                    return None;
+                }
+                return self.limit_completions(pos..pos, sink);
+            }
+            node = current.parent();
+        }
+
+        None
+    }
+
+    fn limit_completions(&self, span: Range<usize>, mut sink: AnnotationsSink) -> Option<(Range<usize>, Vec<Completion>)> {
+        sink.sort_completions();
+        sink.completions.truncate(self.settings.borrow().max_completions());
+        // menu is never built for nothing, limit of 0 turns completion off
+        if sink.completions.is_empty() {
+            return None;
+        }
+        Some((span, sink.completions))
+    }
+
+    pub fn highlight(&self, line: &str) -> String {
+        match parse_line(line) {
+            Some(tree) => self.highlight_tree(&tree, line),
+            None => line.to_string(),
+        }
+    }
+
+    // Same as highlight for a line which is already parsed
+    pub fn highlight_tree<'b>(&self, tree: &'b ParseTree<'b>, line: &str) -> String {
+        self.make_current();
+        let mut insertions = HashMap::<usize, Vec<String>>::new();
+        let mut result = String::new();
+
+        tree.root().walk(&mut |node| {
+            let sink = annotate(node);
+
+            insertions.entry(node.origin.span.start()).or_default()
+                .push(node.origin.value.kind().color_string());
+
+            for x in sink.colors() {
+                insertions.entry(node.origin.span.start()).or_default()
+                    .push(self.settings.borrow().color_scheme().get(x).to_string());
+            }
+
+            insertions.entry(node.origin.span.end()).or_default()
+                .push(termion::color::Fg(termion::color::Reset).to_string());
+        });
+
+        result.push_str(&termion::color::Bg(termion::color::Reset).to_string());
+        result.push_str(&termion::color::Fg(termion::color::Reset).to_string());
+        for (i, s) in line.chars().enumerate() {
+            if let Some(insertions) = insertions.get(&i) {
+                for ins in insertions {
+                    result.push_str(ins);
+                }
+            }
+            result.push(s);
+        }
+        result.push_str(&termion::color::Bg(termion::color::Reset).to_string());
+        result.push_str(&termion::color::Fg(termion::color::Reset).to_string());
+
+        result
+    }
+
+    // Replaces command names which are aliases with their expansions.
+    // Expansions are inserted as is, so quoting inside them is kept, and are not expanded again
+    pub fn expand_aliases<'l>(&self, line: &'l str) -> Cow<'l, str> {
+        let tree = match parse_line(line) {
+            Some(tree) => tree,
+            None => return Cow::Borrowed(line),
+        };
+
+        let mut names = Vec::new();
+        tree.collect(&mut names, |n| n.kind == ASTKind::CommandName && n.origin.value.kind() != ASTKind::Error);
+
+        let mut result = line.to_string();
+        let mut expanded = false;
+        // from the end, so spans of the remaining names stay valid
+        for name in names.into_iter().rev() {
+            if let Some(expansion) = self.entities.get_alias(name.data) {
+                result.replace_range(name.origin.span.as_range(), &expansion);
+                expanded = true;
+            }
+        }
+
+        if expanded { Cow::Owned(result) } else { Cow::Borrowed(line) }
+    }
+
+    // Executes line with the shell's own stdio and records it in the history
+    // History gets the line as typed, aliases are expanded only for execution.
+    // Errors of a line with expanded aliases point into the expanded line
+    pub fn run<'l>(&self, line: &'l str) -> Result<EntityRef, RunError<'l>> {
+        self.make_current();
+        self.history.borrow_mut().push(line.to_string());
+
+        match self.expand_aliases(line) {
+            Cow::Borrowed(line) => self.run_expanded(line),
+            Cow::Owned(line) => self.run_expanded(&line).map_err(RunError::into_owned),
+        }
+    }
+
+    fn run_expanded<'l>(&self, line: &'l str) -> Result<EntityRef, RunError<'l>> {
+        // errors are never executed, mode only tells the caller how serious they are
+        let tree = match parse_line(line) {
+            Some(tree) => tree,
+            None => {
+                let mut report = ErrorReport::new(0..line.len(), line, ErrorType::Syntax);
+                report.add_note("Cannot parse line");
+                return Err(RunError::Syntax(vec![report]));
+            }
+        };
+        let errors = syntax_errors(line, tree.ast());
+        if !errors.is_empty() {
+            return Err(RunError::Syntax(errors));
+        }
+
+        let config = ExecutionConfig {
+            pt: tree.root().id(),
+            null_stdin: self.null_stdin,
+            ..Default::default()
+        };
+        let result = execute_catching(tree.root(), &config);
+        self.set_status(&result);
+
+        result.map_err(|e| RunError::Execution(construct_error_report(line, tree.root(), &e)))
+    }
+
+    // Executes line with streams of the config, for programs embedding the shell. Unlike run, it needs no TTY
+    // and leaves the history alone. Syntax errors are reported at the root as they have no nodes to point to
+    pub fn eval(&self, line: &str, config: ExecutionConfig) -> Result<EntityRef, EntityExecutionError> {
+        self.make_current();
+        let tree = match parse_line(line) {
+            Some(tree) => tree,
+            None => return Err(EntityExecutionError::new_single(PTNodeId::default(), ErrorType::Syntax, "Cannot parse line")),
+        };
+        let errors = syntax_errors(line, tree.ast());
+        if !errors.is_empty() {
+            let mut error = EntityExecutionError::new();
+            error.with_error(tree.root().id(), ErrorType::Syntax)
+                .with_notes(errors.into_iter().map(|r| r.to_string()).collect());
+            return Err(error);
+        }
+
+        let config = ExecutionConfig { pt: tree.root().id(), ..config };
+        let result = execute_catching(tree.root(), &config);
+        self.set_status(&result);
+
+        result
+    }
+
+    // makes the status of the last command available as `$?` and `$status`
+    fn set_status(&self, result: &FoshResult<EntityRef>) {
+        let status = match result {
+            Ok(entity) => entity.borrow().properties().get("status")
+                .and_then(|s| s.try_as_number())
+                .map_or(0, |s| s as i32),
+            Err(e) => e.status.unwrap_or(1),
+        };
+
+        let status = Value::Number(status as f64).into_entity();
+        self.entities.set_variable("?", status.clone());
+        self.entities.set_variable("status", status);
+    }
+}
+
+// the cursor is before the closing paren of the list, or the list isn't closed at all
+fn is_open_at<'b>(list: &'b PTNode<'b>, pos: usize) -> bool {
+    match list.find_child_with_kind(ASTKind::CloseParen) {
+        Some(close) => close.origin.value.kind() == ASTKind::Error || pos <= close.origin.span.start(),
+        None => true,
+    }
+}
+
+fn annotate<'b>(node: &'b PTNode<'b>) -> AnnotationsSink {
+    let mut sink = AnnotationsSink::new();
+    if let Some(annotator) = downcast_to_annotator(node) {
+        annotator.annotate(node, &mut sink);
+    }
+
+    sink
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtin::annotator::tests::init_entities;
+    use crate::builtin::engine::entities::FoshEntity;
+    use crate::entities;
+    use super::*;
+
+    fn session() -> Session {
+        init_entities();
+        Session::with_entities(entities())
+    }
+
+    #[test]
+    fn test_complete() {
+        let session = session();
+
+        let (span, completions) = session.complete("$ec", 3).unwrap();
+        assert_eq!(span, 1..3);
+        assert_eq!(completions, vec![Completion::new("echo").with_description("Echo call")]);
+    }
+
+    #[test]
+    fn test_complete_absent_argument() {
+        let session = session();
+
+        // tests run in the crate root
+        let (span, completions) = session.complete("$cd( ", 5).unwrap();
+        assert_eq!(span, 5..5);
+        assert!(completions.contains(&Completion::new(r#""src/""#)), "{:?}", completions);
+        assert_eq!(session.complete("$cd(", 4).unwrap().0, 4..4);
+
+        assert!(session.complete("$cd() ", 6).is_none());
+        assert!(session.complete(r#"$cd("/"  "#, 9).is_none());
+    }
+
+    #[test]
+    fn test_completion_limit() {
+        let session = session();
+        let names = |session: &Session| session.complete("$e", 2).unwrap().1.into_iter().map(|c| c.text).collect::<Vec<_>>();
+
+        let all = names(&session);
+        assert!(all.len() > 2, "{:?}", all);
+
+        session.settings().borrow_mut().set_max_completions(2);
+        assert_eq!(names(&session), all[..2]);
+
+        session.settings().borrow_mut().set_max_completions(0);
+        assert!(session.complete("$e", 2).is_none());
+    }
+
+    #[test]
+    fn test_highlight() {
+        let session = session();
+
+        let highlighted = session.highlight("$echo");
+        assert!(highlighted.contains("echo"));
+        assert!(highlighted.contains(&termion::color::Fg(termion::color::Reset).to_string()));
+        assert_ne!(highlighted, "$echo");
+    }
+
+    #[test]
+    fn test_run() {
+        let session = session();
+
+        let result = session.run("$PATH").ok().unwrap();
+        assert_eq!(result.try_as_string(), Some(std::env::var("PATH").unwrap()));
+
+        assert!(matches!(session.run("$cd(\"kek\""), Err(RunError::Syntax(_))));
+        assert!(matches!(session.run("$cd(5)"), Err(RunError::Execution(_))));
+
+        assert_eq!(*session.history(), vec!["$PATH", "$cd(\"kek\"", "$cd(5)"]);
+    }
+
+    #[test]
+    fn test_syntax_error_location() {
+        let session = session();
+
+        let reports = match session.run("$cd(\"kek\"") {
+            Err(RunError::Syntax(reports)) => reports,
+            _ => panic!("syntax error expected"),
+        };
+        assert_eq!(reports.len(), 1);
+        // missing `)` right after the last argument
+        let report = reports[0].to_string();
+        assert!(report.starts_with("error: Syntax\n1 | $cd(\"kek\"\n  |          ^\n"), "{}", report);
+        assert!(report.ends_with("note: Expected CloseParen\n"), "{}", report);
+        // the parser could also take another argument there
+        let hint = report.lines().find(|l| l.starts_with("hint: expected one of: ")).unwrap();
+        assert!(hint.contains("`)`") && hint.contains("`number`"), "{}", hint);
+    }
+
+    #[test]
+    fn test_aliases() {
+        let session = session();
+
+        assert!(session.run(r#"$alias("fosh_ll" "ls -la")"#).is_ok());
+        assert!(session.run(r#"$alias("fosh_say" "echo 'a  b' | fosh_ll")"#).is_ok());
+
+        assert_eq!(session.expand_aliases("fosh_ll"), "ls -la");
+        assert_eq!(session.expand_aliases("fosh_ll /tmp | fosh_ll"), "ls -la /tmp | ls -la");
+        // quoting is kept and expansion is not expanded again
+        assert_eq!(session.expand_aliases("fosh_say"), "echo 'a  b' | fosh_ll");
+        // only command names are expanded
+        assert_eq!(session.expand_aliases("echo fosh_ll"), "echo fosh_ll");
+
+        // run expands them, history keeps the line as typed
+        assert!(session.run("fosh_ll").is_ok());
+        assert_eq!(session.history().last().unwrap(), "fosh_ll");
+
+        assert!(session.run(r#"$alias("fosh_bad" "$cd(5)")"#).is_ok());
+        let reports = match session.run("fosh_bad") {
+            Err(RunError::Execution(reports)) => reports,
+            _ => panic!("fosh_bad must fail"),
+        };
+        assert!(reports[0].to_string().contains("$cd(5)"), "{}", reports[0]);
+    }
+
+    #[test]
+    fn test_null_stdin() {
+        let reads_null = "sh -c '[ \"$(readlink /proc/self/fd/0)\" = /dev/null ]'";
+
+        let session = session().with_null_stdin(true);
+        assert!(session.run(reads_null).is_ok());
+
+        // background jobs never get the terminal
+        let session = super::tests::session();
+        assert!(session.run(&format!("{} &", reads_null)).is_ok());
+        let start = std::time::Instant::now();
+        let status = loop {
+            session.entities().reap_jobs();
+            if let Some(status) = session.entities().jobs().last().unwrap().status {
+                break status;
+            }
+            assert!(start.elapsed() < std::time::Duration::from_secs(5), "job did not finish");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_status() {
+        let session = session();
+        let status = || session.entities().get_variable("?").unwrap().try_as_number();
+
+        assert!(session.run("true").is_ok());
+        assert_eq!(status(), Some(0.0));
+
+        assert!(session.run("sh -c 'exit 3'").is_err());
+        assert_eq!(status(), Some(3.0));
+        assert_eq!(session.entities().get_variable("status").unwrap().try_as_number(), Some(3.0));
+
+        assert!(session.run("true | false").is_err());
+        assert_eq!(status(), Some(1.0));
+
+        assert!(session.run("$cd(5)").is_err());
+        assert_eq!(session.run("$?").ok().unwrap().try_as_number(), Some(1.0));
+        assert_eq!(status(), Some(0.0));
+    }
+}
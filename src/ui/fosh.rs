@@ -1,3 +0,0 @@
-pub struct Fosh {
-    
-}
\ No newline at end of file
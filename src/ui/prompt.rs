@@ -0,0 +1,148 @@
+use unicode_width::UnicodeWidthChar;
+use crate::builtin::engine::entities::FoshEntity;
+use crate::session::Session;
+
+pub enum PromptSegment {
+    Literal(String),
+    // `\w`, current directory with $HOME abbreviated to `~`
+    WorkingDirectory,
+    // `\$?`, exit status of the last command
+    Status,
+}
+
+// Prompt made of segments which are evaluated every time it's rendered
+pub struct Prompt {
+    segments: Vec<PromptSegment>,
+}
+
+impl Prompt {
+    pub fn literal<S: Into<String>>(text: S) -> Self {
+        Self {
+            segments: vec![PromptSegment::Literal(text.into())],
+        }
+    }
+
+    // `\w` and `\$?` are replaced with segments, `\\` is a backslash. Everything else is literal text
+    pub fn parse(template: &str) -> Self {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+
+        let mut rest = template;
+        while let Some(c) = rest.chars().next() {
+            let segment = if rest.starts_with("\\w") {
+                Some((PromptSegment::WorkingDirectory, 2))
+            } else if rest.starts_with("\\$?") {
+                Some((PromptSegment::Status, 3))
+            } else if rest.starts_with("\\\\") {
+                literal.push('\\');
+                rest = &rest[2..];
+                continue;
+            } else {
+                None
+            };
+
+            match segment {
+                Some((segment, len)) => {
+                    if !literal.is_empty() {
+                        segments.push(PromptSegment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(segment);
+                    rest = &rest[len..];
+                }
+                None => {
+                    literal.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(PromptSegment::Literal(literal));
+        }
+
+        Self { segments }
+    }
+
+    pub fn render(&self, session: &Session) -> String {
+        let mut result = String::new();
+        for segment in &self.segments {
+            match segment {
+                PromptSegment::Literal(text) => result.push_str(text),
+                PromptSegment::WorkingDirectory => result.push_str(&working_directory()),
+                PromptSegment::Status => {
+                    let status = session.entities().get_variable("?")
+                        .and_then(|s| s.try_as_number())
+                        .map_or(0, |s| s as i32);
+                    result.push_str(&status.to_string());
+                }
+            }
+        }
+
+        result
+    }
+}
+
+fn working_directory() -> String {
+    let dir = match std::env::current_dir() {
+        Ok(dir) => dir.to_string_lossy().to_string(),
+        Err(_) => return "?".to_string(),
+    };
+
+    match std::env::var("HOME") {
+        Ok(home) if !home.is_empty() && (dir == home || dir.starts_with(&format!("{}/", home))) => {
+            format!("~{}", &dir[home.len()..])
+        }
+        _ => dir,
+    }
+}
+
+// Width of the text on the terminal. Escape sequences like colors take no space
+pub fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1B' {
+            // CSI sequence ends with a byte from `@` to `~`
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) { break; }
+                }
+            }
+            continue;
+        }
+        width += c.width().unwrap_or(0);
+    }
+
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtin::annotator::tests::init_entities;
+    use crate::builtin::engine::Value;
+    use crate::builtin::engine::entities::EntitiesManager;
+    use super::*;
+
+    #[test]
+    fn test_prompt_template() {
+        init_entities();
+        let session = Session::with_entities(Box::leak(Box::new(EntitiesManager::new())));
+
+        assert_eq!(Prompt::parse("[\\$?] >> ").render(&session), "[0] >> ");
+
+        session.entities().set_variable("?", Value::Number(3.0).into_entity());
+        assert_eq!(Prompt::parse("\\$?\\\\w").render(&session), "3\\w");
+
+        let dir = std::env::current_dir().unwrap().to_string_lossy().to_string();
+        let rendered = Prompt::parse("\\w $ ").render(&session);
+        assert!(rendered.ends_with(" $ "));
+        assert!(rendered == format!("{} $ ", dir) || rendered.starts_with('~'), "{}", rendered);
+    }
+
+    #[test]
+    fn test_visible_width() {
+        let colored = format!("{}>>{} ", termion::color::Fg(termion::color::Red), termion::color::Fg(termion::color::Reset));
+
+        assert_eq!(visible_width(&colored), 3);
+        assert_eq!(visible_width("~/проект> "), 10);
+    }
+}
@@ -1,4 +1,6 @@
 use std::collections::HashMap;
+use std::path::Path;
+use crate::builtin::paths::expand_tilde;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ColorType {
@@ -12,10 +14,35 @@ pub enum ColorType {
     AbsentCommandName,
 }
 
+impl ColorType {
+    // names used in colors.toml, same as variant names
+    pub fn from_name(name: &str) -> Option<ColorType> {
+        match name {
+            "Error" => Some(ColorType::Error),
+            "CommandDelimiters" => Some(ColorType::CommandDelimiters),
+            "Dollar" => Some(ColorType::Dollar),
+            "Property" => Some(ColorType::Property),
+            "String" => Some(ColorType::String),
+            "Number" => Some(ColorType::Number),
+            "CommandName" => Some(ColorType::CommandName),
+            "AbsentCommandName" => Some(ColorType::AbsentCommandName),
+            _ => None,
+        }
+    }
+}
+
+pub const COLORS_CONFIG: &str = "~/.config/fosh/colors.toml";
+
 pub struct ColorScheme {
     data: HashMap<ColorType, String>,
 }
 
+impl Default for ColorScheme {
+    fn default() -> Self {
+        ColorScheme::new()
+    }
+}
+
 impl ColorScheme {
     pub fn new() -> Self {
         let mut data: HashMap<ColorType, String> = HashMap::new();
@@ -26,34 +53,197 @@ impl ColorScheme {
         data.insert(ColorType::Dollar, Fg(Yellow).to_string());
         data.insert(ColorType::Property, Fg(LightYellow).to_string());
         data.insert(ColorType::String, Fg(LightGreen).to_string());
-        data.insert(ColorType::Number, Fg(LightGreen).to_string());
-        data.insert(ColorType::CommandName, Fg(LightGreen).to_string());
-        data.insert(ColorType::AbsentCommandName, Fg(LightGreen).to_string());
+        data.insert(ColorType::Number, Fg(LightCyan).to_string());
+        data.insert(ColorType::CommandName, Fg(Green).to_string());
+        data.insert(ColorType::AbsentCommandName, Fg(LightRed).to_string());
 
-        return Self {
+        Self {
             data
+        }
+    }
+
+    // Built-in colors overridden by the file. Problems with the file are printed as warnings
+    pub fn from_file(path: &Path) -> Self {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::new(),
+            Err(e) => {
+                eprintln!("warning: cannot read {}: {}", path.display(), e);
+                return Self::new();
+            }
         };
+
+        let (scheme, warnings) = Self::from_toml(&text);
+        for warning in warnings {
+            eprintln!("warning: {}: {}", path.display(), warning);
+        }
+
+        scheme
+    }
+
+    // Returns the scheme and descriptions of ignored entries
+    pub fn from_toml(text: &str) -> (Self, Vec<String>) {
+        let mut scheme = Self::new();
+        let mut warnings = Vec::new();
+
+        let table = match text.parse::<toml::Value>() {
+            Ok(toml::Value::Table(table)) => table,
+            Ok(_) => {
+                warnings.push("expected a table".to_string());
+                return (scheme, warnings);
+            }
+            Err(e) => {
+                warnings.push(e.to_string());
+                return (scheme, warnings);
+            }
+        };
+
+        for (name, value) in table {
+            let color_type = match ColorType::from_name(&name) {
+                Some(t) => t,
+                None => {
+                    warnings.push(format!("unknown color type `{}`", name));
+                    continue;
+                }
+            };
+            let color = match value.as_str().map(parse_color) {
+                Some(Some(color)) => color,
+                _ => {
+                    warnings.push(format!("invalid color for `{}`: {}", name, value));
+                    continue;
+                }
+            };
+            scheme.data.insert(color_type, color);
+        }
+
+        (scheme, warnings)
     }
 
     pub fn get(&self, color_type: &ColorType) -> &str {
-        return self.data.get(color_type).map(|a| a.as_str()).unwrap_or("");
+        self.data.get(color_type).map(|a| a.as_str()).unwrap_or("")
     }
 
 }
 
+// Named color like `light_red` or `#rrggbb`. Returns foreground escape sequence
+pub fn parse_color(spec: &str) -> Option<String> {
+    use termion::color::*;
+
+    if let Some(hex) = spec.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(Fg(Rgb(component(0)?, component(2)?, component(4)?)).to_string());
+    }
+
+    let color = match spec.to_lowercase().replace('-', "_").as_str() {
+        "black" => Fg(Black).to_string(),
+        "red" => Fg(Red).to_string(),
+        "green" => Fg(Green).to_string(),
+        "yellow" => Fg(Yellow).to_string(),
+        "blue" => Fg(Blue).to_string(),
+        "magenta" => Fg(Magenta).to_string(),
+        "cyan" => Fg(Cyan).to_string(),
+        "white" => Fg(White).to_string(),
+        "light_black" => Fg(LightBlack).to_string(),
+        "light_red" => Fg(LightRed).to_string(),
+        "light_green" => Fg(LightGreen).to_string(),
+        "light_yellow" => Fg(LightYellow).to_string(),
+        "light_blue" => Fg(LightBlue).to_string(),
+        "light_magenta" => Fg(LightMagenta).to_string(),
+        "light_cyan" => Fg(LightCyan).to_string(),
+        "light_white" => Fg(LightWhite).to_string(),
+        _ => return None,
+    };
+
+    Some(color)
+}
+
 pub struct TUISettings {
     color_scheme: ColorScheme,
+    continuation_prompt: String,
+    // completions past this many are dropped, after sorting
+    max_completions: usize,
+}
+
+impl Default for TUISettings {
+    fn default() -> Self {
+        TUISettings::new()
+    }
 }
 
 impl TUISettings {
     pub fn new() -> Self {
-        return Self {
+        Self {
             color_scheme: ColorScheme::new(),
-        };
+            continuation_prompt: "... ".to_string(),
+            max_completions: 20,
+        }
+    }
+
+    // Settings with user's colors from COLORS_CONFIG
+    pub fn load() -> Self {
+        let mut settings = Self::new();
+        settings.color_scheme = ColorScheme::from_file(Path::new(expand_tilde(COLORS_CONFIG).as_ref()));
+
+        settings
     }
 
     pub fn color_scheme(&self) -> &ColorScheme {
         &self.color_scheme
     }
+
+    pub fn continuation_prompt(&self) -> &str {
+        &self.continuation_prompt
+    }
+
+    pub fn set_continuation_prompt<S: Into<String>>(&mut self, prompt: S) {
+        self.continuation_prompt = prompt.into();
+    }
+
+    pub fn max_completions(&self) -> usize {
+        self.max_completions
+    }
+
+    pub fn set_max_completions(&mut self, max: usize) {
+        self.max_completions = max;
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use termion::color::*;
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("red"), Some(Fg(Red).to_string()));
+        assert_eq!(parse_color("Light-Blue"), Some(Fg(LightBlue).to_string()));
+        assert_eq!(parse_color("#ff8000"), Some(Fg(Rgb(255, 128, 0)).to_string()));
+        assert_eq!(parse_color("#ff80"), None);
+        assert_eq!(parse_color("#gg8000"), None);
+        assert_eq!(parse_color("purple"), None);
+    }
+
+    #[test]
+    fn test_scheme_from_toml() {
+        let (scheme, warnings) = ColorScheme::from_toml(r##"
+Number = "#00ffff"
+String = "purple"
+Keyword = "red"
+Error = 5
+"##);
+
+        assert_eq!(scheme.get(&ColorType::Number), Fg(Rgb(0, 255, 255)).to_string());
+        // invalid and unspecified entries keep defaults
+        assert_eq!(scheme.get(&ColorType::String), ColorScheme::new().get(&ColorType::String));
+        assert_eq!(scheme.get(&ColorType::Error), ColorScheme::new().get(&ColorType::Error));
+        assert_eq!(scheme.get(&ColorType::Dollar), ColorScheme::new().get(&ColorType::Dollar));
+        assert_eq!(warnings.len(), 3, "{:?}", warnings);
+
+        let (_, warnings) = ColorScheme::from_toml("Number = ");
+        assert_eq!(warnings.len(), 1);
+    }
+}
@@ -1,23 +1,16 @@
 use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::fmt::Formatter;
 use std::io;
-use std::io::{Read, stdin, Stdout, Write};
-use rand::distributions::Open01;
-use termion::event::Key;
+use std::io::{BufRead, Read, stdin, Stdout, Write};
+use std::ops::Range;
+use termion::event::{Event, Key, MouseButton, MouseEvent};
 use termion::input::TermRead;
-use termion::is_tty;
 use termion::raw::{IntoRawMode, RawTerminal};
-use crate::builtin::annotator::downcast_to_annotator;
-use crate::builtin::engine::annotator::{AnnotationsSink, Annotator};
-use crate::builtin::engine::parse_tree::{parse_line, ParseTree, PTNode};
-use crate::parser;
-use crate::ui::settings::TUISettings;
-
-macro_rules! csi {
-    ($( $l:expr ),*) => { concat!("\x1B[", $( $l ),*) };
-}
+use crate::builtin::engine::annotator::Completion;
+use crate::builtin::engine::parse_tree::parse_line;
+use crate::session::Session;
+use crate::ui::prompt::{Prompt, visible_width};
+use unicode_width::UnicodeWidthChar;
 
 #[derive(parse_display_derive::Display)]
 #[display("{}")]
@@ -82,51 +75,187 @@ pub enum CSIControlCodes {
 
     #[display("\x1B[{0} q")]
     SetCursorStyle(CursorMode),
+
+    #[display("\x1B[?2004h")]
+    EnableBracketedPaste,
+
+    #[display("\x1B[?2004l")]
+    DisableBracketedPaste,
+
+    #[display("\x1B[?1000h")]
+    EnableMouseReporting,
+
+    #[display("\x1B[?1000l")]
+    DisableMouseReporting,
+
+    // answered with `ESC [ row ; column R` among the input
+    #[display("\x1B[6n")]
+    RequestCursorPosition,
+}
+
+// Sequences around pasted text while bracketed paste is enabled
+const PASTE_START: &[u8] = b"\x1B[200~";
+const PASTE_END: &[u8] = b"\x1B[201~";
+
+// Keeps bracketed paste enabled while alive. Disabling on drop restores the terminal on errors and panics too
+struct BracketedPaste {}
+
+impl BracketedPaste {
+    fn enable() -> Self {
+        let mut stdout = std::io::stdout();
+        write!(stdout, "{}", CSIControlCodes::EnableBracketedPaste).unwrap();
+        stdout.flush().unwrap();
+
+        Self {}
+    }
+}
+
+impl Drop for BracketedPaste {
+    fn drop(&mut self) {
+        let mut stdout = std::io::stdout();
+        let _ = write!(stdout, "{}", CSIControlCodes::DisableBracketedPaste);
+        let _ = stdout.flush();
+    }
+}
+
+// Keeps mouse clicks reported while alive. Terminal's own selection and scrollback don't work in this mode,
+// so it must not outlive the line being edited
+struct MouseReporting {}
+
+impl MouseReporting {
+    fn enable() -> Self {
+        let mut stdout = std::io::stdout();
+        write!(stdout, "{}", CSIControlCodes::EnableMouseReporting).unwrap();
+        stdout.flush().unwrap();
+
+        Self {}
+    }
+}
+
+impl Drop for MouseReporting {
+    fn drop(&mut self) {
+        let mut stdout = std::io::stdout();
+        let _ = write!(stdout, "{}", CSIControlCodes::DisableMouseReporting);
+        let _ = stdout.flush();
+    }
+}
+
+// State of repeated Tab presses when completions share no longer prefix
+struct CompletionCycle {
+    span: Range<usize>,
+    completions: Vec<Completion>,
+    index: usize,
+}
+
+const MENU_ROWS: usize = 8;
+
+// Completions shown below the prompt. Selection is made with Up/Down
+struct CompletionMenu {
+    span: Range<usize>,
+    completions: Vec<Completion>,
+    selected: Option<usize>,
+    offset: usize,
+}
+
+impl CompletionMenu {
+    fn new(span: Range<usize>, completions: Vec<Completion>) -> Self {
+        Self {
+            span,
+            completions,
+            selected: None,
+            offset: 0,
+        }
+    }
+
+    fn select_next(&mut self) {
+        let next = match self.selected {
+            None => 0,
+            Some(i) => (i + 1) % self.completions.len(),
+        };
+        self.select(next);
+    }
+
+    fn select_prev(&mut self) {
+        let prev = match self.selected {
+            None | Some(0) => self.completions.len() - 1,
+            Some(i) => i - 1,
+        };
+        self.select(prev);
+    }
+
+    fn select(&mut self, idx: usize) {
+        self.selected = Some(idx);
+        if idx < self.offset {
+            self.offset = idx;
+        } else if idx >= self.offset + MENU_ROWS {
+            self.offset = idx + 1 - MENU_ROWS;
+        }
+    }
+
+    // rows taken on the screen including the title
+    fn height(&self) -> usize {
+        self.completions.len().min(MENU_ROWS) + 1
+    }
+
+    // replaces completed span with selected completion and returns new cursor position
+    fn accept(&self, line: &mut String) -> Option<usize> {
+        let completion = &self.completions[self.selected?].text;
+        line.replace_range(self.span.clone(), completion);
+
+        Some(self.span.start + completion.len())
+    }
+}
+
+// Highlighting of the last drawn line. Redraws which don't change the line,
+// like cursor moves and menu navigation, don't parse it again
+struct HighlightCache {
+    line: String,
+    highlighted: String,
 }
 
 pub struct TUI<'a> {
-    prompt: Cow<'a, str>,
-    settings: &'a RefCell<TUISettings>
+    prompt: Prompt,
+    // prompt as of the start of the current line
+    rendered_prompt: String,
+    session: &'a Session,
+    // row of the input where terminal cursor currently is
+    cursor_row: usize,
+    // screen row (1-based) of the first row of the input. None until the terminal reports cursor position
+    input_top: Option<usize>,
+    // most rows the input took since the prompt was drawn, the screen is scrolled for them
+    rows_drawn: usize,
+    highlight_cache: RefCell<Option<HighlightCache>>,
 }
 
 impl<'a> TUI<'a> {
-    pub fn new(prompt: Cow<'a, str>, settings: &'a RefCell<TUISettings>) -> Self {
+    pub fn new(prompt: Cow<'a, str>, session: &'a Session) -> Self {
         Self {
-            settings,
-            prompt,
+            session,
+            rendered_prompt: prompt.to_string(),
+            prompt: Prompt::literal(prompt),
+            cursor_row: 0,
+            input_top: None,
+            rows_drawn: 0,
+            highlight_cache: RefCell::new(None),
         }
     }
 
+    // See Prompt::parse for the syntax
+    pub fn set_prompt_template(&mut self, template: &str) {
+        self.prompt = Prompt::parse(template);
+        self.rendered_prompt = self.prompt.render(self.session);
+    }
+
     pub fn next_line(&mut self) -> Result<Option<String>, io::Error> {
-        if true || atty::is(atty::Stream::Stdin) {
+        self.rendered_prompt = self.prompt.render(self.session);
+        if atty::is(atty::Stream::Stdin) {
             self.next_line_interactive()
         } else {
             self.next_line_bulk()
         }
     }
     fn next_line_bulk(&mut self) -> Result<Option<String>, io::Error> {
-        let input = stdin().lock();
-        let mut buf = Vec::with_capacity(30);
-
-        let mut read = 0;
-        for c in input.bytes() {
-            read += 1;
-            match c {
-                Err(e) => return Err(e),
-                Ok(0) | Ok(3) | Ok(4) => return Ok(None),
-                Ok(0x7f) => {
-                    buf.pop();
-                }
-                Ok(b'\n') | Ok(b'\r') => break,
-                Ok(c) => buf.push(c),
-            }
-        }
-
-        if read <= 0 { return Ok(None); }
-
-        let string = String::from_utf8(buf)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        Ok(Some(string))
+        read_line_bulk(&mut stdin().lock())
     }
     fn next_line_interactive(&mut self) -> Result<Option<String>, io::Error> {
         let mut stdout = match std::io::stdout().into_raw_mode() {
@@ -136,63 +265,160 @@ impl<'a> TUI<'a> {
                 println!("{}", f);
                 panic!("Error: {:?}", e);},
         };
-        write!(stdout, "{}", self.prompt).unwrap();
         write!(stdout, "{}", CSIControlCodes::SetCursorStyle(CursorMode::SteadyBar)).unwrap();
         stdout.flush()?;
+        self.cursor_row = 0;
+        self.input_top = None;
+        self.rows_drawn = 0;
+        // The answer comes among typed keys, so it's taken from the events below instead of being waited for.
+        // The terminal handles the request before the prompt is drawn, so it reports the first row of the input
+        write!(stdout, "{}", CSIControlCodes::RequestCursorPosition).unwrap();
+        stdout.flush()?;
+        let mut awaiting_position = true;
 
         let mut cursor = 0usize;
         let mut line = String::new();
+        let mut cycle: Option<CompletionCycle> = None;
+        let mut menu: Option<CompletionMenu> = None;
 
-        let mut stdin = std::io::stdin();
+        let stdin = std::io::stdin();
+        let _paste_mode = BracketedPaste::enable();
+        let _mouse_mode = MouseReporting::enable();
+        // text pasted so far, while between paste markers
+        let mut paste: Option<String> = None;
 
         macro_rules! print_line {
             () => {
                 {
-                    self.print_cursor_insight(&line, &mut stdout, cursor);
-                    self.print_annotated_line(&line, &mut stdout, cursor);
+                    self.redraw(&line, cursor, menu.as_ref(), &mut stdout);
                 }
             };
         }
+        macro_rules! update_menu {
+            () => {
+                menu = self.analyze(&line, cursor);
+            };
+        }
         print_line!();
-        for c in stdin.keys() {
-            let c = c?;
+        for event in stdin.events() {
+            let event = event?;
+            if let Event::Unsupported(seq) = &event {
+                if let Some(row) = cursor_report_row(seq).filter(|_| awaiting_position) {
+                    awaiting_position = false;
+                    self.input_top = Some(row);
+                    self.fit_input_top();
+                    continue;
+                }
+            }
+            let event = match handle_paste(&mut paste, event) {
+                PasteEvent::Other(event) => event,
+                PasteEvent::Taken => continue,
+                PasteEvent::Pasted(text) => {
+                    line.insert_str(cursor, &text);
+                    cursor += text.len();
+                    update_menu!();
+                    print_line!();
+                    stdout.flush()?;
+                    continue;
+                }
+            };
+            let c = match event {
+                Event::Mouse(MouseEvent::Press(MouseButton::Left, x, y)) => {
+                    // clicks outside of the input are ignored
+                    if let Some(position) = self.position_at(&line, x as usize, y as usize) {
+                        cursor = position;
+                        cycle = None;
+                        update_menu!();
+                        print_line!();
+                        stdout.flush()?;
+                    }
+                    continue;
+                }
+                Event::Key(c) => c,
+                _ => continue,
+            };
+            if c != Key::Char('\t') {
+                cycle = None;
+            }
             match c {
                 Key::Ctrl('c') => {
                     return Err(io::Error::new(io::ErrorKind::Interrupted, "Interrupted"));
                 }
-                Key::Char(c) if c == '\n' => {
+                Key::Char('\n') if menu.as_ref().is_some_and(|m| m.selected.is_some()) => {
+                    cursor = menu.as_ref().unwrap().accept(&mut line).unwrap();
+                    update_menu!();
+                    print_line!();
+                    stdout.flush()?;
+                }
+                Key::Char(c) if c == '\n' && continue_line(&mut line) => {
+                    cursor = line.len();
+                    update_menu!();
+                    print_line!();
+                    stdout.flush()?;
+                }
+                Key::Char('\n') => {
+                    self.redraw(&line, line.len(), None, &mut stdout);
                     write!(stdout, "\n\r").unwrap();
                     stdout.flush().unwrap();
                     return Ok(Some(line));
                 }
+                Key::Char('\t') => {
+                    self.complete(&mut line, &mut cursor, &mut cycle);
+                    update_menu!();
+                    print_line!();
+                    stdout.flush()?;
+                }
                 Key::Char(c) if c != '\n' => {
                     line.insert(cursor, c);
                     cursor += 1;
+                    update_menu!();
                     print_line!();
                     stdout.flush()?;
                 }
-                Key::Right => {
-                    if cursor < line.len() {
-                        write!(stdout, "{}", CSIControlCodes::CursorForward(1)).unwrap();
-                        cursor += 1;
-                        stdout.flush()?;
-                    }
+                // moves between rows need the cursor to be placed again
+                Key::Right if line[cursor..].starts_with('\n') => {
+                    cursor += 1;
+                    print_line!();
+                    stdout.flush()?;
                 }
-                Key::Left => {
-                    if cursor > 0 {
-                        write!(stdout, "{}", CSIControlCodes::CursorBack(1)).unwrap();
-                        cursor -= 1;
-                        stdout.flush()?;
-                    }
+                Key::Left if line[..cursor].ends_with('\n') => {
+                    cursor -= 1;
+                    print_line!();
+                    stdout.flush()?;
                 }
-                Key::Backspace => {
-                    if cursor > 0 {
+                Key::Right if cursor < line.len() => {
+                    write!(stdout, "{}", CSIControlCodes::CursorForward(1)).unwrap();
+                    cursor += 1;
+                    stdout.flush()?;
+                }
+                Key::Left if cursor > 0 => {
+                    write!(stdout, "{}", CSIControlCodes::CursorBack(1)).unwrap();
+                    cursor -= 1;
+                    stdout.flush()?;
+                }
+                Key::Down if menu.is_some() => {
+                    menu.as_mut().unwrap().select_next();
+                    print_line!();
+                    stdout.flush()?;
+                }
+                Key::Up if menu.is_some() => {
+                    menu.as_mut().unwrap().select_prev();
+                    print_line!();
+                    stdout.flush()?;
+                }
+                Key::Esc if menu.is_some() => {
+                    menu = None;
+                    print_line!();
+                    stdout.flush()?;
+                }
+                Key::Backspace
+                    if cursor > 0 => {
                         line.remove(cursor - 1);
                         cursor -= 1;
+                        update_menu!();
                         print_line!();
                         stdout.flush()?;
                     }
-                }
 
                 _ => {}
             }
@@ -204,101 +430,498 @@ impl<'a> TUI<'a> {
         panic!("Unreachable");
     }
 
-    fn print_cursor_insight(&mut self, line: &str, stdout: &mut RawTerminal<Stdout>, cursor: usize) {
-        let tree = parse_line(line);
-        if tree.is_none() { return; }
-        let tree = tree.unwrap();
-
-        let mut nodes = Vec::new();
-        tree.collect(&mut nodes, |a| a.origin.span.start() <= cursor && a.origin.span.end() >= cursor);
+    fn complete(&self, line: &mut String, cursor: &mut usize, cycle: &mut Option<CompletionCycle>) {
+        if let Some(state) = cycle {
+            state.index = (state.index + 1) % state.completions.len();
+            let completion = &state.completions[state.index].text;
+            line.replace_range(state.span.clone(), completion);
+            state.span.end = state.span.start + completion.len();
+            *cursor = state.span.end;
+            return;
+        }
 
-        let mut sink = AnnotationsSink::new();
+        let (span, completions) = match self.session.complete(line, *cursor) {
+            Some(v) => v,
+            None => return,
+        };
 
-        for node in nodes {
-            if let Some(annotator) = downcast_to_annotator(node) {
-                annotator.annotate(node, &mut sink);
-            }
+        if completions.len() == 1 {
+            line.replace_range(span.clone(), &completions[0].text);
+            *cursor = span.start + completions[0].text.len();
+            return;
         }
-        write!(stdout, "\r{}", CSIControlCodes::EraseInDisplay(0)).unwrap();
-        if !sink.completions.is_empty() {
-            write!(stdout, "\n\r").unwrap();
-            write!(stdout, "Completions: \n\r").unwrap();
-            for completion in sink.completions.iter() {
-                write!(stdout, "  {}\n\r", completion).unwrap();
-            }
 
-            write!(stdout, "{}\r", CSIControlCodes::CursorUp(sink.completions.len() + 2)).unwrap();
+        let typed = &line[span.start..*cursor];
+        let texts: Vec<String> = completions.iter().map(|c| c.text.clone()).collect();
+        let prefix = longest_common_prefix(&texts);
+        if prefix.len() > typed.len() && prefix.starts_with(typed) {
+            let prefix = prefix.to_string();
+            line.replace_range(span.start..*cursor, &prefix);
+            *cursor = span.start + prefix.len();
+        } else {
+            line.replace_range(span.clone(), &completions[0].text);
+            *cursor = span.start + completions[0].text.len();
+            *cycle = Some(CompletionCycle {
+                span: span.start..*cursor,
+                completions,
+                index: 0,
+            });
         }
     }
 
-    fn print_annotated_line(&self, line: &str, stdout: &mut RawTerminal<Stdout>, cursor: usize) {
+    // Parses the edited line once for both completion menu and highlighting, the latter is cached for render
+    fn analyze(&self, line: &str, cursor: usize) -> Option<CompletionMenu> {
         let tree = parse_line(line);
-        if tree.is_none() { return; }
-        let tree = tree.unwrap();
-
-        let highlighted = self.highlight_command(&tree, line);
+        let highlighted = match &tree {
+            Some(tree) => self.session.highlight_tree(tree, line),
+            None => line.to_string(),
+        };
+        *self.highlight_cache.borrow_mut() = Some(HighlightCache { line: line.to_string(), highlighted });
 
+        let (span, completions) = self.session.complete_tree(tree.as_ref()?, cursor)?;
+        Some(CompletionMenu::new(span, completions))
+    }
 
-        write!(stdout, "{}{}{}{}{}",
-               CSIControlCodes::CursorHorizontalAbsolute(1),
-               CSIControlCodes::EraseInLine(0),
-               self.prompt,
-               highlighted,
-               CSIControlCodes::CursorHorizontalAbsolute(cursor as usize + self.prompt.len() + 1),
-        ).unwrap();
+    fn highlight(&self, line: &str) -> String {
+        let mut cache = self.highlight_cache.borrow_mut();
+        match cache.as_ref() {
+            Some(c) if c.line == line => c.highlighted.clone(),
+            _ => {
+                let highlighted = self.session.highlight(line);
+                *cache = Some(HighlightCache { line: line.to_string(), highlighted: highlighted.clone() });
+                highlighted
+            }
+        }
+    }
 
+    fn redraw(&mut self, line: &str, cursor: usize, menu: Option<&CompletionMenu>, stdout: &mut RawTerminal<Stdout>) {
+        let (output, row) = self.render(line, cursor, menu);
+        write!(stdout, "{}", output).unwrap();
         stdout.flush().unwrap();
 
+        self.cursor_row = row;
+
+        let rows = line.matches('\n').count() + menu.map_or(0, |m| m.height()) + 1;
+        self.rows_drawn = self.rows_drawn.max(rows);
+        self.fit_input_top();
+    }
+
+    // input drawn below the last screen row scrolls the terminal up
+    fn fit_input_top(&mut self) {
+        if let (Some(top), Ok((_, height))) = (self.input_top, termion::terminal_size()) {
+            if top + self.rows_drawn > height as usize + 1 {
+                self.input_top = Some((height as usize + 1).saturating_sub(self.rows_drawn).max(1));
+            }
+        }
+    }
+
+    // Byte offset in the line under the clicked screen cell (1-based, as reported by the terminal).
+    // Clicks on a prompt go to the start of its row, clicks past the end of a row go to its end
+    fn position_at(&self, line: &str, x: usize, y: usize) -> Option<usize> {
+        let row = y.checked_sub(self.input_top?)?;
+        let row_start: usize = line.split('\n').take(row).map(|r| r.len() + 1).sum();
+        let text = line.split('\n').nth(row)?;
+
+        let prompt_len = if row == 0 {
+            visible_width(&self.rendered_prompt)
+        } else {
+            visible_width(self.session.settings().borrow().continuation_prompt())
+        };
+        let column = x.saturating_sub(1).saturating_sub(prompt_len);
+
+        let mut width = 0;
+        let mut offset = row_start;
+        for c in text.chars() {
+            let char_width = c.width().unwrap_or(0);
+            if column < width + char_width { break; }
+            width += char_width;
+            offset += c.len_utf8();
+        }
+
+        Some(offset)
     }
 
-    fn highlight_command<'b>(&self, tree: &'b ParseTree<'b>, line: &str) -> String {
-        let node = tree.root();
-        let command = line;
-        let mut insertions = HashMap::<usize, Vec<String>>::new();
+    // Draws whole input starting from its first row. Every row after a newline gets continuation prompt.
+    // Returns the output and the row of the input where the cursor is left
+    fn render(&self, line: &str, cursor: usize, menu: Option<&CompletionMenu>) -> (String, usize) {
         let mut result = String::new();
+        if self.cursor_row > 0 {
+            result.push_str(&CSIControlCodes::CursorUp(self.cursor_row).to_string());
+        }
+        result.push_str(&format!("\r{}", CSIControlCodes::EraseInDisplay(0)));
+
+        let highlighted = self.highlight(line);
+        let continuation_prompt = self.session.settings().borrow().continuation_prompt().to_string();
 
-        node.walk(&mut |node| {
-            let sink = self.run_annotator_on_node(node);
+        result.push_str(&self.rendered_prompt);
+        result.push_str(&highlighted.replace('\n', &format!("\n\r{}", continuation_prompt)));
 
-            let v = &node.origin.value;
+        let mut rows = line.matches('\n').count();
+        if let Some(menu) = menu {
+            rows += self.render_menu(menu, &mut result);
+        }
 
-            insertions.entry(node.origin.span.start()).or_insert(Vec::new())
-                .push(v.kind().color_string());
+        let row = line[..cursor].matches('\n').count();
+        let row_start = line[..cursor].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let prompt_len = if row == 0 { visible_width(&self.rendered_prompt) } else { visible_width(&continuation_prompt) };
 
-            for x in sink.colors() {
-                insertions.entry(node.origin.span.start()).or_insert(Vec::new())
-                    .push(self.settings.borrow().color_scheme().get(x).to_string());
+        if rows > row {
+            result.push_str(&CSIControlCodes::CursorUp(rows - row).to_string());
+        }
+        let column = visible_width(&line[row_start..cursor]);
+        result.push_str(&CSIControlCodes::CursorHorizontalAbsolute(column + prompt_len + 1).to_string());
+
+        (result, row)
+    }
+
+    // returns count of printed rows
+    fn render_menu(&self, menu: &CompletionMenu, result: &mut String) -> usize {
+        let shown = menu.completions.len().min(MENU_ROWS);
+        result.push_str(&format!("\n\rCompletions ({}): ", menu.completions.len()));
+        for (i, completion) in menu.completions.iter().enumerate().skip(menu.offset).take(shown) {
+            if menu.selected == Some(i) {
+                result.push_str(&format!("\n\r  {}{}{}{}{}",
+                       termion::color::Bg(termion::color::White),
+                       termion::color::Fg(termion::color::Black),
+                       completion,
+                       termion::color::Bg(termion::color::Reset),
+                       termion::color::Fg(termion::color::Reset),
+                ));
+            } else {
+                result.push_str(&format!("\n\r  {}", completion));
             }
+        }
+
+        menu.height()
+    }
+}
+
+// Row of a cursor position report `ESC [ row ; column R`
+fn cursor_report_row(seq: &[u8]) -> Option<usize> {
+    let report = std::str::from_utf8(seq).ok()?
+        .strip_prefix("\x1B[")?
+        .strip_suffix('R')?;
+    let (row, column) = report.split_once(';')?;
+    column.parse::<usize>().ok()?;
+
+    row.parse().ok()
+}
+
+// What an input event means for bracketed paste
+#[derive(Debug, PartialEq)]
+enum PasteEvent {
+    // not a part of a paste, handled as usual
+    Other(Event),
+    // taken by the paste in progress or a stray paste marker
+    Taken,
+    // the paste is over with this text
+    Pasted(String),
+}
 
-            insertions.entry(node.origin.span.end()).or_insert(Vec::new())
-                .push(termion::color::Fg(termion::color::Reset).to_string());
-        });
+// Every event between paste markers is buffered rather than applied, so pasted newlines don't submit the line
+// and pasted control bytes like Backspace or Ctrl-C don't edit it. Only chars make it into the pasted text
+fn handle_paste(paste: &mut Option<String>, event: Event) -> PasteEvent {
+    match event {
+        Event::Unsupported(seq) if seq == PASTE_START => {
+            *paste = Some(String::new());
+            PasteEvent::Taken
+        }
+        Event::Unsupported(seq) if seq == PASTE_END => match paste.take() {
+            Some(text) => PasteEvent::Pasted(text),
+            None => PasteEvent::Taken,
+        },
+        Event::Key(Key::Char(c)) if paste.is_some() => {
+            paste.as_mut().unwrap().push(c);
+            PasteEvent::Taken
+        }
+        _ if paste.is_some() => PasteEvent::Taken,
+        event => PasteEvent::Other(event),
+    }
+}
+
+// Line ending with unescaped backslash goes on in the next row instead of being submitted.
+// The backslash is replaced with a newline, which the parser skips like any other whitespace
+fn continue_line(line: &mut String) -> bool {
+    let backslashes = line.len() - line.trim_end_matches('\\').len();
+    if backslashes.is_multiple_of(2) {
+        return false;
+    }
 
+    line.pop();
+    line.push('\n');
+    true
+}
 
-        result.push_str(&termion::color::Bg(termion::color::Reset).to_string());
-        result.push_str(&termion::color::Fg(termion::color::Reset).to_string());
-        for (i, s) in command.chars().enumerate() {
-            if let Some(insertions) = insertions.get(&i) {
-                for ins in insertions {
-                    result.push_str(&ins);
+fn read_line_bulk<R: BufRead>(input: &mut R) -> Result<Option<String>, io::Error> {
+    let mut buf = Vec::with_capacity(30);
+
+    let mut read = 0;
+    for c in input.bytes() {
+        read += 1;
+        match c {
+            Err(e) => return Err(e),
+            Ok(0) | Ok(3) | Ok(4) => return Ok(None),
+            Ok(0x7f) => {
+                // drop whole char including its continuation bytes
+                while let Some(b) = buf.pop() {
+                    if b & 0xC0 != 0x80 { break; }
                 }
             }
-            result.push(s);
+            Ok(b'\n') | Ok(b'\r') => break,
+            Ok(c) => buf.push(c),
         }
-        result.push_str(&termion::color::Bg(termion::color::Reset).to_string());
-        result.push_str(&termion::color::Fg(termion::color::Reset).to_string());
-
-        result
     }
 
-    fn run_annotator_on_node<'b>(&self, node: &'b PTNode<'b>) -> AnnotationsSink {
-        let mut sink = AnnotationsSink::new();
-        if let Some(annotator) = downcast_to_annotator(node) {
-            annotator.annotate(node, &mut sink);
+    if read <= 0 { return Ok(None); }
+
+    let string = match String::from_utf8(buf) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("warning: input is not valid UTF-8: {}", e.utf8_error());
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
         }
+    };
+    Ok(Some(string))
+}
 
-        sink
+pub fn longest_common_prefix(strings: &[String]) -> &str {
+    let first = match strings.first() {
+        Some(s) => s.as_str(),
+        None => return "",
+    };
+
+    let mut end = first.len();
+    for s in &strings[1..] {
+        end = first.char_indices()
+            .zip(s.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map(|((i, a), _)| i + a.len_utf8())
+            .unwrap_or(0)
+            .min(end);
     }
+
+    &first[..end]
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::builtin::paths::tests::TempDir;
+    use crate::builtin::annotator::tests::init_entities;
+    use crate::builtin::engine::entities::EntitiesManager;
+    use crate::entities;
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_continuation_prompt() {
+        init_entities();
+        let session = Session::with_entities(entities());
+        let tui = TUI::new(">> ".into(), &session);
+
+        let (output, row) = tui.render("echo a\nb", 8, None);
+
+        assert!(output.contains("\n\r... "), "{:?}", output);
+        assert_eq!(row, 1);
+        // 1 char of the second row after 4 chars of continuation prompt
+        assert!(output.ends_with(&CSIControlCodes::CursorHorizontalAbsolute(6).to_string()), "{:?}", output);
+
+        let (output, row) = tui.render("echo a\nb", 2, None);
+        assert_eq!(row, 0);
+        assert!(output.ends_with(&format!("{}{}", CSIControlCodes::CursorUp(1), CSIControlCodes::CursorHorizontalAbsolute(6))), "{:?}", output);
+    }
+
+    #[test]
+    fn test_colored_prompt_cursor() {
+        init_entities();
+        let session = Session::with_entities(entities());
+        let prompt = format!("{}λ>{} ", termion::color::Fg(termion::color::Red), termion::color::Fg(termion::color::Reset));
+        let tui = TUI::new(prompt.into(), &session);
+
+        // 3 visible chars of the prompt and 2 of `ая`, which are 4 bytes
+        let (output, _) = tui.render("ая", 4, None);
+        assert!(output.ends_with(&CSIControlCodes::CursorHorizontalAbsolute(6).to_string()), "{:?}", output);
+    }
+
+    #[test]
+    fn test_prompt_template_cursor() {
+        init_entities();
+        // own universe, so the status is not changed by other tests
+        let session = Session::with_entities(Box::leak(Box::new(EntitiesManager::new())));
+        let mut tui = TUI::new(">> ".into(), &session);
+        tui.set_prompt_template(&format!("{}[\\$?]{} ", termion::color::Fg(termion::color::Red), termion::color::Fg(termion::color::Reset)));
+
+        let (output, _) = tui.render("ls", 2, None);
+        assert!(output.contains("[0]"), "{:?}", output);
+        // 4 visible chars of `[0] ` and 2 of the line, colors take no space
+        let expected = CSIControlCodes::CursorHorizontalAbsolute(7).to_string();
+        assert!(output.ends_with(&expected), "{:?}", output);
+    }
+
+    #[test]
+    fn test_click_position() {
+        init_entities();
+        let session = Session::with_entities(entities());
+        let mut tui = TUI::new(">> ".into(), &session);
+        let line = "echo a\nbвc";
+
+        // position of the input is unknown
+        assert_eq!(tui.position_at(line, 5, 1), None);
+
+        tui.input_top = Some(10);
+        // `>> ` takes columns 1-3, so `echo` starts at 4
+        assert_eq!(tui.position_at(line, 4, 10), Some(0));
+        assert_eq!(tui.position_at(line, 6, 10), Some(2));
+        assert_eq!(tui.position_at(line, 2, 10), Some(0));
+        assert_eq!(tui.position_at(line, 40, 10), Some(6));
+
+        // `... ` on the second row, `в` is 2 bytes
+        assert_eq!(tui.position_at(line, 5, 11), Some(7));
+        assert_eq!(tui.position_at(line, 6, 11), Some(8));
+        assert_eq!(tui.position_at(line, 7, 11), Some(10));
+        assert_eq!(tui.position_at(line, 40, 11), Some(11));
+
+        assert_eq!(tui.position_at(line, 5, 9), None);
+        assert_eq!(tui.position_at(line, 5, 12), None);
+    }
+
+    #[test]
+    fn test_line_is_parsed_once_per_edit() {
+        init_entities();
+        let session = Session::with_entities(entities());
+        let tui = TUI::new(">> ".into(), &session);
+
+        let menu = tui.analyze("$ec", 3).unwrap();
+        assert_eq!(menu.completions[0].text, "echo");
+        let highlighted = tui.highlight_cache.borrow().as_ref().map(|c| c.highlighted.clone()).unwrap();
+        assert_eq!(highlighted, session.highlight("$ec"));
+
+        // render of the same line reuses highlighting of analyze
+        tui.highlight_cache.borrow_mut().as_mut().unwrap().highlighted = "cached".to_string();
+        let (output, _) = tui.render("$ec", 1, None);
+        assert!(output.contains(">> cached"), "{:?}", output);
+
+        let (output, _) = tui.render("$ech", 1, None);
+        assert!(!output.contains("cached"), "{:?}", output);
+    }
+
+    #[test]
+    fn test_backslash_continuation() {
+        init_entities();
+        let session = Session::with_entities(entities());
+        let dir = TempDir::new("continuation");
+        let path = dir.join("output");
+        let path = path.to_str().unwrap();
+
+        let mut line = "echo first \\".to_string();
+        assert!(continue_line(&mut line));
+        assert_eq!(line, "echo first \n");
+        line.push_str(&format!("second > {}", path));
+        assert!(!continue_line(&mut line));
+
+        assert!(session.run(&line).is_ok(), "{}", line);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "first second\n");
+
+        // escaped backslash is a part of the last word
+        let mut line = "echo a\\\\".to_string();
+        assert!(!continue_line(&mut line));
+        assert_eq!(line, "echo a\\\\");
+
+        let tui = TUI::new(">> ".into(), &session);
+        let (output, row) = tui.render("echo first \nsecond", 18, None);
+        assert!(output.contains("\n\r... "), "{:?}", output);
+        assert_eq!(row, 1);
+        assert!(output.ends_with(&CSIControlCodes::CursorHorizontalAbsolute(11).to_string()), "{:?}", output);
+    }
+
+    #[test]
+    fn test_bulk_multibyte() {
+        let mut input: &[u8] = "echo привет\nls\n".as_bytes();
+
+        assert_eq!(read_line_bulk(&mut input).unwrap(), Some("echo привет".to_string()));
+        assert_eq!(read_line_bulk(&mut input).unwrap(), Some("ls".to_string()));
+        assert_eq!(read_line_bulk(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn test_bulk_commands_execute_in_order() {
+        init_entities();
+        let session = Session::with_entities(entities());
+        let dir = TempDir::new("bulk");
+        let path = dir.join("output");
+        let path = path.to_str().unwrap();
+
+        let script = format!("echo 1 > {0}\necho 2 >> {0}\n\necho 3 >> {0}\n", path);
+        let mut input = script.as_bytes();
+        while let Some(line) = read_line_bulk(&mut input).unwrap() {
+            if line.is_empty() { continue; }
+            assert!(session.run(&line).is_ok(), "{}", line);
+        }
+
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "1\n2\n3\n");
+    }
+
+    #[test]
+    fn test_bulk_backspace_removes_whole_char() {
+        let mut input: &[u8] = "abп\x7f\n".as_bytes();
+
+        assert_eq!(read_line_bulk(&mut input).unwrap(), Some("ab".to_string()));
+    }
+
+    #[test]
+    fn test_bulk_invalid_utf8() {
+        let mut input: &[u8] = b"echo \xff\xfe kek\nls\n";
+
+        assert_eq!(read_line_bulk(&mut input).unwrap(), Some("echo \u{FFFD}\u{FFFD} kek".to_string()));
+        assert_eq!(read_line_bulk(&mut input).unwrap(), Some("ls".to_string()));
+    }
+
+    #[test]
+    fn test_cursor_report_row() {
+        let input: &[u8] = b"\x1B[12;5Ra";
+        let event = input.events().next().unwrap().unwrap();
+        let seq = match event {
+            Event::Unsupported(seq) => seq,
+            e => panic!("{:?}", e),
+        };
+        assert_eq!(cursor_report_row(&seq), Some(12));
+
+        assert_eq!(cursor_report_row(b"\x1B[200~"), None);
+        assert_eq!(cursor_report_row(b"\x1B[12R"), None);
+        assert_eq!(cursor_report_row(b"\x1B[a;5R"), None);
+    }
+
+    fn paste_events(input: &[u8]) -> Vec<PasteEvent> {
+        let mut paste = None;
+        input.events().map(|e| handle_paste(&mut paste, e.unwrap())).collect()
+    }
+
+    #[test]
+    fn test_paste_buffers_every_key() {
+        // Backspace, Ctrl-C and an arrow inside of the paste are not edits
+        let events = paste_events(b"a\x1B[200~b\x7f\x03\x1B[A\tc\rd\x1B[201~\x7f");
 
+        assert_eq!(events.first(), Some(&PasteEvent::Other(Event::Key(Key::Char('a')))));
+        assert_eq!(events.last(), Some(&PasteEvent::Other(Event::Key(Key::Backspace))));
+        assert_eq!(events[events.len() - 2], PasteEvent::Pasted("b\tc\nd".to_string()));
+        assert!(events[1..events.len() - 2].iter().all(|e| *e == PasteEvent::Taken), "{:?}", events);
+    }
+
+    #[test]
+    fn test_paste_end_without_start() {
+        assert_eq!(paste_events(b"\x1B[201~a"), vec![PasteEvent::Taken, PasteEvent::Other(Event::Key(Key::Char('a')))]);
+        assert_eq!(paste_events(b"\x1B[200~\x1B[201~"), vec![PasteEvent::Taken, PasteEvent::Pasted(String::new())]);
+    }
+
+    #[test]
+    fn test_longest_common_prefix() {
+        assert_eq!(longest_common_prefix(&strings(&["cargo", "cargo-fmt", "cargo-clippy"])), "cargo");
+        assert_eq!(longest_common_prefix(&strings(&["cd", "cat"])), "c");
+        assert_eq!(longest_common_prefix(&strings(&["cd", "ls"])), "");
+        assert_eq!(longest_common_prefix(&strings(&["kek"])), "kek");
+        assert_eq!(longest_common_prefix(&[]), "");
+    }
+}
@@ -0,0 +1,90 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::OwnedFd;
+use fosh::{Argument, Callee, ExecutionConfig, FoshEntity, Session, Type, Value};
+
+#[test]
+fn test_eval_cd() {
+    let session = Session::new();
+    let dir = std::env::temp_dir().canonicalize().unwrap();
+
+    let result = session.eval(&format!("$cd(\"{}\")", dir.display()), ExecutionConfig::new(None, None, None));
+    assert!(result.is_ok());
+    assert_eq!(std::env::current_dir().unwrap().canonicalize().unwrap(), dir);
+}
+
+#[test]
+fn test_eval_with_own_stdout() {
+    let session = Session::new();
+    let path = std::env::temp_dir().join(format!("fosh_embedding_{}", std::process::id()));
+    let out = File::create(&path).unwrap();
+
+    let result = session.eval(r#"$echo("kek")"#, ExecutionConfig::new(None, Some(OwnedFd::from(out)), None));
+    assert_eq!(result.ok().unwrap().try_as_string(), Some("kek".to_string()));
+
+    let mut output = String::new();
+    File::open(&path).unwrap().read_to_string(&mut output).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output, "kek\n");
+
+    assert!(session.eval(r#"$cd("kek""#, ExecutionConfig::new(None, None, None)).is_err());
+}
+
+#[test]
+fn test_errors_of_intermediate_stages_go_to_own_stderr() {
+    let session = Session::new();
+    let path = std::env::temp_dir().join(format!("fosh_embedding_stderr_{}", std::process::id()));
+    let err = File::create(&path).unwrap();
+
+    let result = session.eval(r#"$cd(5) ; $echo("1")"#, ExecutionConfig::new(None, None, Some(OwnedFd::from(err))));
+    assert!(result.is_ok());
+
+    let mut output = String::new();
+    File::open(&path).unwrap().read_to_string(&mut output).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.contains("$cd(5)"), "{}", output);
+}
+
+#[test]
+fn test_custom_builtin() {
+    let session = Session::new();
+    let entities = session.entities();
+
+    let greet = entities.make_entity("Greeting".to_string())
+        .with_callee(
+            Callee::new_pseudo_execution(|_pt, args, _stdin, stdout, _stderr| {
+                let greeting = format!("Hello, {}!", args[0].try_as_string().unwrap());
+                let _ = writeln!(stdout, "{}", greeting);
+                Ok(Value::String(greeting).into_entity())
+            }).with_arguments(vec![Argument {
+                name: "name".to_string(),
+                possible_types: vec![Type::String],
+                contributor: &entities.empty_contributor,
+            }])
+        );
+    entities.register_global("greet", greet);
+
+    let path = std::env::temp_dir().join(format!("fosh_embedding_greet_{}", std::process::id()));
+    let out = File::create(&path).unwrap();
+    let result = session.eval(r#"$greet("fosh")"#, ExecutionConfig::new(None, Some(OwnedFd::from(out)), None));
+    assert_eq!(result.ok().unwrap().try_as_string(), Some("Hello, fosh!".to_string()));
+
+    let mut output = String::new();
+    File::open(&path).unwrap().read_to_string(&mut output).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output, "Hello, fosh!\n");
+
+    // arguments are checked like for the shell's own builtins
+    assert!(session.eval("$greet(5)", ExecutionConfig::new(None, None, None)).is_err());
+}
+
+#[test]
+fn test_sessions_on_one_thread_keep_own_universes() {
+    let first = Session::new();
+    first.entities().register_global("only_in_first", first.entities().make_entity("Marker".to_string()));
+    let second = Session::new();
+
+    assert!(first.eval("$only_in_first", ExecutionConfig::new(None, None, None)).is_ok());
+    assert!(second.eval("$only_in_first", ExecutionConfig::new(None, None, None)).is_err());
+    assert!(first.eval("$only_in_first", ExecutionConfig::new(None, None, None)).is_ok());
+}